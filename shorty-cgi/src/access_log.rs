@@ -0,0 +1,69 @@
+use serde::Serialize;
+
+/// One access log record, written as a JSON object per line.
+///
+/// Front ends fill it from their own request type, so every binary logs
+/// the same shape.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RequestLog {
+    /// When the request started, RFC 3339 in UTC.
+    pub timestamp: String,
+    pub method: String,
+    /// Request path without the query string.
+    pub path: String,
+    /// Name of the matched route, `None` if no route matched.
+    pub route: Option<&'static str>,
+    /// Status of the response that was sent.
+    pub status: u16,
+    pub duration_ms: u64,
+    /// Id assigned by the web server, e.g. Apache's `UNIQUE_ID`.
+    pub request_id: Option<String>,
+}
+
+pub trait LogSink {
+    fn log(&self, record: &RequestLog);
+}
+
+/// Writes records to stderr, which Apache sends to the error log.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StderrSink;
+
+impl LogSink for StderrSink {
+    fn log(&self, record: &RequestLog) {
+        if let Ok(line) = serde_json::to_string(record) {
+            eprintln!("{line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_request_log_json() {
+        let record = RequestLog {
+            timestamp: "2024-01-01T00:00:00Z".to_string(),
+            method: "GET".to_string(),
+            path: "/abc".to_string(),
+            route: Some("short_url"),
+            status: 200,
+            duration_ms: 12,
+            request_id: None,
+        };
+        let json: serde_json::Value = serde_json::to_value(&record).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp": "2024-01-01T00:00:00Z",
+                "method": "GET",
+                "path": "/abc",
+                "route": "short_url",
+                "status": 200,
+                "duration_ms": 12,
+                "request_id": null,
+            })
+        );
+        assert!(!serde_json::to_string(&record).unwrap().contains('\n'));
+    }
+}
@@ -5,7 +5,11 @@ use core::{
 use http::uri::InvalidUri;
 use serde::{Deserialize, Serialize};
 use serde_plain::{derive_display_from_serialize, derive_fromstr_from_deserialize};
-use std::{collections::BTreeMap, ffi::OsString};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    io::{BufRead, Read},
+};
 
 pub trait Environment {
     fn vars(&self) -> impl Iterator<Item = (OsString, OsString)>;
@@ -26,9 +30,114 @@ impl Environment for OsEnvironment {
     }
 }
 
+/// The meta-variables a web server would set for a plain HTTP request,
+/// for running the CGI without one.
+#[derive(Debug, Clone, Default)]
+pub struct RequestEnvironment(BTreeMap<String, String>);
+
+impl Environment for RequestEnvironment {
+    fn vars(&self) -> impl Iterator<Item = (OsString, OsString)> {
+        self.0.iter().map(|(k, v)| (k.into(), v.into()))
+    }
+
+    fn var(&self, key: String) -> Option<String> {
+        self.0.get(&key).cloned()
+    }
+}
+
+impl RequestEnvironment {
+    /// Read a request line and headers, up to the first empty line or
+    /// end of input, e.g. `GET /abc HTTP/1.0\r\n\r\n`.
+    ///
+    /// `PATH_INFO` is the percent-decoded path, like a web server sets it.
+    /// The server name and port come from the `Host` header, and default
+    /// to `localhost` and 80.
+    ///
+    /// # Errors
+    /// If reading fails, the request line or a header is malformed, or
+    /// the path is not UTF-8 once decoded.
+    pub fn from_http_request(input: impl BufRead) -> Result<Self, CgiEnvError> {
+        use MetaVariableKind::{
+            ContentLength, ContentType, GatewayInterface, PathInfo, QueryString, RequestMethod,
+            RequestScheme, RequestUri, ServerName, ServerPort, ServerProtocol,
+        };
+
+        let invalid = |line: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("malformed request line or header: {line:?}"),
+            )
+        };
+        let mut lines = input.lines();
+        let request_line = lines.next().transpose()?.unwrap_or_default();
+        let mut fields = request_line.split_whitespace();
+        let (Some(method), Some(uri), Some(protocol), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid(&request_line).into());
+        };
+        let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+        let path = percent_encoding::percent_decode_str(path)
+            .decode_utf8()
+            .map_err(|_| invalid(&request_line))?;
+        let mut vars = BTreeMap::new();
+        let mut set =
+            |key: MetaVariableKind, value: &str| vars.insert(key.to_string(), value.to_string());
+        set(GatewayInterface, "CGI/1.1");
+        set(RequestMethod, method);
+        set(RequestUri, uri);
+        set(PathInfo, &path);
+        set(QueryString, query);
+        set(ServerProtocol, protocol);
+        set(RequestScheme, "http");
+        set(ServerName, "localhost");
+        set(ServerPort, "80");
+        let mut headers = Vec::new();
+        for line in lines {
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').ok_or_else(|| invalid(line))?;
+            let value = value.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "host" => {
+                    // Keeps the brackets of an IPv6 address
+                    let authority =
+                        http::uri::Authority::from_str(value).map_err(|_| invalid(line))?;
+                    set(ServerName, authority.host());
+                    set(ServerPort, &authority.port_u16().unwrap_or(80).to_string());
+                }
+                "content-length" => {
+                    set(ContentLength, value);
+                    continue;
+                }
+                "content-type" => {
+                    set(ContentType, value);
+                    continue;
+                }
+                _ => {}
+            }
+            let key = format!(
+                "HTTP_{}",
+                name.trim().to_ascii_uppercase().replace('-', "_")
+            );
+            headers.push((key, value.to_string()));
+        }
+        vars.extend(headers);
+        Ok(Self(vars))
+    }
+}
+
+/// Called by [`CgiEnv::new_request`] on every request it builds, e.g. to
+/// add typed data to its extensions for the handler to read.
+pub type RequestHook = fn(&mut http::Request<()>);
+
 #[derive(Clone)]
 pub struct CgiEnv<E> {
     env: E,
+    hooks: Vec<RequestHook>,
 }
 
 impl<E> Debug for CgiEnv<E>
@@ -36,9 +145,8 @@ where
     E: Environment,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let cgi_vars: BTreeMap<_, _> = self.iter().collect();
         f.debug_struct("CgiEnv")
-            .field("cgi_env", &cgi_vars)
+            .field("cgi_env", &self.recognized())
             .finish()
     }
 }
@@ -48,6 +156,23 @@ pub enum CgiEnvError {
     InvalidMetaVariable(MetaVariableKind),
     HttpError(http::Error),
     InvalidUrl(InvalidUri),
+    /// The request body is larger than the limit, in bytes.
+    BodyTooLarge(u64),
+    Io(std::io::Error),
+}
+
+impl CgiEnvError {
+    /// The response status this error should be reported with.
+    #[must_use]
+    pub const fn status_code(&self) -> http::StatusCode {
+        match self {
+            Self::BodyTooLarge(_) => http::StatusCode::PAYLOAD_TOO_LARGE,
+            Self::InvalidMetaVariable(_) | Self::HttpError(_) | Self::InvalidUrl(_) => {
+                http::StatusCode::BAD_REQUEST
+            }
+            Self::Io(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
 
 impl core::error::Error for CgiEnvError {}
@@ -66,6 +191,14 @@ impl fmt::Display for CgiEnvError {
             Self::InvalidUrl(x) => {
                 f.debug_tuple("CgiEnvError::InvalidUrl").field(x).finish()?;
             }
+            Self::BodyTooLarge(x) => {
+                f.debug_tuple("CgiEnvError::BodyTooLarge")
+                    .field(x)
+                    .finish()?;
+            }
+            Self::Io(x) => {
+                f.debug_tuple("CgiEnvError::Io").field(x).finish()?;
+            }
         }
 
         Ok(())
@@ -84,6 +217,12 @@ impl From<InvalidUri> for CgiEnvError {
     }
 }
 
+impl From<std::io::Error> for CgiEnvError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PathInfo(String);
 
@@ -93,6 +232,29 @@ impl AsRef<str> for PathInfo {
     }
 }
 
+/// Tenant of a multi-tenant deployment, as an example of data added by a
+/// [`RequestHook`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant(pub String);
+
+impl Tenant {
+    /// Header set by the front end proxy.
+    pub const HEADER: &'static str = "x-tenant";
+
+    /// A [`RequestHook`] adding the tenant from [`Tenant::HEADER`], if
+    /// present and valid UTF-8.
+    pub fn from_header(request: &mut http::Request<()>) {
+        let tenant = request
+            .headers()
+            .get(Self::HEADER)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| Self(x.to_string()));
+        if let Some(tenant) = tenant {
+            request.extensions_mut().insert(tenant);
+        }
+    }
+}
+
 // Not meant to be a generic solution. Just a simple implementation.
 // This works for Apache with suexec cgi.
 impl<E> CgiEnv<E>
@@ -101,7 +263,27 @@ where
 {
     #[must_use]
     pub const fn new(env: E) -> Self {
-        Self { env }
+        Self {
+            env,
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Run `hook` on every request from [`CgiEnv::new_request`], after
+    /// the hooks added before it.
+    ///
+    /// ```
+    /// use cgi::cgi_env::{CgiEnv, OsEnvironment, Tenant};
+    ///
+    /// let cgi_env = CgiEnv::new(OsEnvironment).with_request_hook(Tenant::from_header);
+    /// if let Ok(request) = cgi_env.new_request() {
+    ///     let tenant = request.extensions().get::<Tenant>();
+    /// }
+    /// ```
+    #[must_use]
+    pub fn with_request_hook(mut self, hook: RequestHook) -> Self {
+        self.hooks.push(hook);
+        self
     }
 
     /// # Errors
@@ -139,9 +321,52 @@ where
             ))
             .body(())?;
         req.headers_mut().extend(headers);
+        for hook in &self.hooks {
+            hook(&mut req);
+        }
         Ok(req)
     }
 
+    /// Read the request body from `input`, which is stdin for CGI.
+    ///
+    /// At most `CONTENT_LENGTH` bytes are read, and never more than
+    /// `limit + 1` even if `input` is longer than advertised. Without a
+    /// `CONTENT_LENGTH` the body is empty.
+    ///
+    /// # Errors
+    /// [`CgiEnvError::BodyTooLarge`] if `CONTENT_LENGTH` or the actual
+    /// body exceeds `limit`, or if `CONTENT_LENGTH` is not a number.
+    pub fn read_body(&self, input: impl Read, limit: u64) -> Result<Vec<u8>, CgiEnvError> {
+        const CHUNK_SIZE: usize = 8 * 1024;
+
+        let content_length = match self.getenv(MetaVariableKind::ContentLength) {
+            Some(x) => x
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| CgiEnvError::InvalidMetaVariable(MetaVariableKind::ContentLength))?,
+            None => 0,
+        };
+        if content_length > limit {
+            return Err(CgiEnvError::BodyTooLarge(limit));
+        }
+        let mut input = input.take(content_length.min(limit.saturating_add(1)));
+        let mut body = Vec::new();
+        let mut chunk = [0; CHUNK_SIZE];
+        loop {
+            let n = match input.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            body.extend_from_slice(&chunk[..n]);
+            if body.len() as u64 > limit {
+                return Err(CgiEnvError::BodyTooLarge(limit));
+            }
+        }
+        Ok(body)
+    }
+
     pub fn is_cgi(&self) -> bool {
         self.getenv(MetaVariableKind::GatewayInterface).is_some()
     }
@@ -175,6 +400,12 @@ where
             .collect()
     }
 
+    /// The environment variables that are known meta-variables, sorted.
+    #[must_use]
+    pub fn recognized(&self) -> BTreeMap<MetaVariableKind, String> {
+        self.iter().collect()
+    }
+
     #[allow(clippy::iter_without_into_iter)]
     pub fn iter(&self) -> impl Iterator<Item = (MetaVariableKind, String)> + use<'_, E> {
         self.env.vars().filter_map(|(k, v)| {
@@ -250,10 +481,11 @@ derive_display_from_serialize!(MetaVariableKind);
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Config;
     use http::Method;
     use std::collections::HashMap;
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     struct TestEnvironment {
         vars: HashMap<OsString, OsString>,
     }
@@ -314,6 +546,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_new_request_hook() {
+        let mut env = TestEnvironment::default();
+        env.vars.insert("HTTP_X_TENANT".into(), "acme".into());
+        let req = CgiEnv::new(env.clone()).new_request().unwrap();
+        assert!(req.extensions().get::<Tenant>().is_none());
+
+        let cgi_env = CgiEnv::new(env)
+            .with_request_hook(Tenant::from_header)
+            .with_request_hook(|req| {
+                // Sees what earlier hooks added
+                let seen = req.extensions().get::<Tenant>().cloned();
+                req.extensions_mut().insert(seen.map(|x| x.0.len()));
+            });
+        let req = cgi_env.new_request().unwrap();
+        assert_eq!(
+            req.extensions().get::<Tenant>(),
+            Some(&Tenant("acme".to_string()))
+        );
+        assert_eq!(req.extensions().get::<Option<usize>>(), Some(&Some(4)));
+        // Built-in extensions are still there
+        assert!(req.extensions().get::<PathInfo>().is_some());
+    }
+
     #[test]
     fn test_new_request_missing_env_vars() {
         let result = empty_environ().new_request();
@@ -330,4 +586,104 @@ mod tests {
         let result = env.new_request();
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_request_environment() {
+        let input = "GET /ab?x=1 HTTP/1.1\r\nHost: example.org:8080\r\nX-Test: a b\r\n\r\nignored";
+        let env = CgiEnv::new(RequestEnvironment::from_http_request(input.as_bytes()).unwrap());
+        let req = env.new_request().unwrap();
+        assert_eq!(req.uri(), "http://example.org/ab?x=1");
+        assert_eq!(req.version(), http::Version::HTTP_11);
+        assert_eq!(req.headers()["x-test"], "a b");
+        assert_eq!(req.extensions().get::<PathInfo>().unwrap().as_ref(), "/ab");
+        assert_eq!(env.getenv(MetaVariableKind::QueryString).unwrap(), "x=1");
+        assert_eq!(env.getenv(MetaVariableKind::ServerPort).unwrap(), "8080");
+
+        let input = "GET /caf%C3%A9%20x?q=%20 HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n";
+        let env = CgiEnv::new(RequestEnvironment::from_http_request(input.as_bytes()).unwrap());
+        let req = env.new_request().unwrap();
+        assert_eq!(req.uri(), "http://[::1]/caf%C3%A9%20x?q=%20");
+        assert_eq!(
+            req.extensions().get::<PathInfo>().unwrap().as_ref(),
+            "/café x"
+        );
+        assert_eq!(env.getenv(MetaVariableKind::ServerName).unwrap(), "[::1]");
+        assert_eq!(env.getenv(MetaVariableKind::ServerPort).unwrap(), "8080");
+
+        let input = "GET / HTTP/1.1\r\nHost: [::1]\r\n\r\n";
+        let env = CgiEnv::new(RequestEnvironment::from_http_request(input.as_bytes()).unwrap());
+        assert_eq!(env.getenv(MetaVariableKind::ServerName).unwrap(), "[::1]");
+        assert_eq!(env.getenv(MetaVariableKind::ServerPort).unwrap(), "80");
+
+        assert!(RequestEnvironment::from_http_request(&b"GET /\r\n\r\n"[..]).is_err());
+        assert!(RequestEnvironment::from_http_request(&b"GET /%FF HTTP/1.0\r\n\r\n"[..]).is_err());
+        assert!(
+            RequestEnvironment::from_http_request(&b"GET / HTTP/1.0\r\nHost: a b\r\n\r\n"[..])
+                .is_err()
+        );
+        assert!(RequestEnvironment::from_http_request(&b"GET / HTTP/1.0\r\nbad\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_read_body() {
+        let mut env = environ();
+        assert!(env.read_body(&b"ignored"[..], 4).unwrap().is_empty());
+
+        env.env.vars.insert("CONTENT_LENGTH".into(), "4".into());
+        assert_eq!(env.read_body(&b"body and more"[..], 4).unwrap(), b"body");
+        // Shorter than advertised
+        assert_eq!(env.read_body(&b"bo"[..], 4).unwrap(), b"bo");
+    }
+
+    #[test]
+    fn test_read_body_too_large() {
+        let mut env = environ();
+        env.env.vars.insert("CONTENT_LENGTH".into(), "5".into());
+        let result = env.read_body(&b"12345"[..], 4);
+        assert!(matches!(result, Err(CgiEnvError::BodyTooLarge(4))));
+        assert_eq!(
+            result.unwrap_err().status_code(),
+            http::StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        // A body larger than the limit is never read past it
+        let body = vec![b'x'; 100_000];
+        let mut input = &body[..];
+        let limit = Config::DEFAULT_MAX_REQUEST_BODY;
+        env.env
+            .vars
+            .insert("CONTENT_LENGTH".into(), limit.to_string().into());
+        assert_eq!(
+            env.read_body(&mut input, limit).unwrap().len() as u64,
+            limit
+        );
+        assert_eq!(input.len() as u64, 100_000 - limit);
+    }
+
+    #[test]
+    fn test_read_body_invalid_content_length() {
+        let mut env = environ();
+        env.env.vars.insert("CONTENT_LENGTH".into(), "-1".into());
+        assert!(matches!(
+            env.read_body(&b""[..], 4),
+            Err(CgiEnvError::InvalidMetaVariable(
+                MetaVariableKind::ContentLength
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_recognized() {
+        let recognized = environ().recognized();
+        assert_eq!(
+            recognized.get(&MetaVariableKind::RequestMethod).unwrap(),
+            "GET"
+        );
+        assert_eq!(recognized.len(), 6);
+        assert!(recognized.values().all(|v| v != "test_value"));
+
+        let json = serde_json::to_string(&recognized).unwrap();
+        assert!(json.contains(r#""REQUEST_METHOD":"GET""#));
+        assert!(!json.contains("HTTP_TEST_HEADER"));
+    }
 }
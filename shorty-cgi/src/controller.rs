@@ -1,42 +1,114 @@
 use askama::Template;
 use core::time::Duration;
-use headers::{CacheControl, ETag, Expires, Header as _, HeaderMapExt as _, LastModified};
+use headers::{
+    CacheControl, ETag, Expires, Header as _, HeaderMapExt as _, IfModifiedSince, IfNoneMatch,
+    LastModified,
+};
 use http::{Response, StatusCode};
 use shorty::anyhow;
-use shorty::types::{ShortUrl, UnixTimestamp};
-use shorty::{repository::Repository, types::ShortUrlName};
+use shorty::qr::QrEcLevel;
+use shorty::types::{ShortUrl, UnixTimestamp, Url};
+use shorty::{
+    repository::{Repository, ResolveOpts, UrlStatus},
+    types::ShortUrlName,
+};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::{
-    html_response,
+    html_response, is_not_modified,
     templates::{HttpErrorTemplate, QuotationTemplate, ShortUrlTemplate},
+    timing::{measure, Metric},
     VERSION,
 };
 
 pub struct ShortUrlController<T> {
     repo: T,
+    options: ShortUrlOptions,
+}
+
+/// Deployment specific settings for [`ShortUrlController`].
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ShortUrlOptions {
+    pub qr_ec_level: QrEcLevel,
+    pub qr_relative_title: bool,
+    /// Add the query string of the page URL to the target URL.
+    pub forward_query: bool,
+    /// Redirect to the target instead of rendering the landing page.
+    pub redirect: bool,
+    /// Replace `http` with `https` in the target URL.
+    pub https_upgrade: bool,
+    /// Put this path in front of the path of the target URL.
+    pub base_path: Option<String>,
+    /// How long shared caches may keep the page, shown on it as "cached
+    /// until". Defaults to [`ShortUrlOptions::DEFAULT_MAX_AGE`].
+    pub max_age: Option<Duration>,
+    /// Redirect unknown names here instead of answering 404 Not Found.
+    pub not_found_redirect: Option<Url>,
+}
+
+impl ShortUrlOptions {
+    pub const DEFAULT_MAX_AGE: Duration = Duration::from_mins(5);
+
+    #[must_use]
+    pub fn max_age(&self) -> Duration {
+        self.max_age.unwrap_or(Self::DEFAULT_MAX_AGE)
+    }
 }
 
 impl<T> ShortUrlController<T> {
-    pub const fn new(repo: T) -> Self {
-        Self { repo }
+    pub fn new(repo: T) -> Self {
+        Self::with_options(repo, ShortUrlOptions::default())
+    }
+
+    pub const fn with_options(repo: T, options: ShortUrlOptions) -> Self {
+        Self { repo, options }
     }
 }
 
 pub struct ShortUrlControllerParams {
     pub name: ShortUrlName,
     pub page_url: http::Uri,
+    pub if_none_match: Option<IfNoneMatch>,
+    pub if_modified_since: Option<IfModifiedSince>,
 }
 
+/// `None` for rows from before `last_modified` was added, which can't
+/// be revalidated.
 #[inline]
-fn short_url_to_last_modified(short_url: &ShortUrl) -> (LastModified, UnixTimestamp) {
-    let last_modifed = short_url.last_modified.unwrap_or_default();
+fn short_url_to_last_modified(short_url: &ShortUrl) -> Option<(LastModified, UnixTimestamp)> {
+    short_url.last_modified.map(timestamp_to_last_modified)
+}
+
+#[inline]
+fn timestamp_to_last_modified(timestamp: UnixTimestamp) -> (LastModified, UnixTimestamp) {
     (
-        LastModified::from(UNIX_EPOCH + Duration::from_secs(last_modifed.0)),
-        last_modifed,
+        LastModified::from(UNIX_EPOCH + Duration::from_secs(timestamp.0)),
+        timestamp,
     )
 }
 
+/// Permanently redirect a differently cased request to the stored name.
+fn canonical_redirect(
+    params: &ShortUrlControllerParams,
+    canonical: &ShortUrlName,
+) -> Response<String> {
+    let path = params.page_url.path();
+    let base = path.strip_suffix(params.name.as_ref()).unwrap_or("/");
+    let query = params
+        .page_url
+        .query()
+        .map(|query| format!("?{query}"))
+        .unwrap_or_default();
+    let location = format!("{base}{canonical}{query}");
+    let mut response = html_response(StatusCode::MOVED_PERMANENTLY, String::new());
+    response.headers_mut().insert(
+        http::header::LOCATION,
+        location.try_into().expect("Failed to create Location"),
+    );
+    response
+}
+
 impl<T> Controller for ShortUrlController<T>
 where
     T: Repository,
@@ -45,40 +117,135 @@ where
     type Result = Result<Response<String>, anyhow::Error>;
 
     fn respond(&self, params: Self::Params) -> Self::Result {
-        match self.repo.get_url(&params.name) {
-            Ok(Some(short_url)) => {
-                let (last_modified, last_modified_tstamp) = short_url_to_last_modified(&short_url);
-                let etag = format!("\"{VERSION}-{}\"", last_modified_tstamp.0)
-                    .parse::<ETag>()
-                    .expect("Failed to create ETag");
+        let max_age = self.options.max_age();
+        let resolve_opts = ResolveOpts {
+            https_upgrade: self.options.https_upgrade,
+            base_path: self.options.base_path.as_deref(),
+            forward_query: params
+                .page_url
+                .query()
+                .filter(|_| self.options.forward_query),
+        };
+        if self.options.redirect {
+            let Some(target) =
+                measure(Metric::Db, || self.repo.resolve(&params.name, resolve_opts))?
+            else {
+                return not_found_response(self.options.not_found_redirect.as_ref());
+            };
+            let mut response = html_response(StatusCode::FOUND, String::new());
+            response.headers_mut().insert(
+                http::header::LOCATION,
+                target
+                    .to_string()
+                    .try_into()
+                    .expect("Failed to create Location"),
+            );
+            add_cache_headers(&mut response, None, max_age);
+            return Ok(response);
+        }
+        // Revalidation needs neither the row nor a render. The probe only
+        // knows the exact spelling, so other cases still get redirected.
+        if params.if_none_match.is_some() || params.if_modified_since.is_some() {
+            if let Some(timestamp) = measure(Metric::Db, || self.repo.last_modified(&params.name))?
+            {
+                let mut response = html_response(StatusCode::NOT_MODIFIED, String::new());
+                add_cache_headers(
+                    &mut response,
+                    Some(timestamp_to_last_modified(timestamp)),
+                    max_age,
+                );
+                if is_not_modified(
+                    params.if_none_match.as_ref(),
+                    params.if_modified_since.as_ref(),
+                    response.headers(),
+                ) {
+                    return Ok(response);
+                }
+            }
+        }
+        match measure(Metric::Db, || self.repo.get_or_404_status(&params.name))? {
+            UrlStatus::OtherCase(short_url) => Ok(canonical_redirect(&params, &short_url.name)),
+            UrlStatus::Found(mut short_url) => {
+                let validators = short_url_to_last_modified(&short_url);
+                short_url.url = resolve_opts.apply(&short_url.url);
+                let cached_until = (SystemTime::now() + max_age)
+                    .duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|x| UnixTimestamp(x.as_secs()));
                 let template = ShortUrlTemplate {
                     page_url: params.page_url,
                     short_url,
+                    qr_ec_level: self.options.qr_ec_level,
+                    relative_qr_title: self.options.qr_relative_title,
+                    cached_until,
                 };
-                let body = template.render()?;
+                let body = measure(Metric::Render, || template.render())?;
                 let mut response = html_response(StatusCode::OK, body);
-                response.headers_mut().typed_insert(etag);
-                response.headers_mut().typed_insert(last_modified);
-                // TODO: headers::CacheControl doesn't support all this yet
-                response.headers_mut().insert(
-                    CacheControl::name(),
-                    "public, s-maxage=300, proxy-revalidate"
-                        .try_into()
-                        .expect("Failed to create CacheControl"),
-                );
+                add_cache_headers(&mut response, validators, max_age);
                 Ok(response)
             }
-            Ok(None) => ErrorController {}.respond((StatusCode::NOT_FOUND, String::new())),
-            Err(err) => Err(err),
+            UrlStatus::NotFound => not_found_response(self.options.not_found_redirect.as_ref()),
         }
     }
 }
 
-pub struct QuotationController<T> {
+/// 404 Not Found for an unknown or invalid short URL name, or a 302 to
+/// `redirect` if set, see [`ShortUrlOptions::not_found_redirect`].
+///
+/// # Errors
+///
+/// If the error page fails to render.
+pub fn not_found_response(redirect: Option<&Url>) -> Result<Response<String>, anyhow::Error> {
+    let Some(url) = redirect else {
+        return ErrorController {}.respond((StatusCode::NOT_FOUND, String::new()));
+    };
+    let mut response = html_response(StatusCode::FOUND, String::new());
+    response.headers_mut().insert(
+        http::header::LOCATION,
+        url.to_string()
+            .try_into()
+            .expect("Failed to create Location"),
+    );
+    // The name may be added later
+    response.headers_mut().insert(
+        CacheControl::name(),
+        "no-store"
+            .try_into()
+            .expect("Failed to create CacheControl"),
+    );
+    Ok(response)
+}
+
+/// Validators are only sent for a row with a timestamp. The `ETag` is
+/// weak, as the page shows when it was rendered, so two responses for
+/// the same row differ in that time.
+fn add_cache_headers(
+    response: &mut Response<String>,
+    validators: Option<(LastModified, UnixTimestamp)>,
+    max_age: Duration,
+) {
+    if let Some((last_modified, timestamp)) = validators {
+        let etag = format!("W/\"{VERSION}-{}\"", timestamp.0)
+            .parse::<ETag>()
+            .expect("Failed to create ETag");
+        response.headers_mut().typed_insert(etag);
+        response.headers_mut().typed_insert(last_modified);
+    }
+    // TODO: headers::CacheControl doesn't support all this yet
+    response.headers_mut().insert(
+        CacheControl::name(),
+        format!("public, s-maxage={}, proxy-revalidate", max_age.as_secs())
+            .try_into()
+            .expect("Failed to create CacheControl"),
+    );
+}
+
+/// Redirects to the landing page of a random short URL.
+pub struct RandomUrlController<T> {
     repo: T,
 }
 
-impl<T> QuotationController<T>
+impl<T> RandomUrlController<T>
 where
     T: Repository,
 {
@@ -87,6 +254,92 @@ where
     }
 }
 
+impl<T> Controller for RandomUrlController<T>
+where
+    T: Repository,
+{
+    /// URL of the requested page, the landing page is its sibling.
+    type Params = http::Uri;
+    type Result = Result<Response<String>, anyhow::Error>;
+
+    fn respond(&self, page_url: Self::Params) -> Self::Result {
+        let Some(short_url) = measure(Metric::Db, || self.repo.get_random_url())? else {
+            return ErrorController {}.respond((StatusCode::NOT_FOUND, String::new()));
+        };
+        let path = page_url.path();
+        let base = path.rsplit_once('/').map_or("", |(base, _)| base);
+        let location = format!("{base}/{}", short_url.name);
+        let mut response = html_response(StatusCode::FOUND, String::new());
+        response.headers_mut().insert(
+            http::header::LOCATION,
+            location.try_into().expect("Failed to create Location"),
+        );
+        // Every request should get a new pick
+        response.headers_mut().insert(
+            CacheControl::name(),
+            "no-store"
+                .try_into()
+                .expect("Failed to create CacheControl"),
+        );
+        Ok(response)
+    }
+}
+
+/// How long the home page, with its random quote, may be cached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HomeCache {
+    /// `Expires` in 24 hours, so a cache shows the same quote all day.
+    #[default]
+    Daily,
+    /// `Cache-Control: private, no-cache`, so every visitor gets a fresh
+    /// quote.
+    Private,
+}
+
+/// Deployment specific settings for [`QuotationController`].
+#[derive(Debug, Clone, Default)]
+pub struct QuotationOptions {
+    /// Longest quote in characters, longer ones are cut at a word
+    /// boundary and end with an ellipsis.
+    pub max_quote_len: Option<usize>,
+    pub cache: HomeCache,
+}
+
+pub struct QuotationController<T> {
+    repo: T,
+    options: QuotationOptions,
+}
+
+impl<T> QuotationController<T>
+where
+    T: Repository,
+{
+    pub fn new(repo: T) -> Self {
+        Self::with_options(repo, QuotationOptions::default())
+    }
+
+    pub const fn with_options(repo: T, options: QuotationOptions) -> Self {
+        Self { repo, options }
+    }
+}
+
+/// `quote` cut to at most `max_len` characters, before the word the cut
+/// falls in, with `…` added. A single word longer than `max_len` is cut
+/// inside the word.
+fn truncate_quote(quote: String, max_len: usize) -> String {
+    let Some((cut, _)) = quote.char_indices().nth(max_len) else {
+        return quote;
+    };
+    let head = &quote[..cut];
+    let head = if quote[cut..].starts_with(char::is_whitespace) {
+        head
+    } else {
+        head.rfind(char::is_whitespace).map_or(head, |i| &head[..i])
+    };
+    format!("{}\u{2026}", head.trim_end())
+}
+
 impl<T> Controller for QuotationController<T>
 where
     T: Repository,
@@ -95,13 +348,26 @@ where
     type Result = Result<Response<String>, anyhow::Error>;
 
     fn respond(&self, (): Self::Params) -> Self::Result {
-        let quote = self.repo.get_random_quote()?;
+        let mut quote = measure(Metric::Db, || self.repo.get_random_quote())?;
+        // Before templating, so escaping sees the cut string
+        if let Some(max_len) = self.options.max_quote_len {
+            quote = truncate_quote(quote, max_len);
+        }
         let template = QuotationTemplate { quote };
-        let body = template.render()?;
-        let time = SystemTime::now() + Duration::from_hours(24);
+        let body = measure(Metric::Render, || template.render())?;
 
         let mut response = html_response(StatusCode::OK, body);
-        response.headers_mut().typed_insert(Expires::from(time));
+        match self.options.cache {
+            HomeCache::Daily => {
+                let time = SystemTime::now() + Duration::from_hours(24);
+                response.headers_mut().typed_insert(Expires::from(time));
+            }
+            HomeCache::Private => {
+                response
+                    .headers_mut()
+                    .typed_insert(CacheControl::new().with_private().with_no_cache());
+            }
+        }
         Ok(response)
     }
 }
@@ -117,7 +383,8 @@ impl Controller for ErrorController {
             status_code: params.0,
             details: params.1,
         };
-        Ok(html_response(params.0, template.render()?))
+        let body = measure(Metric::Render, || template.render())?;
+        Ok(html_response(params.0, body))
     }
 }
 
@@ -132,7 +399,9 @@ mod test {
     use super::*;
 
     use shorty::{
-        repository::{sqlite::open_writable_in_memory_repository, WritableRepository},
+        repository::{
+            sqlite::open_writable_in_memory_repository, RepositoryError, WritableRepository,
+        },
         types::{ShortUrl, UnixTimestamp},
     };
 
@@ -168,6 +437,61 @@ mod test {
             .contains("<blockquote>A&#60;&#62;&#39;&#34;</blockquote>"));
     }
 
+    #[test]
+    fn test_quotation_controller_max_quote_len() {
+        let respond = |quote: &str, max_quote_len| {
+            let mut repo = repo(true);
+            repo.insert_quotation(quote).unwrap();
+            let controller = QuotationController::with_options(
+                repo,
+                QuotationOptions {
+                    max_quote_len,
+                    ..Default::default()
+                },
+            );
+            controller.respond(()).unwrap().into_body()
+        };
+        let quote = "A <b> & c";
+
+        // Under and at the cap
+        for max_quote_len in [None, Some(20), Some(9)] {
+            assert!(respond(quote, max_quote_len)
+                .contains("<blockquote>A &#60;b&#62; &#38; c</blockquote>"));
+        }
+        // Over the cap, cut before the word the cap falls in
+        assert!(respond(quote, Some(8))
+            .contains("<blockquote>A &#60;b&#62; &#38;\u{2026}</blockquote>"));
+        assert!(respond(quote, Some(5)).contains("<blockquote>A &#60;b&#62;\u{2026}</blockquote>"));
+        assert!(respond(quote, Some(4)).contains("<blockquote>A\u{2026}</blockquote>"));
+        // A single long word is cut inside it
+        assert!(respond("abcdef", Some(3)).contains("<blockquote>abc\u{2026}</blockquote>"));
+    }
+
+    #[test]
+    fn test_quotation_controller_cache() {
+        let respond = |cache| {
+            let controller = QuotationController::with_options(
+                repo(true),
+                QuotationOptions {
+                    cache,
+                    ..Default::default()
+                },
+            );
+            controller.respond(()).unwrap()
+        };
+
+        let res = respond(HomeCache::Daily);
+        assert!(res.headers().contains_key(http::header::EXPIRES));
+        assert!(!res.headers().contains_key(http::header::CACHE_CONTROL));
+
+        let res = respond(HomeCache::Private);
+        assert!(!res.headers().contains_key(http::header::EXPIRES));
+        assert_eq!(
+            res.headers()[http::header::CACHE_CONTROL],
+            "no-cache, private"
+        );
+    }
+
     #[test]
     fn test_quotation_controller_error() {
         let repo = repo(false);
@@ -175,7 +499,39 @@ mod test {
 
         let res = controller.respond(());
 
-        assert!(res.is_err());
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Migration)
+        ));
+    }
+
+    #[test]
+    fn test_random_url_controller() {
+        let mut repo = repo(true);
+        repo.insert_url(
+            &"surl".try_into().unwrap(),
+            &"https://example.com".try_into().unwrap(),
+        )
+        .unwrap();
+        let controller = RandomUrlController::new(repo);
+
+        let res = controller
+            .respond(http::Uri::from_static("https://example.org/app/random"))
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.headers()[http::header::LOCATION], "/app/surl");
+        assert_eq!(res.headers()[headers::CacheControl::name()], "no-store");
+    }
+
+    #[test]
+    fn test_random_url_controller_empty() {
+        let controller = RandomUrlController::new(repo(true));
+
+        let res = controller
+            .respond(http::Uri::from_static("https://example.org/random"))
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(!res.headers().contains_key(http::header::LOCATION));
     }
 
     #[test]
@@ -185,16 +541,23 @@ mod test {
             name: "surl".try_into().unwrap(),
             url: "https://example.com".try_into().unwrap(),
             last_modified: None,
+            title: None,
         };
         repo.insert_url(&short_url.name, &short_url.url).unwrap();
         let controller = ShortUrlController::new(repo);
         let params = ShortUrlControllerParams {
             page_url: http::Uri::from_static("https://example.org/surl"),
             name: short_url.name,
+            if_none_match: None,
+            if_modified_since: None,
         };
         let res = controller.respond(params).unwrap();
         assert_eq!(res.status(), StatusCode::OK);
-        assert!(res.headers().contains_key(headers::ETag::name()));
+        // Weak, as the shown "cached until" time differs per response
+        assert!(res.headers()[headers::ETag::name()]
+            .to_str()
+            .unwrap()
+            .starts_with("W/\""));
         assert!(res.headers().contains_key(headers::CacheControl::name()));
         assert!(res.headers().contains_key(headers::LastModified::name()));
         assert!(res
@@ -205,12 +568,317 @@ mod test {
         ));
     }
 
+    #[test]
+    fn test_short_url_controller_exact_casing() {
+        let mut repo = repo(true);
+        let name: ShortUrlName = "ab".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+        let controller = ShortUrlController::new(repo);
+        let params = ShortUrlControllerParams {
+            page_url: http::Uri::from_static("https://example.org/ab"),
+            name,
+            if_none_match: None,
+            if_modified_since: None,
+        };
+        let res = controller.respond(params).unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key(http::header::LOCATION));
+    }
+
+    #[test]
+    fn test_short_url_controller_canonical_redirect() {
+        let mut repo = repo(true);
+        repo.insert_url(
+            &"ab".try_into().unwrap(),
+            &"https://example.com".try_into().unwrap(),
+        )
+        .unwrap();
+        let controller = ShortUrlController::new(repo);
+        let params = ShortUrlControllerParams {
+            page_url: http::Uri::from_static("https://example.org/s/AB"),
+            name: "AB".try_into().unwrap(),
+            if_none_match: None,
+            if_modified_since: None,
+        };
+        let res = controller.respond(params).unwrap();
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers().get(http::header::LOCATION).unwrap(), "/s/ab");
+    }
+
+    #[test]
+    fn test_short_url_controller_forward_query() {
+        let mut repo = repo(true);
+        repo.insert_url(
+            &"ab".try_into().unwrap(),
+            &"https://example.com/".try_into().unwrap(),
+        )
+        .unwrap();
+        repo.insert_url(
+            &"cd".try_into().unwrap(),
+            &"https://example.com/?a=1".try_into().unwrap(),
+        )
+        .unwrap();
+        let controller = ShortUrlController::with_options(
+            repo,
+            ShortUrlOptions {
+                forward_query: true,
+                ..Default::default()
+            },
+        );
+        let respond = |page_url: &'static str, name: &str| {
+            controller
+                .respond(ShortUrlControllerParams {
+                    page_url: http::Uri::from_static(page_url),
+                    name: name.try_into().unwrap(),
+                    if_none_match: None,
+                    if_modified_since: None,
+                })
+                .unwrap()
+        };
+
+        let res = respond("https://example.org/ab?ref=x", "ab");
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res
+            .body()
+            .contains(r#"<a href="https://example.com/?ref=x">"#));
+
+        let res = respond("https://example.org/cd?ref=x", "cd");
+        assert!(res
+            .body()
+            .contains(r#"<a href="https://example.com/?a=1&#38;ref=x">"#));
+
+        let res = respond("https://example.org/CD?ref=x", "CD");
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            res.headers().get(http::header::LOCATION).unwrap(),
+            "/cd?ref=x"
+        );
+    }
+
+    #[test]
+    fn test_short_url_controller_max_age() {
+        let mut repo = repo(true);
+        let name: ShortUrlName = "ab".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+        let controller = ShortUrlController::with_options(
+            repo,
+            ShortUrlOptions {
+                max_age: Some(Duration::from_hours(1)),
+                ..Default::default()
+            },
+        );
+        let cached_until = || {
+            let secs = (SystemTime::now() + Duration::from_hours(1))
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            UnixTimestamp(secs).iso8601().unwrap()
+        };
+        let before = cached_until();
+        let res = controller
+            .respond(ShortUrlControllerParams {
+                page_url: http::Uri::from_static("https://example.org/ab"),
+                name,
+                if_none_match: None,
+                if_modified_since: None,
+            })
+            .unwrap();
+        let after = cached_until();
+
+        assert_eq!(
+            res.headers()[headers::CacheControl::name()],
+            "public, s-maxage=3600, proxy-revalidate"
+        );
+        assert!(
+            [before, after].iter().any(|x| res
+                .body()
+                .contains(&format!(r#"Cached until <time datetime="{x}">{x}</time>"#))),
+            "{}",
+            res.body()
+        );
+    }
+
+    #[test]
+    fn test_short_url_controller_not_found() {
+        let params = || ShortUrlControllerParams {
+            page_url: http::Uri::from_static("https://example.org/nope"),
+            name: "nope".try_into().unwrap(),
+            if_none_match: None,
+            if_modified_since: None,
+        };
+
+        let res = ShortUrlController::new(repo(true))
+            .respond(params())
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(!res.headers().contains_key(http::header::LOCATION));
+
+        let controller = ShortUrlController::with_options(
+            repo(true),
+            ShortUrlOptions {
+                not_found_redirect: Some("https://example.com/landing".try_into().unwrap()),
+                ..Default::default()
+            },
+        );
+        let res = controller.respond(params()).unwrap();
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(
+            res.headers()[http::header::LOCATION],
+            "https://example.com/landing"
+        );
+        assert_eq!(res.headers()[headers::CacheControl::name()], "no-store");
+    }
+
+    fn respond_conditional(if_none_match: Option<&str>, since: SystemTime) -> Response<String> {
+        let mut repo = repo(true);
+        let name: ShortUrlName = "surl".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+        let controller = ShortUrlController::new(repo);
+        let params = ShortUrlControllerParams {
+            page_url: http::Uri::from_static("https://example.org/surl"),
+            name,
+            if_none_match: if_none_match
+                .map(|etag| IfNoneMatch::from(etag.parse::<ETag>().expect("Failed to parse ETag"))),
+            if_modified_since: Some(since.into()),
+        };
+        controller.respond(params).unwrap()
+    }
+
+    fn respond_if_modified_since(since: SystemTime) -> Response<String> {
+        respond_conditional(None, since)
+    }
+
+    #[test]
+    fn test_short_url_controller_if_modified_since_future() {
+        let res = respond_if_modified_since(SystemTime::now() + Duration::from_hours(1));
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert!(res.body().is_empty());
+        assert!(res.headers().contains_key(headers::ETag::name()));
+        assert!(res.headers().contains_key(headers::LastModified::name()));
+    }
+
+    #[test]
+    fn test_short_url_controller_if_none_match_mismatch() {
+        // If-None-Match decides, the fresh If-Modified-Since is ignored
+        let res = respond_conditional(
+            Some("\"other\""),
+            SystemTime::now() + Duration::from_hours(1),
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.body().contains("Go to surl"));
+
+        let etag = respond_if_modified_since(SystemTime::now() + Duration::from_hours(1)).headers()
+            [headers::ETag::name()]
+        .to_str()
+        .unwrap()
+        .to_string();
+        let res = respond_conditional(Some(&etag), UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn test_short_url_controller_if_modified_since_past() {
+        let res = respond_if_modified_since(UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.body().contains("Go to surl"));
+    }
+
+    #[test]
+    fn test_short_url_controller_null_last_modified() {
+        let (_repo, temp_dir) = shorty::testutil::seeded_repo(&[("surl", "https://example.com")]);
+        let db_path = shorty::testutil::database_path(&temp_dir);
+        // As stored before migration 2 added the column
+        shorty::rusqlite::Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(
+                "DROP TRIGGER set_last_modified_on_update;
+                 UPDATE urls SET last_modified = NULL;",
+            )
+            .unwrap();
+        let controller = ShortUrlController::new(
+            shorty::repository::sqlite::open_readonly_repository(&db_path).unwrap(),
+        );
+
+        let res = controller
+            .respond(ShortUrlControllerParams {
+                page_url: http::Uri::from_static("https://example.org/surl"),
+                name: "surl".try_into().unwrap(),
+                if_none_match: None,
+                if_modified_since: Some((SystemTime::now() + Duration::from_hours(1)).into()),
+            })
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.body().contains("Go to surl"));
+        assert!(!res.headers().contains_key(headers::ETag::name()));
+        assert!(!res.headers().contains_key(headers::LastModified::name()));
+        assert!(res.headers().contains_key(headers::CacheControl::name()));
+    }
+
+    #[test]
+    fn test_short_url_controller_if_modified_since_not_canonical() {
+        let mut repo = repo(true);
+        repo.insert_url(
+            &"surl".try_into().unwrap(),
+            &"https://example.com".try_into().unwrap(),
+        )
+        .unwrap();
+        let controller = ShortUrlController::new(repo);
+        let since = SystemTime::now() + Duration::from_hours(1);
+        let respond = |name: &str| {
+            controller
+                .respond(ShortUrlControllerParams {
+                    page_url: format!("https://example.org/{name}").parse().unwrap(),
+                    name: name.try_into().unwrap(),
+                    if_none_match: None,
+                    if_modified_since: Some(since.into()),
+                })
+                .unwrap()
+        };
+
+        let res = respond("SURL");
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers()[http::header::LOCATION], "/surl");
+        assert_eq!(respond("nope").status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_short_url_controller_relative_qr_title() {
+        let mut repo = repo(true);
+        let name: ShortUrlName = "surl".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+        let controller = ShortUrlController::with_options(
+            repo,
+            ShortUrlOptions {
+                qr_relative_title: true,
+                ..Default::default()
+            },
+        );
+        let params = ShortUrlControllerParams {
+            page_url: http::Uri::from_static("https://internal.example.org/surl"),
+            name,
+            if_none_match: None,
+            if_modified_since: None,
+        };
+        let res = controller.respond(params).unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res
+            .body()
+            .contains(r#"<img alt="QR code for /surl" title="/surl" src="#));
+        assert!(!res.body().contains("internal.example.org"));
+    }
+
     #[test]
     fn test_short_url_controller_no_quotes_in_db() {
         let controller = ShortUrlController::new(repo(true));
         let params = ShortUrlControllerParams {
             page_url: http::Uri::from_static("https://example.org/surl"),
             name: "abc".try_into().unwrap(),
+            if_none_match: None,
+            if_modified_since: None,
         };
 
         let res = controller.respond(params).unwrap();
@@ -225,6 +893,8 @@ mod test {
         let params = ShortUrlControllerParams {
             page_url: http::Uri::from_static("https://example.org/surl"),
             name: "abc".try_into().unwrap(),
+            if_none_match: None,
+            if_modified_since: None,
         };
 
         let res = controller.respond(params);
@@ -250,8 +920,9 @@ mod test {
             name: ShortUrlName::try_from("ey").unwrap(),
             url: TryFrom::try_from("https://example.com").unwrap(),
             last_modified: Some(UnixTimestamp(0)),
+            title: None,
         };
-        let (header, last_modified) = short_url_to_last_modified(short_url);
+        let (header, last_modified) = short_url_to_last_modified(short_url).unwrap();
         assert_eq!(Some(last_modified), short_url.last_modified);
         assert_eq!(
             format!("{header:?}"),
@@ -265,8 +936,9 @@ mod test {
             name: ShortUrlName::try_from("ey").unwrap(),
             url: TryFrom::try_from("https://example.com").unwrap(),
             last_modified: Some(UnixTimestamp(1_000_000_000)),
+            title: None,
         };
-        let (header, last_modified) = short_url_to_last_modified(short_url);
+        let (header, last_modified) = short_url_to_last_modified(short_url).unwrap();
         assert_eq!(Some(last_modified), short_url.last_modified);
         assert_eq!(
             format!("{header:?}"),
@@ -0,0 +1,118 @@
+use http::StatusCode;
+use serde::Deserialize;
+use shorty::url::form_urlencoded;
+
+/// Fields of a request to create a short URL.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CreateParams {
+    /// Generated when missing.
+    pub name: Option<String>,
+    pub url: String,
+}
+
+/// Parse a create request body according to its `Content-Type`, which
+/// comes from `CONTENT_TYPE`.
+///
+/// Only `application/json` and `application/x-www-form-urlencoded` are
+/// accepted, parameters like `charset` are ignored.
+///
+/// # Errors
+/// `415 Unsupported Media Type` for a missing or other content type,
+/// `400 Bad Request` if the body doesn't parse or lacks `url`.
+pub fn parse_create_body(
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<CreateParams, StatusCode> {
+    let essence = content_type
+        .and_then(|x| x.split(';').next())
+        .map(|x| x.trim().to_ascii_lowercase());
+    match essence.as_deref() {
+        Some("application/json") => {
+            serde_json::from_slice(body).map_err(|_| StatusCode::BAD_REQUEST)
+        }
+        Some("application/x-www-form-urlencoded") => {
+            let mut name = None;
+            let mut url = None;
+            for (key, value) in form_urlencoded::parse(body) {
+                match key.as_ref() {
+                    "name" => name = Some(value.into_owned()),
+                    "url" => url = Some(value.into_owned()),
+                    _ => {}
+                }
+            }
+            let url = url.ok_or(StatusCode::BAD_REQUEST)?;
+            Ok(CreateParams { name, url })
+        }
+        _ => Err(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_create_body_json() {
+        let params = parse_create_body(
+            Some("application/json; charset=utf-8"),
+            br#"{"name": "ab", "url": "https://example.com"}"#,
+        )
+        .unwrap();
+        assert_eq!(params.name.as_deref(), Some("ab"));
+        assert_eq!(params.url, "https://example.com");
+
+        let params = parse_create_body(
+            Some("Application/JSON"),
+            br#"{"url": "https://example.com"}"#,
+        )
+        .unwrap();
+        assert_eq!(params.name, None);
+
+        assert_eq!(
+            parse_create_body(Some("application/json"), br#"{"name": "ab"}"#),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            parse_create_body(Some("application/json"), b"url=x"),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn test_parse_create_body_form() {
+        let params = parse_create_body(
+            Some("application/x-www-form-urlencoded"),
+            b"name=ab&url=https%3A%2F%2Fexample.com%2F%3Fa%3D1&other=x",
+        )
+        .unwrap();
+        assert_eq!(params.name.as_deref(), Some("ab"));
+        assert_eq!(params.url, "https://example.com/?a=1");
+
+        let params = parse_create_body(
+            Some("application/x-www-form-urlencoded"),
+            b"url=https://example.com",
+        )
+        .unwrap();
+        assert_eq!(params.name, None);
+
+        assert_eq!(
+            parse_create_body(Some("application/x-www-form-urlencoded"), b"name=ab"),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn test_parse_create_body_unsupported() {
+        for content_type in [
+            None,
+            Some("text/plain"),
+            Some("multipart/form-data"),
+            Some(""),
+        ] {
+            assert_eq!(
+                parse_create_body(content_type, br#"{"url": "https://example.com"}"#),
+                Err(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+            );
+        }
+    }
+}
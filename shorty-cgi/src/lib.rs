@@ -3,6 +3,11 @@ use http::StatusCode;
 use std::{path::PathBuf, time::SystemTime};
 
 use git_version::git_version;
+use shorty::anyhow;
+use shorty::qr::QrEcLevel;
+use shorty::types::Url;
+
+use crate::{cgi_env::Environment, controller::HomeCache};
 
 #[cfg(feature = "sentry")]
 use crate::sentry::SentryConfig;
@@ -11,22 +16,487 @@ use crate::sentry::SentryConfig;
 #[macro_use]
 extern crate html5ever;
 
+pub mod access_log;
 pub mod cgi_env;
 pub mod controller;
+pub mod create;
+pub mod minify;
 #[cfg(feature = "sentry")]
 pub mod sentry;
 mod templates;
+pub mod timing;
 
 pub const VERSION: &str = git_version!(prefix = "", cargo_prefix = "cargo:", fallback = "unknown");
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config {
     /// If relative, it will be resolved relative to the config file.
     pub database_file: PathBuf,
+    /// Database work still running this many milliseconds after the
+    /// request started is aborted with a 503 response.
+    #[serde(default)]
+    pub request_timeout_ms: Option<u64>,
+    /// Error correction level of the QR code on the short URL page.
+    #[serde(default)]
+    pub qr_ec_level: QrEcLevel,
+    /// Title the QR code with `/{name}` rather than the absolute page URL,
+    /// for deployments where the host seen by the CGI is not the public one.
+    #[serde(default)]
+    pub qr_relative_title: bool,
+    /// Generate weak (`W/"..."`) instead of strong `ETag`s.
+    #[serde(default)]
+    pub weak_etag: bool,
+    /// Don't generate `ETag`s, for proxies with their own caching
+    /// semantics. An `ETag` set by a controller is still sent.
+    #[serde(default)]
+    pub no_etag: bool,
+    /// Largest request body in bytes that will be read. Defaults to
+    /// [`Config::DEFAULT_MAX_REQUEST_BODY`].
+    #[serde(default)]
+    pub max_request_body: Option<u64>,
+    /// Public host name, optionally with port, used for links and QR
+    /// codes instead of the host in the request. Set this behind a proxy
+    /// that passes on its internal host name.
+    #[serde(default)]
+    pub canonical_host: Option<String>,
+    /// Value of the `Server` response header. Defaults to
+    /// `shorty/<version>`; set to `""` to send no header.
+    #[serde(default, with = "optional_header_value")]
+    pub server_header: Option<http::HeaderValue>,
+    /// Accept a query string on short URL pages and add it to the target
+    /// URL, e.g. for tracking parameters. Without this such requests get
+    /// 400 Bad Request.
+    #[serde(default)]
+    pub forward_query: bool,
+    /// Answer short URLs with 302 Found to the target instead of the
+    /// landing page.
+    #[serde(default)]
+    pub redirect: bool,
+    /// Send visitors to `https` targets stored as `http`.
+    #[serde(default)]
+    pub https_upgrade: bool,
+    /// Path put in front of the path of every target, e.g. `/app` when
+    /// the targets moved under a common prefix.
+    #[serde(default)]
+    pub target_base_path: Option<String>,
+    /// Seconds shared caches may keep a short URL page, also shown on
+    /// the page. Defaults to five minutes.
+    #[serde(default)]
+    pub short_url_max_age: Option<u64>,
+    /// Absolute URL that unknown short names redirect to with 302 Found,
+    /// e.g. the home page of the site. Without it they get 404 Not Found.
+    #[serde(default, with = "optional_url")]
+    pub not_found_redirect: Option<Url>,
+    /// Longest quote on the home page in characters. Longer quotes are
+    /// cut at a word boundary and end with an ellipsis.
+    #[serde(default)]
+    pub max_quote_len: Option<usize>,
+    /// Caching of the home page: `daily` (the default) lets caches keep
+    /// one quote for a day, `private` gives every visitor a fresh quote.
+    #[serde(default)]
+    pub home_cache: HomeCache,
+    /// Serve `/debug/env`, which shows the CGI environment and the parsed
+    /// request. Leave this off in production.
+    #[serde(default)]
+    pub debug_route: bool,
+    /// Serve `/admin/export`, which downloads every short URL as CSV.
+    /// Only turn this on if the web server restricts access to it.
+    #[serde(default)]
+    pub export_route: bool,
+    /// Send a `Server-Timing` header with the time spent in the database
+    /// and rendering, for performance debugging.
+    #[serde(default)]
+    pub server_timing: bool,
+    /// Collapse whitespace and strip comments in HTML responses, see
+    /// [`minify::minify_html`].
+    #[serde(default)]
+    pub minify_html: bool,
+    /// Icon served at `/favicon.ico`. If relative, it will be resolved
+    /// relative to the config file. Without it the route answers 204 No
+    /// Content.
+    #[serde(default)]
+    pub favicon_path: Option<PathBuf>,
+    /// Content of `/robots.txt`. Defaults to
+    /// [`Config::DEFAULT_ROBOTS_TXT`], which asks crawlers to stay away.
+    #[serde(default)]
+    pub robots_txt: Option<String>,
+    /// Tag sentry events with a hash of the requested short URL name
+    /// instead of the name itself. Requires `telemetry_hash_key`.
+    #[serde(default)]
+    pub hash_names_in_telemetry: bool,
+    /// Key for hashing names in telemetry, so the hash of a guessed name
+    /// cannot be compared against the tags.
+    #[serde(default)]
+    pub telemetry_hash_key: Option<HashKey>,
+    /// Request headers added to the `Vary` header of every response, for
+    /// ones a proxy in front negotiates on, e.g. `Accept-Encoding` when
+    /// it compresses responses. Comma separated in the environment.
+    #[serde(default, with = "header_names")]
+    pub vary: Vec<http::HeaderName>,
     #[cfg(feature = "sentry")]
     pub sentry: Option<SentryConfig>,
 }
 
+/// Header names as strings.
+mod header_names {
+    use serde::{Deserialize as _, Serialize as _};
+
+    pub fn serialize<S: serde::Serializer>(
+        names: &[http::HeaderName],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        names
+            .iter()
+            .map(http::HeaderName::as_str)
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<http::HeaderName>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|x| http::HeaderName::try_from(x.as_str()))
+            .collect::<Result<_, _>>()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Secret key for [`HashKey::hash`], written as 32 hex digits, e.g. from
+/// `openssl rand -hex 16`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct HashKey([u8; 16]);
+
+impl HashKey {
+    /// Keyed `SipHash` of `bytes`. Without the key, a hash cannot be
+    /// reproduced from a guess of `bytes`.
+    #[must_use]
+    pub fn hash(&self, bytes: &[u8]) -> u64 {
+        use core::hash::Hasher as _;
+        let mut hasher = siphasher::sip::SipHasher24::new_with_key(&self.0);
+        hasher.write(bytes);
+        hasher.finish()
+    }
+}
+
+impl core::str::FromStr for HashKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 32 || !s.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(anyhow::anyhow!("hash key must be 32 hex digits"));
+        }
+        Ok(Self(u128::from_str_radix(s, 16)?.to_be_bytes()))
+    }
+}
+
+/// Keeps the key out of logs.
+impl core::fmt::Debug for HashKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("HashKey(..)")
+    }
+}
+
+impl serde::Serialize for HashKey {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:032x}", u128::from_be_bytes(self.0)))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for HashKey {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// An optional [`Url`] as a string. Private addresses are allowed, as
+/// the operator picked the URL.
+mod optional_url {
+    use serde::{Deserialize as _, Serialize as _};
+    use shorty::types::{InvalidUrl, Url, UrlPolicy};
+
+    pub fn parse(s: &str) -> Result<Url, InvalidUrl> {
+        Url::parse_with(s, UrlPolicy::PERMISSIVE)
+    }
+
+    #[allow(clippy::ref_option)]
+    pub fn serialize<S: serde::Serializer>(
+        url: &Option<Url>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        url.as_ref().map(ToString::to_string).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Url>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|x| parse(&x))
+            .transpose()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// An optional header value as a string.
+mod optional_header_value {
+    use serde::{Deserialize as _, Serialize as _};
+
+    #[allow(clippy::ref_option)]
+    pub fn serialize<S: serde::Serializer>(
+        value: &Option<http::HeaderValue>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value
+            .as_ref()
+            .map(|x| String::from_utf8_lossy(x.as_bytes()))
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<http::HeaderValue>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(http::HeaderValue::try_from)
+            .transpose()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Read `SHORTY_<FIELD>` from `env`, parsed like a plain TOML value.
+fn env_override<T: serde::de::DeserializeOwned>(
+    env: &impl Environment,
+    field: &str,
+) -> Result<Option<T>, anyhow::Error> {
+    let key = format!("{}{}", Config::ENV_PREFIX, field.to_uppercase());
+    env.var(key.clone())
+        .map(|value| {
+            serde_plain::from_str(&value).map_err(|err| anyhow::anyhow!("Invalid {key}: {err}"))
+        })
+        .transpose()
+}
+
+impl Config {
+    /// Prefix of the environment variables read by [`Config::apply_env`].
+    pub const ENV_PREFIX: &'static str = "SHORTY_";
+
+    /// Request body limit used when `max_request_body` is not set: 64 KiB.
+    pub const DEFAULT_MAX_REQUEST_BODY: u64 = 64 * 1024;
+
+    /// `/robots.txt` used when `robots_txt` is not set.
+    pub const DEFAULT_ROBOTS_TXT: &'static str = "User-agent: *\nDisallow: /\n";
+
+    /// Check settings that depend on each other, after
+    /// [`Config::apply_env`].
+    ///
+    /// # Errors
+    ///
+    /// If `hash_names_in_telemetry` is set without `telemetry_hash_key`,
+    /// or if `canonical_host` is not a valid authority.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.hash_names_in_telemetry && self.telemetry_hash_key.is_none() {
+            return Err(anyhow::anyhow!(
+                "hash_names_in_telemetry requires telemetry_hash_key"
+            ));
+        }
+        if let Some(host) = &self.canonical_host {
+            host.parse::<http::uri::Authority>()
+                .map_err(|err| anyhow::anyhow!("canonical_host {host:?}: {err}"))?;
+        }
+        Ok(())
+    }
+
+    /// The key to hash short URL names in telemetry with, `None` to send
+    /// them as is.
+    #[must_use]
+    pub fn telemetry_name_key(&self) -> Option<&HashKey> {
+        self.telemetry_hash_key
+            .as_ref()
+            .filter(|_| self.hash_names_in_telemetry)
+    }
+
+    /// `uri` with the host replaced by `canonical_host`, if set.
+    ///
+    /// # Errors
+    ///
+    /// If `canonical_host` is not a valid authority.
+    pub fn public_uri(&self, uri: &http::Uri) -> Result<http::Uri, http::Error> {
+        let Some(host) = &self.canonical_host else {
+            return Ok(uri.clone());
+        };
+        let mut builder = http::Uri::builder().authority(host.as_str());
+        if let Some(scheme) = uri.scheme() {
+            builder = builder.scheme(scheme.clone());
+        }
+        if let Some(path_and_query) = uri.path_and_query() {
+            builder = builder.path_and_query(path_and_query.clone());
+        }
+        builder.build()
+    }
+
+    /// The `Server` header to send, `None` if disabled.
+    #[must_use]
+    pub fn server_header(&self) -> Option<http::HeaderValue> {
+        match &self.server_header {
+            // The version is a git description, always a valid value
+            None => http::HeaderValue::try_from(format!("shorty/{VERSION}")).ok(),
+            Some(x) if x.is_empty() => None,
+            Some(x) => Some(x.clone()),
+        }
+    }
+
+    /// The configured request body limit, or the default.
+    #[must_use]
+    pub fn request_body_limit(&self) -> u64 {
+        self.max_request_body
+            .unwrap_or(Self::DEFAULT_MAX_REQUEST_BODY)
+    }
+
+    /// The configured `/robots.txt`, or the default.
+    #[must_use]
+    pub fn robots_txt(&self) -> &str {
+        self.robots_txt
+            .as_deref()
+            .unwrap_or(Self::DEFAULT_ROBOTS_TXT)
+    }
+
+    /// Overlay environment variables on the parsed config; the
+    /// environment wins. Each field `foo` is read from `SHORTY_FOO`, and
+    /// sentry settings from `SHORTY_SENTRY_ENABLED`, `SHORTY_SENTRY_DSN`
+    /// and `SHORTY_SENTRY_DEBUG`.
+    ///
+    /// # Errors
+    ///
+    /// If a variable cannot be parsed, or sentry settings are given
+    /// without a DSN.
+    #[allow(clippy::too_many_lines)]
+    pub fn apply_env(&mut self, env: &impl Environment) -> Result<(), anyhow::Error> {
+        if let Some(x) = env_override(env, "database_file")? {
+            self.database_file = x;
+        }
+        if let Some(x) = env_override(env, "request_timeout_ms")? {
+            self.request_timeout_ms = Some(x);
+        }
+        if let Some(x) = env_override(env, "qr_ec_level")? {
+            self.qr_ec_level = x;
+        }
+        if let Some(x) = env_override(env, "qr_relative_title")? {
+            self.qr_relative_title = x;
+        }
+        if let Some(x) = env_override(env, "weak_etag")? {
+            self.weak_etag = x;
+        }
+        if let Some(x) = env_override(env, "no_etag")? {
+            self.no_etag = x;
+        }
+        if let Some(x) = env_override(env, "max_request_body")? {
+            self.max_request_body = Some(x);
+        }
+        if let Some(x) = env_override(env, "canonical_host")? {
+            self.canonical_host = Some(x);
+        }
+        if let Some(x) = env_override::<String>(env, "server_header")? {
+            self.server_header = Some(
+                http::HeaderValue::try_from(x)
+                    .map_err(|err| anyhow::anyhow!("Invalid SHORTY_SERVER_HEADER: {err}"))?,
+            );
+        }
+        if let Some(x) = env_override(env, "forward_query")? {
+            self.forward_query = x;
+        }
+        if let Some(x) = env_override(env, "redirect")? {
+            self.redirect = x;
+        }
+        if let Some(x) = env_override(env, "https_upgrade")? {
+            self.https_upgrade = x;
+        }
+        if let Some(x) = env_override(env, "target_base_path")? {
+            self.target_base_path = Some(x);
+        }
+        if let Some(x) = env_override(env, "short_url_max_age")? {
+            self.short_url_max_age = Some(x);
+        }
+        if let Some(x) = env_override::<String>(env, "not_found_redirect")? {
+            let url = optional_url::parse(&x)
+                .map_err(|err| anyhow::anyhow!("Invalid SHORTY_NOT_FOUND_REDIRECT: {err}"))?;
+            self.not_found_redirect = Some(url);
+        }
+        if let Some(x) = env_override(env, "max_quote_len")? {
+            self.max_quote_len = Some(x);
+        }
+        if let Some(x) = env_override(env, "home_cache")? {
+            self.home_cache = x;
+        }
+        if let Some(x) = env_override(env, "debug_route")? {
+            self.debug_route = x;
+        }
+        if let Some(x) = env_override(env, "export_route")? {
+            self.export_route = x;
+        }
+        if let Some(x) = env_override(env, "server_timing")? {
+            self.server_timing = x;
+        }
+        if let Some(x) = env_override(env, "minify_html")? {
+            self.minify_html = x;
+        }
+        if let Some(x) = env_override(env, "favicon_path")? {
+            self.favicon_path = Some(x);
+        }
+        if let Some(x) = env_override(env, "robots_txt")? {
+            self.robots_txt = Some(x);
+        }
+        if let Some(x) = env_override(env, "hash_names_in_telemetry")? {
+            self.hash_names_in_telemetry = x;
+        }
+        if let Some(x) = env_override(env, "telemetry_hash_key")? {
+            self.telemetry_hash_key = Some(x);
+        }
+        if let Some(x) = env_override::<String>(env, "vary")? {
+            self.vary = x
+                .split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(http::HeaderName::try_from)
+                .collect::<Result<_, _>>()
+                .map_err(|err| anyhow::anyhow!("Invalid SHORTY_VARY: {err}"))?;
+        }
+        #[cfg(feature = "sentry")]
+        {
+            let dsn = env_override(env, "sentry_dsn")?;
+            let enabled = env_override(env, "sentry_enabled")?;
+            let debug = env_override(env, "sentry_debug")?;
+            if dsn.is_some() || enabled.is_some() || debug.is_some() {
+                let sentry = match (self.sentry.take(), dsn) {
+                    (Some(sentry), dsn) => SentryConfig {
+                        dsn: dsn.unwrap_or(sentry.dsn),
+                        ..sentry
+                    },
+                    (None, Some(dsn)) => SentryConfig {
+                        enabled: false,
+                        dsn,
+                        debug: false,
+                    },
+                    (None, None) => {
+                        return Err(anyhow::anyhow!(
+                            "{}SENTRY_DSN is required to configure sentry",
+                            Self::ENV_PREFIX
+                        ))
+                    }
+                };
+                self.sentry = Some(SentryConfig {
+                    enabled: enabled.unwrap_or(sentry.enabled),
+                    debug: debug.unwrap_or(sentry.debug),
+                    ..sentry
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
 #[inline]
 fn serialize_headers(
     headers: &http::HeaderMap,
@@ -52,9 +522,88 @@ impl From<std::io::Error> for SerializeError {
     }
 }
 
-fn create_etag(bytes: &[u8]) -> headers::ETag {
+/// How [`serialize_response_with`] completes a response.
+#[derive(Debug, Clone, Default)]
+pub struct SerializeOptions {
+    /// Generate weak `ETag`s. A weak `ETag` only promises semantic
+    /// equivalence, so caches may reuse it across encodings.
+    pub weak_etag: bool,
+    /// Don't add an `ETag` to responses without one.
+    pub no_etag: bool,
+    /// The request's `If-None-Match`. A 200 response whose `ETag`
+    /// matches is sent as 304 without a body.
+    pub if_none_match: Option<headers::IfNoneMatch>,
+    /// The request's `If-Modified-Since`. Only used without
+    /// `If-None-Match`: a 200 response whose `Last-Modified` is not newer
+    /// is sent as 304 without a body.
+    pub if_modified_since: Option<headers::IfModifiedSince>,
+    /// Added as the `Server` header unless the response has one.
+    pub server_header: Option<http::HeaderValue>,
+    /// Sent as the `Server-Timing` header.
+    pub server_timing: Option<timing::Timings>,
+    /// Added to the `Vary` header of every response, see [`add_vary`].
+    pub vary: Vec<http::HeaderName>,
+}
+
+impl SerializeOptions {
+    /// Take the conditional request headers from `request`.
+    #[must_use]
+    pub fn with_conditional<T>(self, request: &http::Request<T>) -> Self {
+        Self {
+            if_none_match: request.headers().typed_get(),
+            if_modified_since: request.headers().typed_get(),
+            ..self
+        }
+    }
+
+    fn is_not_modified(&self, headers: &http::HeaderMap) -> bool {
+        is_not_modified(
+            self.if_none_match.as_ref(),
+            self.if_modified_since.as_ref(),
+            headers,
+        )
+    }
+}
+
+/// Whether a 200 response with `headers` is fresh in the client's
+/// cache, given the request's validators. `If-Modified-Since` is only
+/// used without `If-None-Match`, see RFC 9110 section 13.2.2.
+#[must_use]
+pub fn is_not_modified(
+    if_none_match: Option<&headers::IfNoneMatch>,
+    if_modified_since: Option<&headers::IfModifiedSince>,
+    headers: &http::HeaderMap,
+) -> bool {
+    if let Some(if_none_match) = if_none_match {
+        return headers
+            .typed_get::<headers::ETag>()
+            .is_some_and(|etag| !if_none_match.precondition_passes(&etag));
+    }
+    match (
+        if_modified_since,
+        headers.typed_get::<headers::LastModified>(),
+    ) {
+        (Some(since), Some(last_modified)) => !since.is_modified(last_modified.into()),
+        _ => false,
+    }
+}
+
+impl From<&Config> for SerializeOptions {
+    fn from(config: &Config) -> Self {
+        Self {
+            weak_etag: config.weak_etag,
+            no_etag: config.no_etag,
+            server_header: config.server_header(),
+            vary: config.vary.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+fn create_etag(bytes: &[u8], weak: bool) -> headers::ETag {
     use xxhash_rust::xxh3::xxh3_64;
-    let etag = format!("\"{:x}\"", xxh3_64(bytes));
+    let prefix = if weak { "W/" } else { "" };
+    let etag = format!("{prefix}\"{:x}\"", xxh3_64(bytes));
     etag.parse::<headers::ETag>()
         .expect("Failed to create ETag")
 }
@@ -64,17 +613,60 @@ pub fn serialize_response<T>(
     response: http::Response<T>,
     out: &mut impl std::io::Write,
 ) -> Result<(), SerializeError>
+where
+    T: AsRef<[u8]>,
+{
+    serialize_response_with(response, out, &SerializeOptions::default()).map(|_| ())
+}
+
+/// Write `response` to `out` as a CGI response. Returns the status that
+/// was written, which is 304 if `options` found the client's copy fresh.
+#[allow(clippy::missing_errors_doc)]
+pub fn serialize_response_with<T>(
+    response: http::Response<T>,
+    out: &mut impl std::io::Write,
+    options: &SerializeOptions,
+) -> Result<StatusCode, SerializeError>
 where
     T: AsRef<[u8]>,
 {
     let mut response = response;
+    if response.status() == StatusCode::OK {
+        if !options.no_etag && !response.headers().contains_key(headers::ETag::name()) {
+            let etag = create_etag(response.body().as_ref(), options.weak_etag);
+            response.headers_mut().typed_insert(etag);
+        }
+        if options.is_not_modified(response.headers()) {
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+        }
+    }
     let status = response.status();
     let is_empty_response = status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED;
-    if (status == StatusCode::OK || status == StatusCode::NOT_FOUND)
-        && !response.headers().contains_key(headers::ETag::name())
+    // Errors may be transient, e.g. a 404 until the name is created, so
+    // shared caches must not keep them.
+    if (status.is_client_error() || status.is_server_error())
+        && !response
+            .headers()
+            .contains_key(headers::CacheControl::name())
     {
-        let etag = create_etag(response.body().as_ref());
-        response.headers_mut().typed_insert(etag);
+        response
+            .headers_mut()
+            .typed_insert(headers::CacheControl::new().with_no_store());
+    }
+    for name in &options.vary {
+        add_vary(&mut response, name);
+    }
+    if let Some(timings) = &options.server_timing {
+        response
+            .headers_mut()
+            .insert("server-timing", timings.header_value());
+    }
+    if let Some(server) = &options.server_header {
+        if !response.headers().contains_key(http::header::SERVER) {
+            response
+                .headers_mut()
+                .insert(http::header::SERVER, server.clone());
+        }
     }
     write!(out, "Status: {}\r\n", response.status())?;
     response
@@ -98,7 +690,33 @@ where
     }
     out.flush()?;
 
-    Ok(())
+    Ok(status)
+}
+
+/// Add `name` to the `Vary` header of `response`, unless it is there
+/// already, for a response chosen by that request header. Without it a
+/// shared cache may serve one representation to every client.
+pub fn add_vary<T>(response: &mut http::Response<T>, name: &http::HeaderName) {
+    let mut names: Vec<String> = response
+        .headers()
+        .get_all(http::header::VARY)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(','))
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect();
+    if names
+        .iter()
+        .any(|x| x == "*" || x.eq_ignore_ascii_case(name.as_str()))
+    {
+        return;
+    }
+    names.push(name.to_string());
+    response.headers_mut().insert(
+        http::header::VARY,
+        names.join(", ").try_into().expect("Failed to create Vary"),
+    );
 }
 
 #[must_use]
@@ -123,6 +741,44 @@ pub fn response<T: AsRef<str>>(
     response
 }
 
+/// File name for an export downloaded at `now`, e.g.
+/// `shorty-export-2024-01-01.csv`.
+#[must_use]
+pub fn export_filename(now: SystemTime, extension: &str) -> String {
+    let secs = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    let date = shorty::types::UnixTimestamp(secs)
+        .iso8601()
+        .and_then(|x| x.get(..10).map(str::to_string))
+        .unwrap_or_default();
+    format!("shorty-export-{date}.{extension}")
+}
+
+/// Ask the browser to save `response` as `filename` instead of showing it.
+///
+/// Characters outside `[A-Za-z0-9._-]` are replaced with `_`, so the
+/// name can be sent unquoted and never escapes the download directory.
+pub fn set_attachment<T>(response: &mut http::Response<T>, filename: &str) {
+    let filename: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let filename = filename.trim_start_matches('.');
+    response.headers_mut().insert(
+        http::header::CONTENT_DISPOSITION,
+        format!("attachment; filename={filename}")
+            .try_into()
+            .expect("Failed to create Content-Disposition"),
+    );
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -165,6 +821,69 @@ mod test {
         assert!(out.contains("\r\n\r\nHello, world!"));
     }
 
+    #[test]
+    fn test_serialize_response_no_etag() {
+        let options = SerializeOptions {
+            no_etag: true,
+            ..Default::default()
+        };
+        let response = Response::builder().body("Hello, world!").unwrap();
+        let out: &mut Vec<_> = &mut Vec::new();
+        serialize_response_with(response, out, &options).unwrap();
+        let out = String::from_utf8(out.to_owned()).unwrap();
+        assert!(out.contains("Status: 200 OK\r\n"));
+        assert!(!out.contains("\r\netag: "));
+
+        let response = Response::builder()
+            .header(headers::ETag::name(), "\"d'oh\"")
+            .body("Hello, world!")
+            .unwrap();
+        let out: &mut Vec<_> = &mut Vec::new();
+        serialize_response_with(response, out, &options).unwrap();
+        let out = String::from_utf8(out.to_owned()).unwrap();
+        assert!(out.contains("\r\netag: \"d'oh\"\r\n"));
+    }
+
+    #[test]
+    fn test_add_vary() {
+        let mut response = Response::builder().body("").unwrap();
+        add_vary(&mut response, &http::header::ACCEPT);
+        add_vary(&mut response, &http::header::ACCEPT_ENCODING);
+        add_vary(&mut response, &http::header::ACCEPT);
+        assert_eq!(response.headers()["vary"], "accept, accept-encoding");
+
+        let mut response = Response::builder()
+            .header("vary", "Accept-Language")
+            .header("vary", "Accept")
+            .body("")
+            .unwrap();
+        add_vary(&mut response, &http::header::ACCEPT);
+        assert_eq!(response.headers().get_all("vary").iter().count(), 2);
+
+        let mut response = Response::builder().header("vary", "*").body("").unwrap();
+        add_vary(&mut response, &http::header::ACCEPT);
+        assert_eq!(response.headers()["vary"], "*");
+    }
+
+    #[test]
+    fn test_serialize_response_vary() {
+        let options = SerializeOptions {
+            vary: vec![http::header::ACCEPT_ENCODING],
+            ..Default::default()
+        };
+        let response = Response::builder()
+            .header("vary", "Accept")
+            .body("Hello, world!")
+            .unwrap();
+        let out: &mut Vec<_> = &mut Vec::new();
+        serialize_response_with(response, out, &options).unwrap();
+        let out = String::from_utf8(out.to_owned()).unwrap();
+        assert!(
+            out.contains("\r\nvary: Accept, accept-encoding\r\n"),
+            "{out}"
+        );
+    }
+
     fn test_serialize_response_no_content(status: StatusCode) {
         let body = "Hello, world!";
         let response = Response::builder().status(status).body(body).unwrap();
@@ -186,8 +905,371 @@ mod test {
         test_serialize_response_no_content(StatusCode::NOT_MODIFIED);
     }
 
+    #[test]
+    fn test_serialize_response_error_no_store() {
+        let response = Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Not found")
+            .unwrap();
+        let out: &mut Vec<_> = &mut Vec::new();
+        serialize_response(response, out).unwrap();
+        let out = String::from_utf8(out.to_owned()).unwrap();
+        assert!(out.contains("Status: 404 Not Found\r\n"));
+        assert!(!out.contains("\r\netag: "));
+        assert!(out.contains("\r\ncache-control: no-store\r\n"));
+    }
+
     #[test]
     fn test_create_etag() {
-        create_etag(b"abc");
+        create_etag(b"abc", false);
+    }
+
+    #[test]
+    fn test_serialize_response_weak_etag() {
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .body("Hello, world!")
+            .unwrap();
+        let out: &mut Vec<_> = &mut Vec::new();
+        let options = SerializeOptions {
+            weak_etag: true,
+            ..Default::default()
+        };
+        serialize_response_with(response, out, &options).unwrap();
+        let out = String::from_utf8(out.to_owned()).unwrap();
+        assert!(out.contains("\r\netag: W/\""));
+    }
+
+    fn serialize_conditional(request: &http::Request<()>, response: Response<&str>) -> String {
+        let options = SerializeOptions::default().with_conditional(request);
+        let out: &mut Vec<_> = &mut Vec::new();
+        serialize_response_with(response, out, &options).unwrap();
+        String::from_utf8(out.to_owned()).unwrap()
+    }
+
+    #[test]
+    fn test_serialize_response_if_none_match() {
+        let ok = || Response::new("Hello, world!");
+        let etag = create_etag(b"Hello, world!", false);
+        let mut request = http::Request::new(());
+        request
+            .headers_mut()
+            .typed_insert(headers::IfNoneMatch::from(etag));
+
+        let out = serialize_conditional(&request, ok());
+        assert!(out.starts_with("Status: 304 Not Modified\r\n"));
+        assert!(out.contains("\r\netag: "));
+        assert!(!out.contains("content-length"));
+        assert!(!out.contains("Hello"));
+
+        // Weak comparison: a weak validator matches the strong ETag
+        let mut request = http::Request::new(());
+        request.headers_mut().insert(
+            http::header::IF_NONE_MATCH,
+            format!("W/\"{:x}\"", xxhash_rust::xxh3::xxh3_64(b"Hello, world!"))
+                .parse()
+                .unwrap(),
+        );
+        let out = serialize_conditional(&request, ok());
+        assert!(out.starts_with("Status: 304 Not Modified\r\n"));
+
+        let mut request = http::Request::new(());
+        request
+            .headers_mut()
+            .insert(http::header::IF_NONE_MATCH, "\"other\"".parse().unwrap());
+        let out = serialize_conditional(&request, ok());
+        assert!(out.starts_with("Status: 200 OK\r\n"));
+        assert!(out.ends_with("\r\n\r\nHello, world!"));
+    }
+
+    #[test]
+    fn test_serialize_response_if_modified_since() {
+        let modified = SystemTime::UNIX_EPOCH + core::time::Duration::from_secs(1_000_000_000);
+        let ok = || {
+            let mut response = Response::new("Hello, world!");
+            response
+                .headers_mut()
+                .typed_insert(headers::LastModified::from(modified));
+            response
+        };
+        let request_since = |since: SystemTime| {
+            let mut request = http::Request::new(());
+            request
+                .headers_mut()
+                .typed_insert(headers::IfModifiedSince::from(since));
+            request
+        };
+
+        let out = serialize_conditional(&request_since(modified), ok());
+        assert!(out.starts_with("Status: 304 Not Modified\r\n"));
+        assert!(!out.contains("Hello"));
+
+        let earlier = modified - core::time::Duration::from_secs(1);
+        let out = serialize_conditional(&request_since(earlier), ok());
+        assert!(out.starts_with("Status: 200 OK\r\n"));
+
+        // If-None-Match wins over If-Modified-Since
+        let mut request = request_since(modified);
+        request
+            .headers_mut()
+            .insert(http::header::IF_NONE_MATCH, "\"other\"".parse().unwrap());
+        let out = serialize_conditional(&request, ok());
+        assert!(out.starts_with("Status: 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_serialize_response_conditional_error() {
+        let mut request = http::Request::new(());
+        request
+            .headers_mut()
+            .typed_insert(headers::IfNoneMatch::any());
+        let response = Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Not found")
+            .unwrap();
+        let out = serialize_conditional(&request, response);
+        assert!(out.starts_with("Status: 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_serialize_response_server_timing() {
+        let out: &mut Vec<_> = &mut Vec::new();
+        serialize_response(Response::new("Hello"), out).unwrap();
+        assert!(!String::from_utf8(out.to_owned())
+            .unwrap()
+            .contains("server-timing:"));
+
+        let options = SerializeOptions {
+            server_timing: Some(timing::Timings::default()),
+            ..Default::default()
+        };
+        let out: &mut Vec<_> = &mut Vec::new();
+        serialize_response_with(Response::new("Hello"), out, &options).unwrap();
+        assert!(String::from_utf8(out.to_owned())
+            .unwrap()
+            .contains("\r\nserver-timing: db;dur=0.000, render;dur=0.000\r\n"));
+    }
+
+    #[test]
+    fn test_serialize_response_server_header() {
+        let serialize = |config: &Config| {
+            let out: &mut Vec<_> = &mut Vec::new();
+            serialize_response_with(Response::new("Hello"), out, &config.into()).unwrap();
+            String::from_utf8(out.to_owned()).unwrap()
+        };
+
+        let mut config = Config::default();
+        assert!(serialize(&config).contains(&format!("\r\nserver: shorty/{VERSION}\r\n")));
+
+        config.server_header = Some(http::HeaderValue::from_static("sho.rt"));
+        assert!(serialize(&config).contains("\r\nserver: sho.rt\r\n"));
+
+        config.server_header = Some(http::HeaderValue::from_static(""));
+        assert!(!serialize(&config).contains("server:"));
+
+        // Not added by plain serialize_response
+        let out: &mut Vec<_> = &mut Vec::new();
+        serialize_response(Response::new("Hello"), out).unwrap();
+        assert!(!String::from_utf8(out.to_owned())
+            .unwrap()
+            .contains("server:"));
+    }
+
+    struct MapEnvironment(Vec<(&'static str, &'static str)>);
+
+    impl Environment for MapEnvironment {
+        fn vars(&self) -> impl Iterator<Item = (std::ffi::OsString, std::ffi::OsString)> {
+            self.0.iter().map(|(k, v)| (k.into(), v.into()))
+        }
+
+        fn var(&self, key: String) -> Option<String> {
+            self.0
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| (*v).to_string())
+        }
+    }
+
+    #[test]
+    fn test_config_apply_env() {
+        let mut config: Config = toml::from_str(
+            "database_file = \"/from/toml.db\"\nrequest_timeout_ms = 100\nweak_etag = false\n",
+        )
+        .unwrap();
+        config
+            .apply_env(&MapEnvironment(vec![
+                ("SHORTY_DATABASE_FILE", "/from/env.db"),
+                ("SHORTY_WEAK_ETAG", "true"),
+                ("SHORTY_QR_EC_LEVEL", "H"),
+                ("SHORTY_VARY", "Accept-Encoding, Accept-Language"),
+            ]))
+            .unwrap();
+        assert_eq!(config.database_file, PathBuf::from("/from/env.db"));
+        assert_eq!(config.request_timeout_ms, Some(100));
+        assert!(config.weak_etag);
+        assert_eq!(config.qr_ec_level, QrEcLevel::H);
+        assert_eq!(
+            config.vary,
+            [http::header::ACCEPT_ENCODING, http::header::ACCEPT_LANGUAGE]
+        );
+
+        let err = config
+            .apply_env(&MapEnvironment(vec![("SHORTY_VARY", "Accept, Bad Name")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("SHORTY_VARY"));
+        assert!(toml::from_str::<Config>("database_file = \"x.db\"\nvary = [\"a b\"]").is_err());
+
+        let err = config
+            .apply_env(&MapEnvironment(vec![("SHORTY_SERVER_HEADER", "a\nb")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("SHORTY_SERVER_HEADER"));
+        assert!(toml::from_str::<Config>(
+            "database_file = \"x.db\"\nserver_header = \"a\\u0000b\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_config_public_uri() {
+        let uri = http::Uri::from_static("http://internal:8080/s/abc?x=1");
+        let mut config = Config::default();
+        assert_eq!(config.public_uri(&uri).unwrap(), uri);
+
+        config.canonical_host = Some("sho.rt".to_string());
+        assert_eq!(
+            config.public_uri(&uri).unwrap(),
+            http::Uri::from_static("http://sho.rt/s/abc?x=1")
+        );
+
+        config.canonical_host = Some("not a host".to_string());
+        assert!(config.public_uri(&uri).is_err());
+    }
+
+    #[test]
+    fn test_config_canonical_host_validate() {
+        let mut config = Config::default();
+        config
+            .apply_env(&MapEnvironment(vec![(
+                "SHORTY_CANONICAL_HOST",
+                "sho.rt:8443",
+            )]))
+            .unwrap();
+        config.validate().unwrap();
+
+        config
+            .apply_env(&MapEnvironment(vec![(
+                "SHORTY_CANONICAL_HOST",
+                "not a host",
+            )]))
+            .unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("canonical_host"));
+    }
+
+    #[test]
+    fn test_config_not_found_redirect() {
+        let parse =
+            |toml: &str| toml::from_str::<Config>(&format!("database_file = \"x.db\"\n{toml}"));
+        assert_eq!(parse("").unwrap().not_found_redirect, None);
+
+        let config = parse("not_found_redirect = \"http://10.0.0.1/\"").unwrap();
+        assert_eq!(
+            config.not_found_redirect.unwrap().to_string(),
+            "http://10.0.0.1/"
+        );
+        assert!(parse("not_found_redirect = \"/relative\"").is_err());
+
+        let mut config = Config::default();
+        let err = config
+            .apply_env(&MapEnvironment(vec![("SHORTY_NOT_FOUND_REDIRECT", "nope")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("SHORTY_NOT_FOUND_REDIRECT"));
+    }
+
+    #[test]
+    fn test_config_apply_env_invalid() {
+        let mut config = Config::default();
+        let err = config
+            .apply_env(&MapEnvironment(vec![("SHORTY_REQUEST_TIMEOUT_MS", "soon")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("SHORTY_REQUEST_TIMEOUT_MS"));
+    }
+
+    #[test]
+    fn test_config_telemetry_hash_key() {
+        let mut config = Config {
+            hash_names_in_telemetry: true,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let key = "000102030405060708090A0B0C0D0E0F";
+        config
+            .apply_env(&MapEnvironment(vec![("SHORTY_TELEMETRY_HASH_KEY", key)]))
+            .unwrap();
+        config.validate().unwrap();
+        assert!(config.telemetry_name_key().is_some());
+        assert_eq!(format!("{config:?}").matches("0A0B").count(), 0);
+
+        config.hash_names_in_telemetry = false;
+        assert!(config.telemetry_name_key().is_none());
+
+        for key in [
+            "",
+            "0001",
+            "+00102030405060708090a0b0c0d0e0f",
+            "x00102030405060708090a0b0c0d0e0f",
+        ] {
+            assert!(key.parse::<HashKey>().is_err(), "{key}");
+        }
+    }
+
+    #[cfg(feature = "sentry")]
+    #[test]
+    fn test_config_apply_env_sentry() {
+        let mut config = Config::default();
+        assert!(config
+            .apply_env(&MapEnvironment(vec![("SHORTY_SENTRY_ENABLED", "true")]))
+            .is_err());
+
+        config
+            .apply_env(&MapEnvironment(vec![
+                ("SHORTY_SENTRY_ENABLED", "true"),
+                ("SHORTY_SENTRY_DSN", "https://key@example.com/1"),
+            ]))
+            .unwrap();
+        let sentry = config.sentry.unwrap();
+        assert!(sentry.enabled);
+        assert!(!sentry.debug);
+        assert_eq!(sentry.dsn.public_key(), "key");
+    }
+
+    #[test]
+    fn test_export_filename() {
+        let now = SystemTime::UNIX_EPOCH + core::time::Duration::from_hours(473_352);
+        assert_eq!(export_filename(now, "csv"), "shorty-export-2024-01-01.csv");
+    }
+
+    #[test]
+    fn test_set_attachment() {
+        let mut response = text_response(StatusCode::OK, "a,b\r\n");
+        set_attachment(&mut response, &export_filename(SystemTime::now(), "csv"));
+        let value = response
+            .headers()
+            .get(http::header::CONTENT_DISPOSITION)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(value.starts_with("attachment; filename=shorty-export-20"));
+        assert_eq!(value.rsplit('.').next(), Some("csv"));
+
+        set_attachment(&mut response, "../\"evil\";\r\n.csv");
+        assert_eq!(
+            response
+                .headers()
+                .get(http::header::CONTENT_DISPOSITION)
+                .unwrap(),
+            "attachment; filename=__evil____.csv"
+        );
     }
 }
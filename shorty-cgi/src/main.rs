@@ -1,23 +1,31 @@
 use anyhow::anyhow;
-use cgi::cgi_env::{CgiEnv, Environment, MetaVariableKind, OsEnvironment, PathInfo};
+use cgi::access_log::{LogSink, RequestLog, StderrSink};
+use cgi::cgi_env::{
+    CgiEnv, CgiEnvError, Environment, MetaVariableKind, OsEnvironment, PathInfo,
+    RequestEnvironment, Tenant,
+};
 use cgi::controller::{
-    Controller, ErrorController, QuotationController, ShortUrlController, ShortUrlControllerParams,
+    not_found_response, Controller, ErrorController, QuotationController, QuotationOptions,
+    RandomUrlController, ShortUrlController, ShortUrlControllerParams, ShortUrlOptions,
 };
 #[cfg(all(feature = "sentry", not(test)))]
 use cgi::sentry::SentryConfig;
 use cgi::Config;
-use cgi::{serialize_response, text_response};
+use cgi::{serialize_response, serialize_response_with, text_response, timing, SerializeOptions};
 use core::fmt;
 use core::str::FromStr;
+use core::time::Duration;
+use headers::HeaderMapExt as _;
 use http::StatusCode;
 use matchit::{Match, MatchError, Router};
 use shorty::anyhow;
 use shorty::repository::{
-    sqlite::{open_readonly_repository, open_writable_repository},
-    Repository, WritableRepository,
+    sqlite::{open_readonly_repository_with_deadline, open_writable_repository},
+    MigrationState, Repository, RepositoryError, WritableRepository,
 };
-use shorty::types::ShortUrlName;
+use shorty::types::{ShortUrlName, UnixTimestamp};
 use std::sync::Once;
+use std::time::SystemTime;
 use std::{env, fs, path::Path};
 
 const SHORT_URL_PARAM: &str = "short_url";
@@ -26,22 +34,41 @@ const SHORT_URL_PARAM: &str = "short_url";
 enum Route {
     Home,
     ShortUrl,
-    #[cfg(debug_assertions)]
+    Random,
     Debug,
     ErrorDocument,
+    Favicon,
+    Robots,
+    Export,
+}
+
+impl Route {
+    /// Name used in the access log.
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Home => "home",
+            Self::ShortUrl => "short_url",
+            Self::Random => "random",
+            Self::Debug => "debug",
+            Self::ErrorDocument => "error_document",
+            Self::Favicon => "favicon",
+            Self::Robots => "robots",
+            Self::Export => "export",
+        }
+    }
 }
 
 fn main() -> Result<(), Box<dyn core::error::Error>> {
     let args: Vec<_> = env::args_os().collect();
     if !matches!(args.len(), 2 | 3) {
-        eprintln!("Usage: shorty [--migrate] config.toml");
+        eprintln!("Usage: shorty [--migrate | serve-cgi] config.toml");
         return Err("Missing config file argument".into());
     }
     let exe_path = args.last().ok_or("Exe path not found")?;
     let exe_path = fs::canonicalize(exe_path)?;
     let config = read_config(exe_path)?;
 
-    let cgi_env = &CgiEnv::new(OsEnvironment);
+    let cgi_env = &with_request_hooks(OsEnvironment);
     if cgi_env.is_cgi() {
         #[cfg(all(feature = "sentry", not(test)))]
         let _guard = match &config.sentry {
@@ -60,15 +87,24 @@ fn main() -> Result<(), Box<dyn core::error::Error>> {
             ))),
             _ => None,
         };
-        cgi_main(&config, cgi_env);
+        cgi_main(&config, cgi_env, &StderrSink);
     } else if args.len() == 3 && args[1] == *"--migrate" {
         run_migrations(config.database_file)?;
+    } else if args.len() == 3 && args[1] == *"serve-cgi" {
+        // Run one request from stdin like the web server would
+        let env = RequestEnvironment::from_http_request(std::io::stdin().lock())?;
+        cgi_main(&config, &with_request_hooks(env), &StderrSink);
     } else {
         return Err("Unknown command".into());
     }
     Ok(())
 }
 
+/// `env` with the request hooks the handlers rely on.
+fn with_request_hooks<E: Environment>(env: E) -> CgiEnv<E> {
+    CgiEnv::new(env).with_request_hook(Tenant::from_header)
+}
+
 fn read_config<P: AsRef<Path>>(path: P) -> Result<Config, anyhow::Error> {
     let path = path.as_ref();
     if path.is_relative() {
@@ -77,12 +113,20 @@ fn read_config<P: AsRef<Path>>(path: P) -> Result<Config, anyhow::Error> {
     let content = fs::read_to_string(path)?;
     let config_start = content.lines().skip(1).collect::<Vec<_>>().join("\n");
     let mut config: Config = toml::from_str(&config_start)?;
+    config.apply_env(&OsEnvironment)?;
+    config.validate()?;
     if config.database_file.is_relative() {
         config.database_file = path
             .parent()
             .ok_or_else(|| anyhow::anyhow!("Failed to get parent directory"))?
             .join(config.database_file);
     }
+    if let Some(favicon_path) = config.favicon_path.as_mut().filter(|x| x.is_relative()) {
+        *favicon_path = path
+            .parent()
+            .ok_or_else(|| anyhow::anyhow!("Failed to get parent directory"))?
+            .join(&favicon_path);
+    }
     Ok(config)
 }
 
@@ -118,61 +162,299 @@ fn setup_cgi() {
     });
 }
 
-fn cgi_main<T: fmt::Debug + Environment>(config: &Config, cgi_env: &CgiEnv<T>) {
+fn cgi_main<T: fmt::Debug + Environment>(
+    config: &Config,
+    cgi_env: &CgiEnv<T>,
+    log_sink: &impl LogSink,
+) {
     setup_cgi();
+    let started = SystemTime::now();
+    // From the start of the request, not from when the database opens
+    let deadline = config
+        .request_timeout_ms
+        .map(|ms| started + Duration::from_millis(ms));
     let mut out = std::io::stdout().lock();
+    let mut options = SerializeOptions::from(config);
+    if let Ok(request) = cgi_env.new_request() {
+        options = options.with_conditional(&request);
+    }
 
+    let router = routes(config);
+    let path_info = cgi_env
+        .getenv(MetaVariableKind::PathInfo)
+        .unwrap_or_default();
+    let route = router
+        .as_ref()
+        .ok()
+        .and_then(|router| router.at(&path_info).ok())
+        .map(|x| *x.value);
     #[allow(clippy::unwrap_used)]
-    match run(config, cgi_env) {
-        Ok(response) => {
-            serialize_response(response, &mut out).unwrap();
-        }
+    let response = match router.and_then(|router| handle(config, cgi_env, &router, deadline)) {
+        Ok(response) => response,
         Err(err) => {
             #[cfg(feature = "sentry")]
             sentry::integrations::anyhow::capture_anyhow(&err);
-            serialize_response(
-                ErrorController {}
-                    .respond((StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#?}")))
-                    .unwrap(),
-                &mut out,
-            )
-            .unwrap();
+            let (status_code, details) = match err.downcast_ref::<RepositoryError>() {
+                Some(RepositoryError::Interrupted | RepositoryError::Locked) => {
+                    (StatusCode::SERVICE_UNAVAILABLE, format!("{err:#?}"))
+                }
+                Some(RepositoryError::Migration) => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Service not initialized".to_string(),
+                ),
+                Some(RepositoryError::SchemaAhead) => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Service being upgraded".to_string(),
+                ),
+                None => match err.downcast_ref::<CgiEnvError>() {
+                    Some(cgi_err @ CgiEnvError::BodyTooLarge(_)) => {
+                        (cgi_err.status_code(), String::new())
+                    }
+                    _ => (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#?}")),
+                },
+            };
+            ErrorController {}
+                .respond((status_code, details))
+                .unwrap()
+                .map(String::into_bytes)
         }
+    };
+    let response = if config.minify_html {
+        cgi::minify::minify_response(response)
+    } else {
+        response
+    };
+    if config.server_timing {
+        options.server_timing = Some(timing::take());
     }
+    #[allow(clippy::unwrap_used)]
+    let status = serialize_response_with(response, &mut out, &options).unwrap();
+    log_sink.log(&request_log(cgi_env, started, route, status));
 }
 
-fn run<T: fmt::Debug + Environment>(
-    config: &Config,
+fn request_log<T: Environment>(
     cgi_env: &CgiEnv<T>,
-) -> Result<http::Response<String>, anyhow::Error> {
+    started: SystemTime,
+    route: Option<Route>,
+    status: StatusCode,
+) -> RequestLog {
+    let timestamp = started
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .and_then(|x| UnixTimestamp(x.as_secs()).iso8601())
+        .unwrap_or_default();
+    let duration_ms = started
+        .elapsed()
+        .map_or(0, |x| x.as_millis().try_into().unwrap_or(u64::MAX));
+    let request_uri = cgi_env
+        .getenv(MetaVariableKind::RequestUri)
+        .unwrap_or_default();
+    let path = request_uri
+        .split_once('?')
+        .map_or(request_uri.as_str(), |(path, _)| path);
+    RequestLog {
+        timestamp,
+        method: cgi_env
+            .getenv(MetaVariableKind::RequestMethod)
+            .unwrap_or_default(),
+        path: path.to_string(),
+        route: route.map(Route::name),
+        status: status.as_u16(),
+        duration_ms,
+        request_id: cgi_env.getenv(MetaVariableKind::UniqueId),
+    }
+}
+
+fn routes(config: &Config) -> Result<Router<Route>, anyhow::Error> {
     let mut router = Router::new();
     router.insert(format!("/{{{SHORT_URL_PARAM}}}"), Route::ShortUrl)?;
     router.insert("/", Route::Home)?;
     router.insert("", Route::Home)?;
     router.insert("/error/doc", Route::ErrorDocument)?;
-    #[cfg(debug_assertions)]
-    router.insert("/debug/env", Route::Debug)?;
-    handle(config, cgi_env, &router)
+    // Browsers ask for it, and the dot would make it a 404 as a name
+    router.insert("/favicon.ico", Route::Favicon)?;
+    router.insert("/robots.txt", Route::Robots)?;
+    // Keep in sync with `ShortUrlName::RESERVED`
+    router.insert("/random", Route::Random)?;
+    if config.debug_route {
+        router.insert("/debug/env", Route::Debug)?;
+    }
+    if config.export_route {
+        router.insert("/admin/export", Route::Export)?;
+    }
+    Ok(router)
+}
+
+/// `headers` as sorted `name: value` lines, as parsed from the `HTTP_*`
+/// variables.
+fn header_lines(headers: &http::HeaderMap) -> String {
+    let mut lines: Vec<_> = headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {}\n", String::from_utf8_lossy(value.as_bytes())))
+        .collect();
+    lines.sort();
+    lines.concat()
+}
+
+/// The CGI variables and the parsed request, never cached. With `?json`
+/// only the variables as JSON, with `?headers` only the headers.
+fn debug_response<T: fmt::Debug + Environment>(
+    cgi_env: &CgiEnv<T>,
+    request: &http::Request<()>,
+) -> Result<http::Response<String>, anyhow::Error> {
+    use headers::{CacheControl, ContentType};
+    let mut response = match request.uri().query() {
+        Some("json") => cgi::response(
+            StatusCode::OK,
+            serde_json::to_string_pretty(&cgi_env.recognized())?,
+            ContentType::json(),
+        ),
+        Some("headers") => text_response(StatusCode::OK, header_lines(request.headers())),
+        _ => text_response(
+            StatusCode::OK,
+            format!(
+                "{cgi_env:#?}\n\n{request:#?}\n\n{:?}\n",
+                request.extensions().get::<Tenant>()
+            ),
+        ),
+    };
+    response
+        .headers_mut()
+        .typed_insert(CacheControl::new().with_no_store());
+    Ok(response)
 }
 
-fn repo_from_config(config: &Config) -> Result<impl Repository, anyhow::Error> {
+/// The configured icon, or 204 No Content without one. Either may be
+/// cached for a day, so browsers don't ask on every page.
+fn favicon_response(config: &Config) -> Result<http::Response<Vec<u8>>, anyhow::Error> {
+    let mut response = if let Some(path) = &config.favicon_path {
+        let content_type = match path.extension().and_then(|x| x.to_str()) {
+            Some("png") => "image/png",
+            Some("svg") => "image/svg+xml",
+            _ => "image/x-icon",
+        };
+        let mut response = http::Response::new(fs::read(path)?);
+        response.headers_mut().insert(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static(content_type),
+        );
+        response
+    } else {
+        let mut response = http::Response::new(Vec::new());
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        response
+    };
+    response.headers_mut().typed_insert(
+        headers::CacheControl::new()
+            .with_public()
+            .with_max_age(Duration::from_hours(24)),
+    );
+    Ok(response)
+}
+
+/// Every short URL as a CSV download named after today's date.
+fn export_response(
+    config: &Config,
+    deadline: Option<SystemTime>,
+) -> Result<http::Response<Vec<u8>>, anyhow::Error> {
+    let repo = repo_from_config(config, deadline)?;
+    let mut body = Vec::new();
+    timing::measure(timing::Metric::Db, || repo.export_csv(&mut body))?;
+    let mut response = http::Response::new(body);
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("text/csv; charset=utf-8"),
+    );
+    response
+        .headers_mut()
+        .typed_insert(headers::CacheControl::new().with_no_store());
+    cgi::set_attachment(
+        &mut response,
+        &cgi::export_filename(SystemTime::now(), "csv"),
+    );
+    Ok(response)
+}
+
+/// Open the database for a request, aborting its work at `deadline`.
+fn repo_from_config(
+    config: &Config,
+    deadline: Option<SystemTime>,
+) -> Result<impl Repository, anyhow::Error> {
     let path = config.database_file.clone();
-    open_readonly_repository(path)
+    timing::measure(timing::Metric::Db, || {
+        let repo = open_readonly_repository_with_deadline(path, deadline)?;
+        // Leave a database migrated by a newer version alone
+        if repo.migration_state()? == MigrationState::Ahead {
+            return Err(RepositoryError::SchemaAhead.into());
+        }
+        Ok(repo)
+    })
+}
+
+/// Links are what content negotiation on `Accept` applies to. Saying so
+/// from the start means caches in front need no purge once it does.
+fn with_vary_accept<T>(mut response: http::Response<T>) -> http::Response<T> {
+    cgi::add_vary(&mut response, &http::header::ACCEPT);
+    response
+}
+
+fn short_url_response(
+    config: &Config,
+    request: &http::Request<()>,
+    segment: &str,
+    deadline: Option<SystemTime>,
+) -> Result<http::Response<String>, anyhow::Error> {
+    if !config.forward_query && !request.uri().query().unwrap_or_default().is_empty() {
+        return ErrorController {}.respond((StatusCode::BAD_REQUEST, String::new()));
+    }
+    // Cheap length check first, so huge paths cost nothing more
+    let short_url = Some(segment)
+        .filter(|x| x.len() <= ShortUrlName::MAX_LENGTH)
+        .and_then(|x| ShortUrlName::try_from(x).ok());
+    let Some(short_url) = short_url else {
+        return not_found_response(config.not_found_redirect.as_ref());
+    };
+    #[cfg(feature = "sentry")]
+    cgi::sentry::add_short_url_context(&short_url, config.telemetry_name_key());
+    let repo = repo_from_config(config, deadline)?;
+    let controller = ShortUrlController::with_options(
+        repo,
+        ShortUrlOptions {
+            qr_ec_level: config.qr_ec_level,
+            qr_relative_title: config.qr_relative_title,
+            forward_query: config.forward_query,
+            redirect: config.redirect,
+            https_upgrade: config.https_upgrade,
+            base_path: config.target_base_path.clone(),
+            max_age: config.short_url_max_age.map(Duration::from_secs),
+            not_found_redirect: config.not_found_redirect.clone(),
+        },
+    );
+    let params = ShortUrlControllerParams {
+        name: short_url,
+        page_url: config.public_uri(request.uri())?,
+        if_none_match: request.headers().typed_get(),
+        if_modified_since: request.headers().typed_get(),
+    };
+    controller.respond(params)
 }
 
 fn handle<T: fmt::Debug + Environment>(
     config: &Config,
     cgi_env: &CgiEnv<T>,
     router: &Router<Route>,
-) -> Result<http::Response<String>, anyhow::Error> {
+    deadline: Option<SystemTime>,
+) -> Result<http::Response<Vec<u8>>, anyhow::Error> {
     let request = &cgi_env.new_request()?;
     #[cfg(feature = "sentry")]
     {
-        cgi::sentry::add_request_context(request);
-        cgi::sentry::add_cgi_context(cgi_env);
+        cgi::sentry::add_request_context(request, config.telemetry_name_key());
+        cgi::sentry::add_cgi_context(cgi_env, config.telemetry_name_key());
     }
     if request.method() != http::Method::GET {
-        return ErrorController {}.respond((StatusCode::METHOD_NOT_ALLOWED, String::new()));
+        return ErrorController {}
+            .respond((StatusCode::METHOD_NOT_ALLOWED, String::new()))
+            .map(|response| response.map(String::into_bytes));
     }
     #[allow(clippy::unwrap_used)]
     let path_info = request.extensions().get::<PathInfo>().unwrap();
@@ -183,8 +465,14 @@ fn handle<T: fmt::Debug + Environment>(
         }) => {
             let uri = request.uri();
             if uri.query().unwrap_or_default().is_empty() {
-                let repo = repo_from_config(config)?;
-                let controller = QuotationController::new(repo);
+                let repo = repo_from_config(config, deadline)?;
+                let controller = QuotationController::with_options(
+                    repo,
+                    QuotationOptions {
+                        max_quote_len: config.max_quote_len,
+                        cache: config.home_cache,
+                    },
+                );
                 let response = controller.respond(())?;
                 Ok(response)
             } else {
@@ -197,45 +485,25 @@ fn handle<T: fmt::Debug + Environment>(
             value: Route::ShortUrl,
             params,
         }) => {
-            let uri = request.uri();
-            if uri.query().unwrap_or_default().is_empty() {
-                #[allow(clippy::unwrap_used)]
-                let short_url = params.get(SHORT_URL_PARAM).unwrap().to_string();
-                match ShortUrlName::try_from(short_url.as_str()) {
-                    Ok(short_url) => {
-                        let repo = repo_from_config(config)?;
-                        let controller = ShortUrlController::new(repo);
-                        let params = ShortUrlControllerParams {
-                            name: short_url,
-                            page_url: request.uri().clone(),
-                        };
-                        let response = controller.respond(params)?;
-                        Ok(response)
-                    }
-                    Err(_) => {
-                        Ok(ErrorController {}.respond((StatusCode::NOT_FOUND, String::new()))?)
-                    }
-                }
-            } else {
-                Ok(ErrorController {}.respond((StatusCode::BAD_REQUEST, String::new()))?)
-            }
+            #[allow(clippy::unwrap_used)]
+            let segment = params.get(SHORT_URL_PARAM).unwrap();
+            short_url_response(config, request, segment, deadline).map(with_vary_accept)
+        }
+        Ok(Match {
+            value: Route::Random,
+            params: _params,
+        }) => {
+            let repo = repo_from_config(config, deadline)?;
+            let controller = RandomUrlController::new(repo);
+            Ok(with_vary_accept(controller.respond(request.uri().clone())?))
         }
         Err(MatchError::NotFound) => {
             Ok(ErrorController {}.respond((StatusCode::NOT_FOUND, String::new()))?)
         }
-        #[cfg(debug_assertions)]
         Ok(Match {
             value: Route::Debug,
             params: _params,
-        }) => {
-            use headers::{CacheControl, HeaderMapExt as _};
-            let mut response =
-                text_response(StatusCode::OK, format!("{cgi_env:#?}\n\n{request:#?}\n"));
-            response
-                .headers_mut()
-                .typed_insert(CacheControl::new().with_no_store());
-            Ok(response)
-        }
+        }) => debug_response(cgi_env, request),
         Ok(Match {
             value: Route::ErrorDocument,
             params: _params,
@@ -246,7 +514,19 @@ fn handle<T: fmt::Debug + Environment>(
                 .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
             Ok(ErrorController {}.respond((status_code, String::new()))?)
         }
+        Ok(Match {
+            value: Route::Favicon,
+            params: _params,
+        }) => return favicon_response(config),
+        Ok(Match {
+            value: Route::Robots,
+            params: _params,
+        }) => Ok(text_response(StatusCode::OK, config.robots_txt())),
+        Ok(Match {
+            value: Route::Export,
+            params: _params,
+        }) => return export_response(config, deadline),
     };
 
-    res
+    res.map(|response| response.map(String::into_bytes))
 }
@@ -0,0 +1,246 @@
+//! Whitespace and comment removal for rendered HTML.
+//!
+//! Only handles what the templates produce: escaped text, quoted
+//! attributes and no `<` in text, which the template escaping ensures.
+
+/// Comments starting with this are kept, see the `comment` template
+/// filter.
+pub const KEEP_COMMENT: &str = "<!--!";
+
+/// Elements whose content is copied as is.
+const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// Elements around which whitespace doesn't render.
+const BLOCK_ELEMENTS: [&str; 25] = [
+    "!doctype",
+    "blockquote",
+    "body",
+    "div",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "html",
+    "li",
+    "link",
+    "main",
+    "meta",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "style",
+    "title",
+    "ul",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'a> {
+    /// A tag, doctype or comment.
+    Markup(&'a str),
+    /// Content of a raw element.
+    Raw(&'a str),
+    Text(&'a str),
+}
+
+impl Token<'_> {
+    fn is_block(&self) -> bool {
+        matches!(self, Self::Markup(markup) if BLOCK_ELEMENTS.contains(&tag_name(markup).as_str()))
+    }
+}
+
+/// Lower case name of `<name ...>` or `</name>`, empty for comments.
+fn tag_name(markup: &str) -> String {
+    if markup.starts_with("<!--") {
+        return String::new();
+    }
+    markup
+        .trim_start_matches('<')
+        .trim_start_matches('/')
+        .split(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+/// Length of the tag, doctype or comment at the start of `s`.
+fn markup_len(s: &str) -> usize {
+    if let Some(comment) = s.strip_prefix("<!--") {
+        return comment.find("-->").map_or(s.len(), |end| end + 7);
+    }
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return i + 1,
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+fn tokenize(html: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        let is_markup = rest.starts_with('<')
+            && rest[1..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        if is_markup {
+            let (markup, tail) = rest.split_at(markup_len(rest));
+            tokens.push(Token::Markup(markup));
+            rest = tail;
+            let name = tag_name(markup);
+            if !markup.starts_with("</") && RAW_ELEMENTS.contains(&name.as_str()) {
+                let end = rest
+                    .to_ascii_lowercase()
+                    .find(&format!("</{name}"))
+                    .unwrap_or(rest.len());
+                let (raw, tail) = rest.split_at(end);
+                if !raw.is_empty() {
+                    tokens.push(Token::Raw(raw));
+                }
+                rest = tail;
+            }
+        } else {
+            // Skip the first character, which may be a `<` that starts no tag
+            let first = rest.chars().next().map_or(0, char::len_utf8);
+            let end = rest[first..].find('<').map_or(rest.len(), |x| x + first);
+            let (text, tail) = rest.split_at(end);
+            tokens.push(Token::Text(text));
+            rest = tail;
+        }
+    }
+    tokens
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_space = false;
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            if !in_space {
+                out.push(' ');
+            }
+            in_space = true;
+        } else {
+            out.push(c);
+            in_space = false;
+        }
+    }
+    out
+}
+
+/// Collapse whitespace to a single space, drop it next to block
+/// elements, and strip comments not starting with [`KEEP_COMMENT`].
+/// The content of `pre`, `textarea`, `script` and `style` is kept as
+/// is.
+#[must_use]
+pub fn minify_html(html: &str) -> String {
+    let tokens: Vec<_> = tokenize(html)
+        .into_iter()
+        .filter(|token| {
+            !matches!(token, Token::Markup(markup)
+                if markup.starts_with("<!--") && !markup.starts_with(KEEP_COMMENT))
+        })
+        .collect();
+    let mut out = String::with_capacity(html.len());
+    // Whether the output so far ends at a block boundary
+    let mut after_block = true;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Markup(markup) | Token::Raw(markup) => {
+                out.push_str(markup);
+                after_block = token.is_block();
+            }
+            Token::Text(text) => {
+                let mut text = collapse_whitespace(text);
+                // A dropped comment can leave two texts in a row
+                if after_block || out.ends_with(' ') {
+                    text = text.trim_start().to_string();
+                }
+                if tokens.get(i + 1).is_none_or(Token::is_block) {
+                    text = text.trim_end().to_string();
+                }
+                if !text.is_empty() {
+                    out.push_str(&text);
+                    after_block = false;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Minify the body of an HTML response, leaving other responses alone.
+#[must_use]
+pub fn minify_response(response: http::Response<Vec<u8>>) -> http::Response<Vec<u8>> {
+    let is_html = response
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.starts_with("text/html"));
+    if !is_html {
+        return response;
+    }
+    response.map(|body| match String::from_utf8(body) {
+        Ok(html) => minify_html(&html).into_bytes(),
+        Err(err) => err.into_bytes(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_minify_html() {
+        let html = "<!DOCTYPE html>\n<html>\n<head>\n  <title> A  title </title>\n</head>\n\
+            <body>\n  <!-- dropped -->\n  <!--! kept -->\n  <p>Go  to <!-- dropped --> \n    <a href=\"x > y\">x</a> now</p>\n\
+            <pre>  keep\n  this </pre>\n</body>\n</html>\n";
+        assert_eq!(
+            minify_html(html),
+            "<!DOCTYPE html><html><head><title>A title</title></head><body><!--! kept -->\
+            <p>Go to <a href=\"x > y\">x</a> now</p><pre>  keep\n  this </pre></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_minify_html_raw_elements() {
+        let html = "<style>\n  a  { color: red; }\n</style>\n<script>if (a<b) {}</script>";
+        assert_eq!(
+            minify_html(html),
+            "<style>\n  a  { color: red; }\n</style><script>if (a<b) {}</script>"
+        );
+    }
+
+    #[test]
+    fn test_minify_html_non_ascii() {
+        assert_eq!(minify_html("<h2> Über </h2>"), "<h2>Über</h2>");
+        assert_eq!(minify_html("<h2>Über</h2>"), "<h2>Über</h2>");
+        assert_eq!(
+            minify_html("<blockquote>“Hi”</blockquote>"),
+            "<blockquote>“Hi”</blockquote>"
+        );
+        assert_eq!(minify_html("é<p>x</p>"), "é<p>x</p>");
+    }
+
+    #[test]
+    fn test_minify_response() {
+        let html = crate::html_response(http::StatusCode::OK, " <p> x </p> ".to_string());
+        let response = minify_response(html.map(String::into_bytes));
+        assert_eq!(response.body(), b"<p>x</p>");
+
+        let text = crate::text_response(http::StatusCode::OK, " x ");
+        let response = minify_response(text.map(String::into_bytes));
+        assert_eq!(response.body(), b" x ");
+    }
+}
@@ -1,5 +1,9 @@
-use crate::cgi_env::{CgiEnv, Environment};
+use crate::{
+    cgi_env::{CgiEnv, Environment, MetaVariableKind},
+    HashKey,
+};
 use sentry::{Breadcrumb, Level};
+use shorty::types::ShortUrlName;
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct SentryConfig {
@@ -21,18 +25,33 @@ pub fn add_breadcrumb(category: &str, message: String) {
     });
 }
 
-pub fn add_request_context<T>(request: &http::Request<T>) {
+/// Add the method, URI and headers of `request` to events.
+///
+/// With `key`, the URI and `Referer`, which may hold a short URL name,
+/// are sent as keyed hashes, see [`add_short_url_context`].
+pub fn add_request_context<T>(request: &http::Request<T>, key: Option<&HashKey>) {
     sentry::configure_scope(|scope| {
         let mut map = std::collections::BTreeMap::new();
         map.insert(String::from("method"), request.method().to_string().into());
-        map.insert(String::from("uri"), request.uri().to_string().into());
+        map.insert(
+            String::from("uri"),
+            redact(&request.uri().to_string(), key).into(),
+        );
         map.insert(
             String::from("headers"),
             sentry::protocol::Value::Object(
                 request
                     .headers()
                     .iter()
-                    .map(|(k, v)| (format!("{k:?}"), format!("{v:?}").into()))
+                    .map(|(k, v)| {
+                        let v = format!("{v:?}");
+                        let v = if k == http::header::REFERER {
+                            redact(&v, key)
+                        } else {
+                            v
+                        };
+                        (format!("{k:?}"), v.into())
+                    })
                     .collect(),
             ),
         );
@@ -40,12 +59,140 @@ pub fn add_request_context<T>(request: &http::Request<T>) {
     });
 }
 
-pub fn add_cgi_context<E: Environment>(cgi_env: &CgiEnv<E>) {
+/// `value` as is, or with `key` as a keyed hash.
+fn redact(value: &str, key: Option<&HashKey>) -> String {
+    key.map_or_else(
+        || value.to_string(),
+        |key| format!("{:016x}", key.hash(value.as_bytes())),
+    )
+}
+
+/// Tag events with the requested short URL name.
+///
+/// Errors can then be matched to a link. With `key` the tag is a keyed
+/// hash of the lowercased name instead, which keeps the name out of the
+/// event without letting guesses be checked against it.
+pub fn add_short_url_context(name: &ShortUrlName, key: Option<&HashKey>) {
+    // Every spelling of a name gets the same hash
+    let name = if key.is_some() {
+        name.as_ref().to_ascii_lowercase()
+    } else {
+        name.to_string()
+    };
+    sentry::configure_scope(|scope| scope.set_tag("short_url", redact(&name, key)));
+}
+
+/// Add the recognized meta-variables to events.
+///
+/// With `key`, those holding the request path, and so the short URL
+/// name, are sent as keyed hashes, see [`add_short_url_context`].
+pub fn add_cgi_context<E: Environment>(cgi_env: &CgiEnv<E>, key: Option<&HashKey>) {
+    use MetaVariableKind::{
+        DocumentPathInfo, DocumentUri, PathInfo, PathTranslated, RedirectUrl, RequestUri,
+        ScriptUri, ScriptUrl,
+    };
+
     sentry::configure_scope(|scope| {
         let map: std::collections::BTreeMap<String, _> = cgi_env
             .iter()
-            .map(|(k, v)| (k.to_string(), v.into()))
+            .map(|(k, v)| {
+                let v = match k {
+                    DocumentPathInfo | DocumentUri | PathInfo | PathTranslated | RedirectUrl
+                    | RequestUri | ScriptUri | ScriptUrl => redact(&v, key),
+                    _ => v,
+                };
+                (k.to_string(), v.into())
+            })
             .collect();
         scope.set_context("cgi_environment", sentry::protocol::Context::Other(map));
     });
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn short_url_tag(name: &str, key: Option<&str>) -> String {
+        let key: Option<HashKey> = key.map(|key| key.parse().unwrap());
+        let events = sentry::test::with_captured_events(|| {
+            add_short_url_context(&name.try_into().unwrap(), key.as_ref());
+            sentry::capture_message("test", Level::Error);
+        });
+        events[0].tags["short_url"].clone()
+    }
+
+    /// A request for `/Secret`, as Apache would describe it.
+    struct ApacheEnvironment;
+
+    impl Environment for ApacheEnvironment {
+        fn vars(&self) -> impl Iterator<Item = (std::ffi::OsString, std::ffi::OsString)> {
+            [
+                ("REQUEST_METHOD", "GET"),
+                ("SERVER_PROTOCOL", "HTTP/1.1"),
+                ("REQUEST_SCHEME", "https"),
+                ("SERVER_NAME", "example.org"),
+                ("SERVER_PORT", "443"),
+                ("HTTPS", "on"),
+                ("REQUEST_URI", "/Secret?x=1"),
+                ("QUERY_STRING", "x=1"),
+                ("PATH_INFO", "/Secret"),
+                ("PATH_TRANSLATED", "/var/www/Secret"),
+                ("SCRIPT_URL", "/Secret"),
+                ("SCRIPT_URI", "https://example.org/Secret"),
+                ("HTTP_REFERER", "https://example.org/Secret"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+        }
+
+        fn var(&self, key: String) -> Option<String> {
+            self.vars()
+                .find(|(k, _)| *k == *key)
+                .and_then(|(_, v)| v.into_string().ok())
+        }
+    }
+
+    /// Every context and tag of an event for a request of `/Secret`.
+    fn event_json(key: Option<&str>) -> String {
+        let key: Option<HashKey> = key.map(|key| key.parse().unwrap());
+        let cgi_env = CgiEnv::new(ApacheEnvironment);
+        let request = cgi_env.new_request().unwrap();
+        let events = sentry::test::with_captured_events(|| {
+            add_request_context(&request, key.as_ref());
+            add_cgi_context(&cgi_env, key.as_ref());
+            add_short_url_context(&"Secret".try_into().unwrap(), key.as_ref());
+            sentry::capture_message("test", Level::Error);
+        });
+        let event = &events[0];
+        assert!(event.contexts.contains_key("request"));
+        assert!(event.contexts.contains_key("cgi_environment"));
+        serde_json::to_string(&(&event.contexts, &event.tags)).unwrap()
+    }
+
+    #[test]
+    fn test_contexts_without_name() {
+        let json = event_json(None).to_ascii_lowercase();
+        assert_eq!(json.matches("secret").count(), 8, "{json}");
+
+        let json = event_json(Some("000102030405060708090a0b0c0d0e0f")).to_ascii_lowercase();
+        assert!(!json.contains("secret"), "{json}");
+        assert!(json.contains("example.org"), "{json}");
+    }
+
+    #[test]
+    fn test_add_short_url_context() {
+        const KEY: &str = "000102030405060708090a0b0c0d0e0f";
+        assert_eq!(short_url_tag("AbC", None), "AbC");
+
+        let hashed = short_url_tag("AbC", Some(KEY));
+        assert_eq!(hashed.len(), 16);
+        assert!(!hashed.contains("abc"));
+        assert_eq!(hashed, short_url_tag("abc", Some(KEY)));
+        assert_ne!(hashed, short_url_tag("abd", Some(KEY)));
+        // Another key gives another hash
+        assert_ne!(
+            hashed,
+            short_url_tag("abc", Some("ffffffffffffffffffffffffffffffff"))
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use askama::Template;
 use http::StatusCode;
-use qrcode::{render::svg, types::QrError, QrCode};
-use shorty::types::ShortUrl;
+use shorty::qr::QrEcLevel;
+use shorty::types::{ShortUrl, UnixTimestamp};
 
 use crate::VERSION;
 
@@ -19,6 +19,29 @@ pub struct HttpErrorTemplate {
 pub struct ShortUrlTemplate {
     pub page_url: http::Uri,
     pub short_url: ShortUrl,
+    pub qr_ec_level: QrEcLevel,
+    /// Show `/{name}` instead of `page_url` as the QR code title.
+    pub relative_qr_title: bool,
+    /// When shared caches stop serving this page, shown as a note.
+    pub cached_until: Option<UnixTimestamp>,
+}
+
+impl ShortUrlTemplate {
+    fn qr_title(&self) -> String {
+        if self.relative_qr_title {
+            format!("/{}", self.short_url.name)
+        } else {
+            self.page_url.to_string()
+        }
+    }
+
+    fn qr_alt(&self) -> String {
+        if self.relative_qr_title {
+            format!("QR code for /{}", self.short_url.name)
+        } else {
+            "QR code".to_string()
+        }
+    }
 }
 
 #[allow(unused_imports)]
@@ -40,8 +63,13 @@ mod filters {
     use fmt::Display;
 
     #[askama::filter_fn]
-    pub fn qrcode(s: impl Display, _: &dyn askama::Values) -> askama::Result<String> {
-        super::qr_svg(s.to_string()).map_err(|err| ::askama::Error::Custom(Box::new(err)))
+    pub fn qrcode(
+        s: impl Display,
+        _: &dyn askama::Values,
+        ec_level: &super::QrEcLevel,
+    ) -> askama::Result<String> {
+        shorty::qr::qr_svg(s.to_string(), *ec_level)
+            .map_err(|err| ::askama::Error::Custom(Box::new(err)))
     }
 
     #[askama::filter_fn]
@@ -61,30 +89,19 @@ mod filters {
     // The above is true for XML comments as well. In addition, in
     // XML, such as in SVG or MathML markup, a comment cannot contain
     // the character sequence --
+    //
+    // Starts with `!` so the minifier keeps it.
     #[allow(clippy::doc_markdown)]
     #[askama::filter_fn]
     pub fn comment(s: impl Display, _: &dyn askama::Values) -> Result<Safe<String>, Infallible> {
         Ok(Safe(format!(
-            "<!-- {} -->",
+            "{} {} -->",
+            crate::minify::KEEP_COMMENT,
             s.to_string().replace("--", "__")
         )))
     }
 }
 
-fn qr_svg<D>(data: D) -> Result<String, QrError>
-where
-    D: AsRef<[u8]>,
-{
-    let code = QrCode::new(data)?;
-    let image = code
-        .render()
-        .min_dimensions(200, 200)
-        .dark_color(svg::Color("#000000"))
-        .light_color(svg::Color("#ffffff"))
-        .build();
-    Ok(image)
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -108,11 +125,81 @@ mod test {
                 name: "abc".try_into().unwrap(),
                 url: "https://example.com#ch-1".try_into().unwrap(),
                 last_modified: None,
+                title: None,
+            },
+            qr_ec_level: QrEcLevel::default(),
+            relative_qr_title: false,
+            cached_until: None,
+        };
+        let output = template.render().unwrap();
+        let errors = html::validate(&output);
+        assert_eq!(errors.borrow().len(), 0, "{errors:#?}");
+    }
+
+    #[test]
+    fn test_short_url_template_title_valid_html() {
+        let template = ShortUrlTemplate {
+            page_url: http::Uri::from_static("https://example.com/abc"),
+            short_url: ShortUrl {
+                name: "abc".try_into().unwrap(),
+                url: "https://example.com".try_into().unwrap(),
+                last_modified: None,
+                title: Some("Fish & <chips>".to_string()),
             },
+            qr_ec_level: QrEcLevel::default(),
+            relative_qr_title: false,
+            cached_until: None,
         };
         let output = template.render().unwrap();
         let errors = html::validate(&output);
         assert_eq!(errors.borrow().len(), 0, "{errors:#?}");
+        assert!(output.contains("<h2>Fish &#38; &#60;chips&#62;</h2>"));
+        assert!(output.contains("<title>Fish &#38; &#60;chips&#62; - Shorty</title>"));
+    }
+
+    #[test]
+    fn test_short_url_template_ec_level_h_valid_html() {
+        let template = ShortUrlTemplate {
+            page_url: http::Uri::from_static("https://example.com/abc"),
+            short_url: ShortUrl {
+                name: "abc".try_into().unwrap(),
+                url: "https://example.com".try_into().unwrap(),
+                last_modified: None,
+                title: None,
+            },
+            qr_ec_level: QrEcLevel::H,
+            relative_qr_title: true,
+            cached_until: Some(UnixTimestamp(1_700_000_000)),
+        };
+        let output = template.render().unwrap();
+        let errors = html::validate(&output);
+        assert_eq!(errors.borrow().len(), 0, "{errors:#?}");
+    }
+
+    #[test]
+    fn test_short_url_template_minified_valid_html() {
+        let template = ShortUrlTemplate {
+            page_url: http::Uri::from_static("https://example.com/abc"),
+            short_url: ShortUrl {
+                name: "abc".try_into().unwrap(),
+                url: "https://example.com".try_into().unwrap(),
+                last_modified: Some(UnixTimestamp(1_700_000_000)),
+                title: Some("Fish & chips".to_string()),
+            },
+            qr_ec_level: QrEcLevel::default(),
+            relative_qr_title: false,
+            cached_until: Some(UnixTimestamp(1_700_000_000)),
+        };
+        let output = template.render().unwrap();
+        let minified = crate::minify::minify_html(&output);
+        let errors = html::validate(&minified);
+        assert_eq!(errors.borrow().len(), 0, "{errors:#?}");
+        assert!(minified.len() < output.len(), "{minified}");
+        assert!(minified.contains(&format!("<!--! {VERSION} -->")));
+        assert!(
+            minified.contains("<a href=\"https://example.com/\">Go to abc<div"),
+            "{minified}"
+        );
     }
 
     #[test]
@@ -0,0 +1,95 @@
+use core::cell::Cell;
+use core::time::Duration;
+use std::time::SystemTime;
+
+/// A phase of handling a request, reported in the `Server-Timing`
+/// header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Opening and querying the repository
+    Db,
+    /// Rendering templates
+    Render,
+}
+
+/// Time spent per [`Metric`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    pub db: Duration,
+    pub render: Duration,
+}
+
+thread_local! {
+    // A CGI process handles one request, so one tally per thread is
+    // one per request.
+    static SPENT: Cell<Timings> = const {
+        Cell::new(Timings {
+            db: Duration::ZERO,
+            render: Duration::ZERO,
+        })
+    };
+}
+
+/// Run `f`, adding the time it takes to `metric`.
+pub fn measure<T>(metric: Metric, f: impl FnOnce() -> T) -> T {
+    let started = SystemTime::now();
+    let result = f();
+    // Zero if the clock went backwards
+    let elapsed = started.elapsed().unwrap_or_default();
+    SPENT.with(|spent| {
+        let mut timings = spent.get();
+        match metric {
+            Metric::Db => timings.db += elapsed,
+            Metric::Render => timings.render += elapsed,
+        }
+        spent.set(timings);
+    });
+    result
+}
+
+/// The time measured on this thread so far, which is then reset.
+#[must_use]
+pub fn take() -> Timings {
+    SPENT.with(Cell::take)
+}
+
+impl Timings {
+    /// `Server-Timing` header value, e.g. `db;dur=1.204, render;dur=0.087`
+    /// with durations in milliseconds.
+    #[must_use]
+    pub fn header_value(&self) -> http::HeaderValue {
+        let ms = |x: Duration| x.as_secs_f64() * 1000.0;
+        format!(
+            "db;dur={:.3}, render;dur={:.3}",
+            ms(self.db),
+            ms(self.render)
+        )
+        .try_into()
+        .expect("Failed to create Server-Timing")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_measure() {
+        let _ = take();
+        assert_eq!(measure(Metric::Db, || 42), 42);
+        measure(Metric::Render, || {
+            std::thread::sleep(Duration::from_millis(2));
+        });
+        let timings = take();
+        assert!(timings.render >= Duration::from_millis(2));
+        assert_eq!(take(), Timings::default());
+        assert_eq!(
+            Timings {
+                db: Duration::from_micros(1500),
+                render: Duration::ZERO,
+            }
+            .header_value(),
+            "db;dur=1.500, render;dur=0.000"
+        );
+    }
+}
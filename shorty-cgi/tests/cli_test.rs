@@ -13,22 +13,25 @@ mod test {
     use cgi::Config;
     use predicates::prelude::*;
     use shorty::{
-        repository::{sqlite::open_writable_repository, WritableRepository},
+        repository::WritableRepository,
+        testutil::{database_path, seeded_repo},
         types::{ShortUrlName, Url},
     };
     use tempfile::{tempdir, TempDir};
 
-    fn base_command(db_path: &Path) -> assert_cmd::Command {
-        let temp_dir = db_path.parent().unwrap();
+    fn config(db_path: &Path) -> Config {
+        Config {
+            database_file: db_path.to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    fn base_command(config: &Config) -> assert_cmd::Command {
+        let temp_dir = config.database_file.parent().unwrap();
         let cgi_path = PathBuf::from(cargo_bin_cmd!("cgi").get_program());
 
         let script_path = temp_dir.join("shorty.cgi");
-        let config = Config {
-            database_file: db_path.to_path_buf(),
-            #[cfg(feature = "sentry")]
-            sentry: None,
-        };
-        let toml_string = toml::to_string_pretty(&config).unwrap();
+        let toml_string = toml::to_string_pretty(config).unwrap();
         let script = format!(
             "\
  #!{}
@@ -51,17 +54,17 @@ mod test {
         TempDir,
         path::PathBuf,
     ) {
-        let temp_dir = tempdir().expect("Failed to create temp dir");
-        let db_path = temp_dir.path().join("test.db");
-        assert!(!db_path.exists());
-
-        let mut repo = open_writable_repository(db_path.clone()).unwrap();
-        repo.migrate().unwrap();
+        let (repo, temp_dir) = seeded_repo(&[]);
+        let db_path = database_path(&temp_dir);
         (repo, temp_dir, db_path)
     }
 
     fn get(db_path: &Path, name: Option<&ShortUrlName>) -> assert_cmd::Command {
-        let mut cmd = base_command(db_path);
+        get_with_config(&config(db_path), name)
+    }
+
+    fn get_with_config(config: &Config, name: Option<&ShortUrlName>) -> assert_cmd::Command {
+        let mut cmd = base_command(config);
         cmd.env("GATEWAY_INTERFACE", "CGI/1.1")
             .env("REQUEST_METHOD", "GET")
             .env("REQUEST_SCHEME", "http")
@@ -90,6 +93,173 @@ mod test {
             .stdout(predicate::str::contains(url.to_string()));
     }
 
+    #[test]
+    fn test_get_vary() {
+        let (mut repo, _temp_dir, db_path) = init_repo();
+        let name = "short-url".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+        let config = Config {
+            vary: vec![http::header::ACCEPT_ENCODING],
+            ..config(&db_path)
+        };
+
+        let mut cmd = get_with_config(&config, Some(&name));
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::contains(
+                "vary: accept, accept-encoding\r\n",
+            ));
+        let mut cmd = get_with_config(&config, None);
+        cmd.env("PATH_INFO", "/random")
+            .env("REQUEST_URI", "/random");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 302"))
+            .stdout(predicate::str::contains(
+                "vary: accept, accept-encoding\r\n",
+            ));
+        // Other routes only get the configured names
+        let mut cmd = get_with_config(&config, None);
+        cmd.env("PATH_INFO", "/robots.txt");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("vary: accept-encoding\r\n"));
+    }
+
+    #[test]
+    fn test_get_alias() {
+        let (mut repo, _temp_dir, db_path) = init_repo();
+        let name = "short-url".try_into().unwrap();
+        let alias = "alias".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+        repo.add_alias(&alias, &name).unwrap();
+
+        let mut cmd = get(&db_path, Some(&alias));
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::contains(
+                r#"<a href="https://example.com/">Go to alias"#,
+            ));
+    }
+
+    #[test]
+    fn test_get_canonical_host() {
+        let (mut repo, _temp_dir, db_path) = init_repo();
+        let name: ShortUrlName = "short-url".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+
+        let mut cmd = get(&db_path, Some(&name));
+        cmd.env("REQUEST_URI", "/short-url");
+        cmd.assert().success().stdout(predicate::str::contains(
+            "title=\"http://localhost.localdomain/short-url\"",
+        ));
+
+        let config = Config {
+            canonical_host: Some("sho.rt".to_string()),
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, Some(&name));
+        cmd.env("REQUEST_URI", "/short-url");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "title=\"http://sho.rt/short-url\"",
+            ))
+            .stdout(predicate::str::contains("localhost.localdomain").not());
+    }
+
+    #[test]
+    fn test_access_log() {
+        let (mut _repo, _temp_dir, db_path) = init_repo();
+
+        let name: ShortUrlName = "short-url".try_into().unwrap();
+        let mut cmd = get(&db_path, Some(&name));
+        cmd.env("REQUEST_URI", "/short-url?x=1")
+            .env("UNIQUE_ID", "abc123");
+        let output = cmd.assert().success().get_output().stderr.clone();
+        let line = String::from_utf8(output).unwrap();
+        let record: serde_json::Value = serde_json::from_str(line.trim()).unwrap();
+        assert_eq!(record["method"], "GET");
+        assert_eq!(record["path"], "/short-url");
+        assert_eq!(record["route"], "short_url");
+        assert_eq!(record["status"], 400);
+        assert_eq!(record["request_id"], "abc123");
+    }
+
+    #[test]
+    fn test_get_forward_query() {
+        let (mut repo, _temp_dir, db_path) = init_repo();
+        let name: ShortUrlName = "short-url".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com/?a=1".try_into().unwrap())
+            .unwrap();
+
+        let mut cmd = get(&db_path, Some(&name));
+        cmd.env("REQUEST_URI", "/short-url?ref=x");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 400"));
+
+        let config = Config {
+            forward_query: true,
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, Some(&name));
+        cmd.env("REQUEST_URI", "/short-url?ref=x");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::contains(
+                "href=\"https://example.com/?a=1&#38;ref=x\"",
+            ));
+    }
+
+    #[test]
+    fn test_get_redirect() {
+        let (mut repo, _temp_dir, db_path) = init_repo();
+        let name: ShortUrlName = "short-url".try_into().unwrap();
+        repo.insert_url(&name, &"http://example.com/x?a=1".try_into().unwrap())
+            .unwrap();
+
+        let config = Config {
+            redirect: true,
+            forward_query: true,
+            https_upgrade: true,
+            target_base_path: Some("/app".to_string()),
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, Some(&name));
+        cmd.env("REQUEST_URI", "/short-url?ref=x");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 302"))
+            .stdout(predicate::str::contains(
+                "\r\nlocation: https://example.com/app/x?a=1&ref=x\r\n",
+            ));
+
+        let mut cmd = get_with_config(&config, Some(&"other".try_into().unwrap()));
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 404"));
+
+        // The landing page links to the same target
+        let config = Config {
+            redirect: false,
+            ..config
+        };
+        let mut cmd = get_with_config(&config, Some(&name));
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::contains(
+                "href=\"https://example.com/app/x?a=1\"",
+            ));
+    }
+
     #[test]
     fn test_get_404() {
         let (mut _repo, _temp_dir, db_path) = init_repo();
@@ -101,6 +271,302 @@ mod test {
             .stdout(predicate::str::starts_with("Status: 404"));
     }
 
+    #[test]
+    fn test_get_not_found_redirect() {
+        let (mut _repo, _temp_dir, db_path) = init_repo();
+
+        let name: ShortUrlName = "short-url".try_into().unwrap();
+        let config = Config {
+            not_found_redirect: Some("https://example.com/".try_into().unwrap()),
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, Some(&name));
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 302"))
+            .stdout(predicate::str::contains(
+                "location: https://example.com/\r\n",
+            ));
+    }
+
+    #[test]
+    fn test_get_invalid_name_not_found_redirect() {
+        let (mut _repo, _temp_dir, db_path) = init_repo();
+
+        let config = Config {
+            not_found_redirect: Some("https://example.com/".try_into().unwrap()),
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, None);
+        cmd.env("PATH_INFO", "/foo.bar");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 302"))
+            .stdout(predicate::str::contains(
+                "location: https://example.com/\r\n",
+            ));
+    }
+
+    #[test]
+    fn test_get_invalid_not_found_redirect() {
+        let (mut _repo, _temp_dir, db_path) = init_repo();
+
+        let name: ShortUrlName = "short-url".try_into().unwrap();
+        let mut cmd = get(&db_path, Some(&name));
+        cmd.env("SHORTY_NOT_FOUND_REDIRECT", "/relative");
+        cmd.assert()
+            .failure()
+            .stderr(predicate::str::contains("SHORTY_NOT_FOUND_REDIRECT"));
+    }
+
+    #[test]
+    fn test_get_oversized_name_404() {
+        let temp_dir = tempdir().unwrap();
+        // The database is never opened for a name that is too long
+        let config = config(&temp_dir.path().join("missing.db"));
+        let mut cmd = get_with_config(&config, None);
+        cmd.env("PATH_INFO", format!("/{}", "a".repeat(10 * 1024)));
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 404"));
+    }
+
+    #[test]
+    fn test_get_random() {
+        let (mut repo, _temp_dir, db_path) = init_repo();
+
+        let mut cmd = get(&db_path, None);
+        cmd.env("PATH_INFO", "/random")
+            .env("REQUEST_URI", "/random");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 404"));
+
+        repo.insert_url(
+            &"short-url".try_into().unwrap(),
+            &"https://example.com".try_into().unwrap(),
+        )
+        .unwrap();
+        let mut cmd = get(&db_path, None);
+        cmd.env("PATH_INFO", "/random")
+            .env("REQUEST_URI", "/random");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 302"))
+            .stdout(predicate::str::contains("location: /short-url\r\n"));
+    }
+
+    #[test]
+    fn test_serve_cgi() {
+        let (mut repo, temp_dir, db_path) = init_repo();
+        repo.insert_url(
+            &"abc".try_into().unwrap(),
+            &"https://example.com".try_into().unwrap(),
+        )
+        .unwrap();
+        // Writes the config script
+        base_command(&config(&db_path));
+
+        let mut cmd = cargo_bin_cmd!("cgi");
+        cmd.arg("serve-cgi")
+            .arg(temp_dir.path().join("shorty.cgi"))
+            .env_remove("GATEWAY_INTERFACE")
+            .write_stdin("GET /abc HTTP/1.0\r\nHost: example.org\r\n\r\n");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::contains(
+                r#"<a href="https://example.com/">"#,
+            ));
+    }
+
+    #[test]
+    fn test_server_timing() {
+        let (_repo, _temp_dir, db_path) = init_repo();
+
+        let mut cmd = get(&db_path, None);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("server-timing:").not());
+
+        let config = Config {
+            server_timing: true,
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, None);
+        cmd.assert().success().stdout(
+            predicate::str::is_match(r"\r\nserver-timing: db;dur=[0-9.]+, render;dur=[0-9.]+\r\n")
+                .unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_favicon() {
+        let (_repo, temp_dir, db_path) = init_repo();
+
+        let mut cmd = get(&db_path, None);
+        cmd.env("PATH_INFO", "/favicon.ico")
+            .env("REQUEST_URI", "/favicon.ico");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 204"));
+
+        std::fs::write(temp_dir.path().join("icon.svg"), "<svg/>").unwrap();
+        let config = Config {
+            favicon_path: Some("icon.svg".into()),
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, None);
+        cmd.env("PATH_INFO", "/favicon.ico")
+            .env("REQUEST_URI", "/favicon.ico");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::contains(
+                "\r\ncontent-type: image/svg+xml\r\n",
+            ))
+            .stdout(predicate::str::ends_with("\r\n\r\n<svg/>"));
+    }
+
+    #[test]
+    fn test_robots_txt() {
+        let (_repo, _temp_dir, db_path) = init_repo();
+
+        let mut cmd = get(&db_path, None);
+        cmd.env("PATH_INFO", "/robots.txt")
+            .env("REQUEST_URI", "/robots.txt");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::contains(
+                "\r\ncontent-type: text/plain; charset=utf-8\r\n",
+            ))
+            .stdout(predicate::str::ends_with(
+                "\r\n\r\nUser-agent: *\nDisallow: /\n",
+            ));
+
+        let config = Config {
+            robots_txt: Some("User-agent: *\nAllow: /\n".to_string()),
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, None);
+        cmd.env("PATH_INFO", "/robots.txt")
+            .env("REQUEST_URI", "/robots.txt");
+        cmd.assert().success().stdout(predicate::str::ends_with(
+            "\r\n\r\nUser-agent: *\nAllow: /\n",
+        ));
+    }
+
+    #[test]
+    fn test_debug_headers() {
+        let (_repo, _temp_dir, db_path) = init_repo();
+
+        let mut cmd = get(&db_path, None);
+        cmd.env("PATH_INFO", "/debug/env")
+            .env("REQUEST_URI", "/debug/env?headers")
+            .env("HTTP_X_FORWARDED_FOR", "192.0.2.1");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 404"));
+
+        let config = Config {
+            debug_route: true,
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, None);
+        cmd.env("PATH_INFO", "/debug/env")
+            .env("REQUEST_URI", "/debug/env?headers")
+            .env("HTTP_X_FORWARDED_FOR", "192.0.2.1");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::contains(
+                "\r\n\r\nx-forwarded-for: 192.0.2.1\n",
+            ));
+    }
+
+    #[test]
+    fn test_debug_tenant() {
+        let (_repo, _temp_dir, db_path) = init_repo();
+        let config = Config {
+            debug_route: true,
+            ..config(&db_path)
+        };
+
+        let mut cmd = get_with_config(&config, None);
+        cmd.env("PATH_INFO", "/debug/env")
+            .env("REQUEST_URI", "/debug/env")
+            .env("HTTP_X_TENANT", "acme");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::ends_with("\n\nSome(Tenant(\"acme\"))\n"));
+
+        let mut cmd = get_with_config(&config, None);
+        cmd.env("PATH_INFO", "/debug/env")
+            .env("REQUEST_URI", "/debug/env");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::ends_with("\n\nNone\n"));
+    }
+
+    #[test]
+    fn test_export() {
+        let (mut repo, _temp_dir, db_path) = init_repo();
+        repo.insert_url(
+            &"short-url".try_into().unwrap(),
+            &"https://example.com/".try_into().unwrap(),
+        )
+        .unwrap();
+
+        let mut cmd = get(&db_path, None);
+        cmd.env("PATH_INFO", "/admin/export");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 404"));
+
+        let config = Config {
+            export_route: true,
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, None);
+        cmd.env("PATH_INFO", "/admin/export");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::contains("content-type: text/csv; charset=utf-8\r\n"))
+            .stdout(predicate::str::is_match(
+                "content-disposition: attachment; filename=shorty-export-[0-9]{4}-[0-9]{2}-[0-9]{2}\\.csv\r\n",
+            ).unwrap())
+            .stdout(predicate::str::contains("short-url,https://example.com/,"));
+    }
+
+    #[test]
+    fn test_export_timeout_503() {
+        let (mut repo, _temp_dir, db_path) = init_repo();
+        // Enough rows that the export is interrupted mid-scan
+        let urls: Vec<(ShortUrlName, Url)> = (0..5000)
+            .map(|i| {
+                (
+                    format!("short-url-{i}").as_str().try_into().unwrap(),
+                    "https://example.com/".try_into().unwrap(),
+                )
+            })
+            .collect();
+        repo.insert_urls(&urls).unwrap();
+
+        let config = Config {
+            export_route: true,
+            request_timeout_ms: Some(0),
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, None);
+        cmd.env("PATH_INFO", "/admin/export");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 503"));
+    }
+
     #[test]
     fn test_get_landing_page() {
         let (mut _repo, _temp_dir, db_path) = init_repo();
@@ -111,4 +577,111 @@ mod test {
             .stdout(predicate::str::starts_with("Status: 200"))
             .stdout(predicate::str::contains("Douglas Adams"));
     }
+
+    #[test]
+    fn test_get_landing_page_minify_html() {
+        let (mut _repo, _temp_dir, db_path) = init_repo();
+
+        let config = Config {
+            minify_html: true,
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, None);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::contains("<body><!--! "))
+            .stdout(predicate::str::contains("</header><main>"));
+    }
+
+    #[test]
+    fn test_get_landing_page_minify_html_non_ascii_quote() {
+        let (mut repo, _temp_dir, db_path) = init_repo();
+        repo.insert_quotation("“Über” alles").unwrap();
+
+        let config = Config {
+            minify_html: true,
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, None);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"))
+            .stdout(predicate::str::contains("“Über” alles"));
+    }
+
+    #[test]
+    fn test_request_timeout_503() {
+        let (mut repo, _temp_dir, db_path) = init_repo();
+        // Enough rows that picking a random one takes a while
+        for i in 0..5000 {
+            repo.insert_quotation(&format!("quote {i}")).unwrap();
+        }
+
+        let config = Config {
+            request_timeout_ms: Some(0),
+            ..config(&db_path)
+        };
+        let mut cmd = get_with_config(&config, None);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 503"));
+    }
+
+    #[test]
+    fn test_env_overrides_database_file() {
+        let (mut repo, temp_dir, db_path) = init_repo();
+        let name = "short-url".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+
+        let config = config(&temp_dir.path().join("missing.db"));
+        let mut cmd = get_with_config(&config, Some(&name));
+        cmd.env("SHORTY_DATABASE_FILE", &db_path);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 200"));
+    }
+
+    #[test]
+    fn test_not_migrated_503() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        File::create(&db_path).unwrap();
+
+        let mut cmd = get(&db_path, None);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 503"))
+            .stdout(predicate::str::contains("Service not initialized"));
+    }
+
+    #[test]
+    fn test_missing_table_migrated_500() {
+        let (_repo, _temp_dir, db_path) = init_repo();
+        shorty::rusqlite::Connection::open(&db_path)
+            .unwrap()
+            .execute_batch("DROP TABLE quotations")
+            .unwrap();
+
+        let mut cmd = get(&db_path, None);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 500"));
+    }
+
+    #[test]
+    fn test_schema_ahead_503() {
+        let (_repo, _temp_dir, db_path) = init_repo();
+        shorty::rusqlite::Connection::open(&db_path)
+            .unwrap()
+            .execute_batch("PRAGMA user_version = 1000")
+            .unwrap();
+
+        let mut cmd = get(&db_path, None);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::starts_with("Status: 503"))
+            .stdout(predicate::str::contains("Service being upgraded"));
+    }
 }
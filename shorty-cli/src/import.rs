@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use shorty::anyhow;
+use shorty::repository::WritableRepository;
+use shorty::types::{ShortUrl, ShortUrlName, Url, UrlPolicy};
+
+use crate::ExportRecord;
+
+/// A validated name and URL ready to insert.
+pub type Row = (ShortUrlName, Url);
+
+/// A [`Row`] with the title from the optional `title` column or field.
+pub type TitledRow = (ShortUrlName, Url, Option<String>);
+
+/// Header row written by `export --format csv`.
+const CSV_HEADER: &str = "shorturl,url,last_modified";
+
+/// Header row written by `export --format tsv`.
+const TSV_HEADER: &str = "shorturl\turl\tlast_modified";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImportFormat {
+    Csv,
+    Tsv,
+    /// A JSON array of objects, as written by `export --format json`
+    Json,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl ImportFormat {
+    /// Guess the format from the first non-empty line of `input`.
+    ///
+    /// # Errors
+    ///
+    /// If the line is neither a CSV or TSV header, the start of a JSON
+    /// array nor a JSON object.
+    pub fn detect(input: &str) -> Result<Self, anyhow::Error> {
+        let first = input
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .ok_or_else(|| anyhow!("input is empty"))?;
+        if first.starts_with('[') {
+            Ok(Self::Json)
+        } else if first.starts_with('{') {
+            Ok(Self::Ndjson)
+        } else if first == CSV_HEADER || first == format!("{CSV_HEADER},title") {
+            Ok(Self::Csv)
+        } else if first == TSV_HEADER || first == format!("{TSV_HEADER}\ttitle") {
+            Ok(Self::Tsv)
+        } else {
+            Err(anyhow!(
+                "cannot detect input format: expected the CSV header `{CSV_HEADER}`, \
+                 a JSON array or a JSON object per line, use --format to choose"
+            ))
+        }
+    }
+}
+
+/// What to do with names that occur more than once in the input,
+/// ignoring case.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnDuplicate {
+    /// Keep the first row
+    First,
+    /// Keep the last row, like inserting them one by one would
+    #[default]
+    Last,
+    /// Import nothing
+    Error,
+}
+
+/// Rows inserted and dropped by [`import`].
+#[derive(Debug, Default)]
+pub struct Imported {
+    pub count: usize,
+    /// Rows dropped as case-insensitive duplicates of an imported row.
+    pub dropped: Vec<TitledRow>,
+}
+
+/// Remove rows whose name, ignoring case, was already seen.
+///
+/// Kept rows stay at the position of the first occurrence of their name.
+/// Returns the kept and the dropped rows.
+///
+/// # Errors
+///
+/// On the first duplicate if `policy` is [`OnDuplicate::Error`].
+pub fn dedup(
+    rows: Vec<TitledRow>,
+    policy: OnDuplicate,
+) -> Result<(Vec<TitledRow>, Vec<TitledRow>), anyhow::Error> {
+    let mut kept: Vec<TitledRow> = Vec::with_capacity(rows.len());
+    let mut dropped = Vec::new();
+    // ShortUrlName hashes and compares ignoring case
+    let mut seen: HashMap<ShortUrlName, usize> = HashMap::new();
+    for row in rows {
+        let Some(&index) = seen.get(&row.0) else {
+            seen.insert(row.0.clone(), kept.len());
+            kept.push(row);
+            continue;
+        };
+        match policy {
+            OnDuplicate::First => dropped.push(row),
+            OnDuplicate::Last => dropped.push(core::mem::replace(&mut kept[index], row)),
+            OnDuplicate::Error => {
+                return Err(anyhow!(
+                    "duplicate name {} in input, also given as {}",
+                    row.0,
+                    kept[index].0
+                ));
+            }
+        }
+    }
+    Ok((kept, dropped))
+}
+
+/// Parse `input` into validated names, URLs and titles.
+///
+/// Timestamps in the input are ignored; imported rows get the time of
+/// the import. Empty titles count as none.
+///
+/// # Errors
+///
+/// On the first row that cannot be parsed or validated.
+pub fn parse(
+    input: &str,
+    format: ImportFormat,
+    policy: UrlPolicy,
+) -> Result<Vec<TitledRow>, anyhow::Error> {
+    let validate =
+        |name: &str, url: &str, title: Option<&str>| -> Result<TitledRow, anyhow::Error> {
+            let name = ShortUrlName::try_from(name)?;
+            let url = Url::parse_with(url, policy)?;
+            let title = title.filter(|title| !title.is_empty()).map(String::from);
+            Ok((name, url, title))
+        };
+    match format {
+        ImportFormat::Csv | ImportFormat::Tsv => {
+            let delimiter = if format == ImportFormat::Tsv {
+                b'\t'
+            } else {
+                b','
+            };
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(true)
+                .delimiter(delimiter)
+                .from_reader(input.as_bytes());
+            let title_index = reader.headers()?.iter().position(|x| x == "title");
+            reader
+                .records()
+                .map(|record| {
+                    let record = record?;
+                    let title = title_index.and_then(|index| record.get(index));
+                    match (record.get(0), record.get(1)) {
+                        (Some(name), Some(url)) => validate(name, url, title),
+                        _ => Err(anyhow!("missing field in CSV record {record:?}")),
+                    }
+                })
+                .collect()
+        }
+        ImportFormat::Json => serde_json::from_str::<Vec<ExportRecord>>(input)?
+            .iter()
+            .map(|record| validate(&record.name, &record.url, record.title.as_deref()))
+            .collect(),
+        ImportFormat::Ndjson => input
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let record: ExportRecord = serde_json::from_str(line)?;
+                validate(&record.name, &record.url, record.title.as_deref())
+            })
+            .collect(),
+    }
+}
+
+/// Parse all of `input` and insert it into `repo`.
+///
+/// All rows are inserted in one transaction, and nothing is inserted
+/// unless every row is valid. Names given more than once, ignoring case,
+/// are handled according to `on_duplicate`.
+///
+/// # Errors
+///
+/// If the format cannot be detected, a forced `format` finds no rows, a
+/// row is invalid, a name is duplicated with [`OnDuplicate::Error`] or
+/// the insert fails.
+pub fn import(
+    repo: &mut impl WritableRepository,
+    input: &str,
+    format: Option<ImportFormat>,
+    policy: UrlPolicy,
+    on_duplicate: OnDuplicate,
+) -> Result<Imported, anyhow::Error> {
+    let forced = format.is_some();
+    let format = match format {
+        Some(format) => format,
+        None => ImportFormat::detect(input)?,
+    };
+    let rows = parse(input, format, policy)?;
+    if forced && rows.is_empty() {
+        // E.g. headerless CSV, where the only row is taken as the header
+        return Err(anyhow!("no rows found in input with the given --format"));
+    }
+    let (rows, dropped) = dedup(rows, on_duplicate)?;
+    repo.insert_urls_with_titles(&rows)?;
+    Ok(Imported {
+        count: rows.len(),
+        dropped,
+    })
+}
+
+/// Parse one name per line, skipping blank lines and `#` comments.
+///
+/// Returns the valid names, and the 1-based line number and reason of
+/// every invalid line.
+pub fn parse_names(input: &str) -> (Vec<ShortUrlName>, Vec<(usize, String)>) {
+    let mut names = Vec::new();
+    let mut skipped = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match ShortUrlName::try_from(line) {
+            Ok(name) => names.push(name),
+            Err(err) => skipped.push((index + 1, err.to_string())),
+        }
+    }
+    (names, skipped)
+}
+
+/// Parse quotes, one per line with blank lines skipped. If a line is
+/// exactly `---`, quotes are instead separated by such lines and may
+/// span several lines.
+pub fn parse_quotes(input: &str) -> Vec<String> {
+    if input.lines().any(|line| line.trim_end() == "---") {
+        let mut quotes = vec![String::new()];
+        for line in input.lines() {
+            if line.trim_end() == "---" {
+                quotes.push(String::new());
+            } else if let Some(quote) = quotes.last_mut() {
+                quote.push_str(line);
+                quote.push('\n');
+            }
+        }
+        quotes
+            .iter()
+            .map(|quote| quote.trim().to_string())
+            .filter(|quote| !quote.is_empty())
+            .collect()
+    } else {
+        input
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect()
+    }
+}
+
+/// Parse `name url` lines like [`ShortUrl::parse_with`], also in the
+/// `name -> url` form, skipping blank lines and `#` comments.
+///
+/// Returns the valid rows, and the 1-based line number and reason of
+/// every invalid line.
+pub fn parse_lines(input: &str, policy: UrlPolicy) -> (Vec<Row>, Vec<(usize, String)>) {
+    let mut rows = Vec::new();
+    let mut skipped = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match ShortUrl::parse_with(line, policy) {
+            Ok(short_url) => rows.push((short_url.name, short_url.url)),
+            Err(err) => skipped.push((index + 1, err.to_string())),
+        }
+    }
+    (rows, skipped)
+}
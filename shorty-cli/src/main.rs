@@ -1,20 +1,29 @@
 use anyhow::anyhow;
 use core::cell::RefCell;
+use core::time::Duration;
 use shorty::anyhow;
-use std::io::Write as _;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read as _, Write};
 use std::path::PathBuf;
+use std::time::SystemTime;
 
-use clap::Parser;
-use csv::{Terminator, WriterBuilder};
+use clap::{CommandFactory as _, Parser};
 use git_version::git_version;
 use shorty::{
+    qr::{self, QrEcLevel},
     repository::{
-        Repository, WritableRepository,
-        sqlite::{open_readonly_repository, open_writable_repository},
+        BusyRetry, MigrationState, Pattern, Repository, WritableRepository,
+        sqlite::{OpenOptions, open_readonly_repository, open_writable_in_memory_repository},
     },
-    types::{ShortUrlName, Url},
+    types::{ShortUrl, ShortUrlName, Tz, Url, UrlPolicy},
 };
 
+mod import;
+mod report;
+mod shell;
+#[cfg(feature = "verify")]
+mod verify;
+
 #[derive(Debug, Parser)] // requires `derive` feature
 #[command(about = "Shorty", long_about = None, version = git_version!())]
 struct Cli {
@@ -26,16 +35,668 @@ struct Cli {
 struct CommonArgs {
     #[arg(long, env = "SHORTY_DB")]
     database: PathBuf,
+    /// Log every SQL statement with how long it ran to stderr
+    #[arg(short, long, env = "SHORTY_TRACE", value_parser = clap::builder::FalseyValueParser::new())]
+    verbose: bool,
+}
+
+impl CommonArgs {
+    fn open_options(&self) -> OpenOptions {
+        OpenOptions::new().with_trace(self.verbose)
+    }
+
+    fn open_readonly(&self) -> anyhow::Result<impl Repository> {
+        self.open_options().open_readonly(&self.database)
+    }
+
+    fn open_writable(&self) -> anyhow::Result<impl WritableRepository> {
+        self.open_options().open_writable(&self.database)
+    }
+}
+
+/// Rules for the URLs a command writes.
+#[derive(Debug, clap::Args, Clone)]
+struct PolicyArgs {
+    /// Accept loopback and private network IP addresses as host
+    #[arg(long)]
+    allow_private_ip: bool,
+    /// Reject URLs too long for a QR code at this error correction
+    /// level: L, M, Q or H
+    #[arg(long, env = "SHORTY_QR_EC_LEVEL", default_value_t)]
+    qr_ec_level: QrEcLevel,
+}
+
+impl PolicyArgs {
+    fn url_policy(&self) -> UrlPolicy {
+        UrlPolicy {
+            allow_private_ip: self.allow_private_ip,
+            ..Default::default()
+        }
+        .fitting_qr(self.qr_ec_level)
+    }
+}
+
+#[cfg(feature = "verify")]
+#[derive(Debug, clap::Args, Clone)]
+struct ProbeArgs {
+    /// Also send a HEAD request to the URL and print the status to stderr
+    #[arg(long)]
+    probe: bool,
+    /// How long to wait for the probe, e.g. 5s or 1m
+    #[arg(long, value_parser = parse_duration, default_value = "10s", requires = "probe")]
+    timeout: Duration,
+}
+
+/// How `get` prints the URL.
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+enum GetFormat {
+    /// The target URL
+    #[default]
+    Url,
+    /// A QR code in Unicode blocks
+    Qr,
+}
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+enum ColorChoice {
+    /// Color when writing to a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct ColorArgs {
+    #[arg(long, value_enum, default_value_t)]
+    color: ColorChoice,
+}
+
+impl ColorArgs {
+    /// Standard output, with styles stripped unless color is enabled.
+    fn stdout(&self) -> anstream::AutoStream<std::io::StdoutLock<'static>> {
+        let choice = match self.color {
+            ColorChoice::Auto => anstream::ColorChoice::Auto,
+            ColorChoice::Always => anstream::ColorChoice::Always,
+            ColorChoice::Never => anstream::ColorChoice::Never,
+        };
+        anstream::AutoStream::new(std::io::stdout().lock(), choice)
+    }
+}
+
+const NAME_STYLE: anstyle::Style = anstyle::Style::new().bold();
+const COUNT_STYLE: anstyle::Style = anstyle::Style::new()
+    .bold()
+    .fg_color(Some(anstyle::Color::Ansi(anstyle::AnsiColor::Green)));
+const URL_STYLE: anstyle::Style = anstyle::Style::new().dimmed();
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+enum ExportFormat {
+    #[default]
+    Csv,
+    Tsv,
+    Json,
+}
+
+/// Field order here is the field order in the JSON output.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ExportRecord {
+    name: String,
+    url: String,
+    last_modified: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+}
+
+impl From<ShortUrl> for ExportRecord {
+    fn from(value: ShortUrl) -> Self {
+        Self {
+            name: value.name.to_string(),
+            url: value.url.to_string(),
+            last_modified: value.last_modified.map(|x| x.0),
+            title: value.title,
+        }
+    }
+}
+
+fn export_json(
+    repo: &impl Repository,
+    pretty: bool,
+    with_title: bool,
+) -> Result<(), anyhow::Error> {
+    let records = RefCell::new(Vec::<ExportRecord>::new());
+    repo.for_each_short_url(&|short_url| {
+        let mut record = ExportRecord::from(short_url);
+        if !with_title {
+            record.title = None;
+        }
+        records.borrow_mut().push(record);
+        Ok(())
+    })?;
+    let mut out = std::io::stdout().lock();
+    if pretty {
+        serde_json::to_writer_pretty(&mut out, &records.into_inner())?;
+    } else {
+        serde_json::to_writer(&mut out, &records.into_inner())?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+fn get_url(
+    repo: &impl Repository,
+    name: &ShortUrlName,
+    out: &mut dyn Write,
+) -> anyhow::Result<Url> {
+    match repo.get_url(name)? {
+        Some(short_url) => {
+            writeln!(out, "{URL_STYLE}{}{URL_STYLE:#}", short_url.url)?;
+            Ok(short_url.url)
+        }
+        None => Err(anyhow!("url not found")),
+    }
+}
+
+/// Print a QR code of the target of `name`, or of its short URL under
+/// `base_url`. Returns the target.
+fn get_qr(
+    repo: &impl Repository,
+    name: &ShortUrlName,
+    base_url: Option<&Url>,
+    ec_level: QrEcLevel,
+    out: &mut dyn Write,
+) -> anyhow::Result<Url> {
+    let short_url = repo
+        .get_url(name)?
+        .ok_or_else(|| anyhow!("url not found"))?;
+    let data = match base_url {
+        Some(base_url) => short_url.public_url(base_url)?.to_string(),
+        None => short_url.url.to_string(),
+    };
+    writeln!(out, "{}", qr::qr_text(data, ec_level)?)?;
+    Ok(short_url.url)
+}
+
+fn set_url(
+    repo: &mut impl WritableRepository,
+    name: &ShortUrlName,
+    url: &Url,
+    title: Option<String>,
+    policy: UrlPolicy,
+) -> anyhow::Result<()> {
+    policy.check(url)?;
+    if title.is_some() {
+        // The URL and title are saved together or not at all
+        repo.insert_urls_with_titles(&[(name.clone(), url.clone(), title)])?;
+    } else {
+        repo.insert_url(name, url)?;
+    }
+    eprintln!("url saved");
+    Ok(())
+}
+
+/// Characters of names made by `new`. Without `-` and `_`, a name never
+/// looks like an option.
+const RANDOM_NAME_ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// Names `new` tries before giving up.
+const RANDOM_NAME_ATTEMPTS: usize = 10;
+
+/// A random name of `len` characters from [`RANDOM_NAME_ALPHABET`].
+fn random_name(len: u32) -> anyhow::Result<ShortUrlName> {
+    use core::hash::BuildHasher as _;
+    use std::hash::RandomState;
+    // Seeded from the OS, and reseeded for every name
+    let state = RandomState::new();
+    let name: String = (0..len)
+        .map(|i| {
+            let x = state.hash_one(i) % RANDOM_NAME_ALPHABET.len() as u64;
+            char::from(RANDOM_NAME_ALPHABET[usize::try_from(x).unwrap_or_default()])
+        })
+        .collect();
+    Ok(ShortUrlName::try_from(name)?)
+}
+
+/// Save `url` under a new random name of `len` characters and return
+/// the name. Warns on stderr when names of that length are running out.
+fn new_url(
+    repo: &mut impl WritableRepository,
+    url: &Url,
+    len: u32,
+    policy: UrlPolicy,
+) -> anyhow::Result<ShortUrlName> {
+    policy.check(url)?;
+    let alphabet_len = u32::try_from(RANDOM_NAME_ALPHABET.len())?;
+    let capacity = ShortUrlName::capacity(alphabet_len, len);
+    let count = repo.count_by_prefix("")?;
+    if ShortUrlName::is_crowded(count, capacity) {
+        eprintln!(
+            "warning: {count} short URLs are over 1% of the {capacity} names of length {len}, \
+             random names will collide, use a longer --length"
+        );
+    }
+    for _ in 0..RANDOM_NAME_ATTEMPTS {
+        let name = random_name(len)?;
+        if name.check_not_reserved().is_err() {
+            continue;
+        }
+        if repo.get_or_insert(&name, url)?.1 {
+            return Ok(name);
+        }
+    }
+    Err(anyhow!(
+        "no free name found in {RANDOM_NAME_ATTEMPTS} tries, use a longer --length"
+    ))
+}
+
+fn set_from_file(
+    repo: &mut impl WritableRepository,
+    path: &std::path::Path,
+    policy: UrlPolicy,
+) -> anyhow::Result<()> {
+    let input = std::fs::read_to_string(path)?;
+    let (rows, skipped) = import::parse_lines(&input, policy);
+    for (line, reason) in &skipped {
+        eprintln!("{}:{line}: {reason}", path.display());
+    }
+    repo.insert_urls(&rows)?;
+    eprintln!("{} urls saved, {} lines skipped", rows.len(), skipped.len());
+    Ok(())
+}
+
+/// Write `<name>.svg` to `dir` for each of `names`, or for every short
+/// URL if `names` is empty. Returns the number of files written.
+fn qr_export(
+    repo: &impl Repository,
+    dir: &std::path::Path,
+    base_url: &Url,
+    names: &[ShortUrlName],
+    ec_level: QrEcLevel,
+) -> anyhow::Result<usize> {
+    let write = |short_url: &ShortUrl| -> anyhow::Result<()> {
+        // Names are path safe, but don't let a bug write outside `dir`
+        let file_name = short_url.name.as_ref();
+        if !file_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow!("unsafe file name {file_name:?}"));
+        }
+        let svg = qr::qr_svg(short_url.public_url(base_url)?.to_string(), ec_level)?;
+        std::fs::write(dir.join(format!("{file_name}.svg")), svg)?;
+        Ok(())
+    };
+    if names.is_empty() {
+        let count = RefCell::new(0);
+        repo.for_each_short_url(&|short_url| {
+            write(&short_url)?;
+            *count.borrow_mut() += 1;
+            Ok(())
+        })?;
+        return Ok(count.into_inner());
+    }
+    for name in names {
+        let short_url = repo
+            .get_url(name)?
+            .ok_or_else(|| anyhow!("url not found: {name}"))?;
+        write(&short_url)?;
+    }
+    Ok(names.len())
+}
+
+fn list_names(
+    repo: &impl Repository,
+    prefix: Option<&str>,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let out = RefCell::new(out);
+    #[allow(clippy::option_if_let_else)]
+    match prefix {
+        Some(prefix) => repo.find_by_prefix(prefix, &|short_url| {
+            Ok(writeln!(
+                out.borrow_mut(),
+                "{NAME_STYLE}{}{NAME_STYLE:#}",
+                short_url.name
+            )?)
+        }),
+        None => repo.for_each_name(&|name| {
+            Ok(writeln!(
+                out.borrow_mut(),
+                "{NAME_STYLE}{name}{NAME_STYLE:#}"
+            )?)
+        }),
+    }
+}
+
+/// Check the targets of the short URLs starting with `prefix`, report
+/// those that failed to stdout in `format`, and progress as
+/// `checked/total` to stderr.
+#[cfg(feature = "verify")]
+fn verify_urls(
+    repo: &impl Repository,
+    prefix: &str,
+    verifier: &verify::Verifier,
+    concurrency: usize,
+    format: report::ReportFormat,
+) -> anyhow::Result<()> {
+    let short_urls = RefCell::new(Vec::new());
+    repo.find_by_prefix(prefix, &|short_url| {
+        short_urls
+            .borrow_mut()
+            .push((short_url.name, short_url.url));
+        Ok(())
+    })?;
+    let short_urls = short_urls.into_inner();
+    let results = verify::check_all(verifier, &short_urls, concurrency, &mut |checked, total| {
+        eprint!("\r{checked}/{total}");
+    });
+    if !short_urls.is_empty() {
+        eprintln!();
+    }
+    let failures: Vec<_> = short_urls
+        .iter()
+        .zip(results)
+        .filter_map(|((name, url), result)| {
+            let (status, reason) = match result {
+                Ok(status) if status.is_client_error() || status.is_server_error() => {
+                    (Some(status.as_u16()), status.to_string())
+                }
+                Ok(_) => return None,
+                Err(err) => (None, err.to_string()),
+            };
+            Some(VerifyFailure {
+                name: name.to_string(),
+                url: url.to_string(),
+                status,
+                reason,
+            })
+        })
+        .collect();
+    format.report(std::io::stdout().lock(), &failures)?;
+    if !failures.is_empty() {
+        return Err(anyhow!(
+            "{} of {} URLs failed",
+            failures.len(),
+            short_urls.len()
+        ));
+    }
+    Ok(())
+}
+
+fn search(repo: &impl Repository, pattern: &Pattern, out: &mut dyn Write) -> anyhow::Result<()> {
+    let out = RefCell::new(out);
+    repo.find_by_pattern(pattern, &|short_url| {
+        Ok(writeln!(
+            out.borrow_mut(),
+            "{NAME_STYLE}{}{NAME_STYLE:#}",
+            short_url.name
+        )?)
+    })
+}
+
+fn find_host(repo: &impl Repository, host: &str, out: &mut dyn Write) -> anyhow::Result<()> {
+    let out = RefCell::new(out);
+    repo.find_by_host(host, &|short_url| {
+        Ok(writeln!(
+            out.borrow_mut(),
+            "{NAME_STYLE}{}{NAME_STYLE:#}\t{}",
+            short_url.name,
+            short_url.url
+        )?)
+    })
+}
+
+fn list_tagged(repo: &impl Repository, tag: &str, out: &mut dyn Write) -> anyhow::Result<()> {
+    let out = RefCell::new(out);
+    repo.find_by_tag(tag, &|short_url| {
+        Ok(writeln!(
+            out.borrow_mut(),
+            "{NAME_STYLE}{}{NAME_STYLE:#}",
+            short_url.name
+        )?)
+    })
+}
+
+fn delete_url(repo: &mut impl WritableRepository, name: &ShortUrlName) -> anyhow::Result<()> {
+    if repo.delete_url(name)? {
+        eprintln!("url deleted");
+        Ok(())
+    } else {
+        Err(anyhow!("url not found"))
+    }
+}
+
+fn delete_from_file(
+    repo: &mut impl WritableRepository,
+    path: &std::path::Path,
+) -> anyhow::Result<()> {
+    let input = std::fs::read_to_string(path)?;
+    let (names, skipped) = import::parse_names(&input);
+    for (line, reason) in &skipped {
+        eprintln!("{}:{line}: {reason}", path.display());
+    }
+    let count = repo.delete_many(&names)?;
+    eprintln!("{count} urls deleted, {} lines skipped", skipped.len());
+    Ok(())
+}
+
+fn open_migrated_repository(common: &CommonArgs) -> anyhow::Result<impl WritableRepository> {
+    let repo = common.open_writable()?;
+    ensure_migrated(&repo)?;
+    Ok(repo)
+}
+
+fn ensure_writable(repo: &impl Repository) -> anyhow::Result<()> {
+    if !repo.is_writable() {
+        return Err(anyhow!(
+            "database opened read-only, check the file permissions"
+        ));
+    }
+    Ok(())
+}
+
+fn ensure_migrated(repo: &impl Repository) -> anyhow::Result<()> {
+    match repo.migration_state()? {
+        MigrationState::UpToDate => Ok(()),
+        MigrationState::Pending => Err(anyhow!("migrations needed")),
+        MigrationState::Ahead => Err(anyhow!(
+            "database schema is newer than this binary, upgrade shorty"
+        )),
+    }
+}
+
+fn write_recent(out: &mut dyn Write, short_url: &ShortUrl, tz: Tz) -> anyhow::Result<()> {
+    write!(out, "{short_url}")?;
+    if let Some(iso8601) = short_url.last_modified.and_then(|x| x.iso8601_in_tz(tz)) {
+        write!(out, " ({iso8601})")?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Parse a number of milliseconds, seconds, minutes, hours or days, like
+/// `10m`.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration {s:?}"))?;
+    let secs = match unit {
+        "ms" => return Ok(Duration::from_millis(number)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("invalid unit {unit:?}, expected ms, s, m, h or d")),
+    };
+    Ok(Duration::from_secs(number.saturating_mul(secs)))
+}
+
+/// Print short URLs with `print` as they are added or changed, oldest
+/// first, polling every `interval`. Runs until the process is stopped.
+///
+/// Rows modified in the second it starts or later are printed, also
+/// those changed before the first poll. A name changed twice within one
+/// second is printed once, as timestamps have second resolution.
+fn watch(
+    repo: &impl Repository,
+    interval: Duration,
+    print: &mut dyn FnMut(&ShortUrl) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let now = || -> anyhow::Result<u64> {
+        Ok(SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs())
+    };
+    // Newest timestamp seen, with the names printed at it, since more
+    // rows may get that timestamp after a poll
+    let mut newest = now()?;
+    let mut seen = HashSet::new();
+    loop {
+        let rows = RefCell::new(Vec::new());
+        // One second extra, in case the clock ticks before the query
+        repo.modified_since(now()?.saturating_sub(newest) + 1, &|short_url| {
+            rows.borrow_mut().push(short_url);
+            Ok(())
+        })?;
+        for short_url in rows.into_inner().iter().rev() {
+            let modified = short_url.last_modified.unwrap_or_default().0;
+            if modified < newest {
+                continue;
+            }
+            if modified > newest {
+                newest = modified;
+                seen.clear();
+            }
+            if seen.insert(short_url.name.as_ref().to_ascii_lowercase()) {
+                print(short_url)?;
+            }
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Number of short URLs per target host, most common first, then by host.
+fn count_by_host(repo: &impl Repository) -> anyhow::Result<Vec<(String, u64)>> {
+    let counts = RefCell::new(BTreeMap::<String, u64>::new());
+    repo.for_each_short_url(&|short_url| {
+        let host = short_url.url.host().unwrap_or_default().to_string();
+        *counts.borrow_mut().entry(host).or_default() += 1;
+        Ok(())
+    })?;
+    let mut counts: Vec<_> = counts.into_inner().into_iter().collect();
+    counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+    Ok(counts)
+}
+
+/// Row of `stats --by-host`.
+#[derive(Debug, serde::Serialize)]
+struct HostCount {
+    host: String,
+    count: u64,
+}
+
+impl report::ReportRow for HostCount {
+    fn write_text(&self, out: &mut dyn Write) -> anyhow::Result<()> {
+        let Self { host, count } = self;
+        Ok(writeln!(
+            out,
+            "{COUNT_STYLE}{count}{COUNT_STYLE:#}\t{host}"
+        )?)
+    }
+
+    const CSV_HEADER: &'static [&'static str] = &["host", "count"];
+
+    fn csv_record(&self) -> Vec<String> {
+        vec![self.host.clone(), self.count.to_string()]
+    }
+}
+
+/// Row of `duplicates`.
+#[derive(Debug, serde::Serialize)]
+struct DuplicateGroup {
+    url: String,
+    names: Vec<String>,
+}
+
+impl report::ReportRow for DuplicateGroup {
+    fn write_text(&self, out: &mut dyn Write) -> anyhow::Result<()> {
+        Ok(writeln!(out, "{} -> {}", self.names.join(", "), self.url)?)
+    }
+
+    const CSV_HEADER: &'static [&'static str] = &["url", "names"];
+
+    fn csv_record(&self) -> Vec<String> {
+        vec![self.url.clone(), self.names.join(" ")]
+    }
+}
+
+/// Row of `verify`, for a target that failed.
+#[cfg(feature = "verify")]
+#[derive(Debug, serde::Serialize)]
+struct VerifyFailure {
+    name: String,
+    url: String,
+    /// The final status, if the target answered.
+    status: Option<u16>,
+    reason: String,
+}
+
+#[cfg(feature = "verify")]
+impl report::ReportRow for VerifyFailure {
+    fn write_text(&self, out: &mut dyn Write) -> anyhow::Result<()> {
+        let Self {
+            name, url, reason, ..
+        } = self;
+        Ok(writeln!(out, "{name} {url}: {reason}")?)
+    }
+
+    const CSV_HEADER: &'static [&'static str] = &["name", "url", "status", "reason"];
+
+    fn csv_record(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.url.clone(),
+            self.status.map(|x| x.to_string()).unwrap_or_default(),
+            self.reason.clone(),
+        ]
+    }
 }
 
 #[derive(Debug, clap::Parser)]
 enum Command {
     Set {
         //
-        #[arg(value_parser = |s: &str| ShortUrlName::try_from(s))]
-        name: ShortUrlName,
-        #[arg(value_parser = |s: &str| Url::try_from(s))]
+        #[arg(value_parser = |s: &str| ShortUrlName::try_from(s), required_unless_present = "from_file")]
+        name: Option<ShortUrlName>,
+        #[arg(value_parser = |s: &str| Url::parse_with(s, UrlPolicy::PERMISSIVE), required_unless_present = "from_file")]
+        url: Option<Url>,
+        /// Read `name url` or `name -> url` lines from this file instead.
+        /// Blank lines and lines starting with `#` are ignored.
+        #[arg(long, conflicts_with_all = ["name", "url"])]
+        from_file: Option<PathBuf>,
+        /// Title shown on the short URL page
+        #[arg(long, conflicts_with = "from_file")]
+        title: Option<String>,
+        #[command(flatten)]
+        policy: PolicyArgs,
+        /// Dangerous: write even if the database is not fully migrated.
+        /// Only meant for repairing a database by hand; the write may fail
+        /// or leave the data inconsistent
+        #[arg(long)]
+        no_migrations_check: bool,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Save a URL under a new random name and print the name
+    New {
+        #[arg(value_parser = |s: &str| Url::parse_with(s, UrlPolicy::PERMISSIVE))]
         url: Url,
+        /// Length of the name
+        #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(2..=16))]
+        length: u32,
+        #[command(flatten)]
+        policy: PolicyArgs,
         #[command(flatten)]
         common: CommonArgs,
     },
@@ -43,76 +704,703 @@ enum Command {
         //
         #[arg(value_parser = |s: &str| ShortUrlName::try_from(s))]
         name: ShortUrlName,
+        #[arg(long, value_enum, default_value_t)]
+        format: GetFormat,
+        /// With --format qr, encode the short URL under this public URL,
+        /// e.g. `https://sho.rt/`, instead of the target
+        #[arg(long, value_parser = |s: &str| Url::parse_with(s, UrlPolicy::PERMISSIVE))]
+        base_url: Option<Url>,
+        /// Error correction level of --format qr: L, M, Q or H
+        #[arg(long, env = "SHORTY_QR_EC_LEVEL", default_value_t)]
+        qr_ec_level: QrEcLevel,
+        #[cfg(feature = "verify")]
+        #[command(flatten)]
+        probe: ProbeArgs,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Send a HEAD request to every target URL, and print those that
+    /// fail to answer or answer with an error status
+    #[cfg(feature = "verify")]
+    Verify {
+        /// Only names starting with this, ignoring case
+        #[arg(long)]
+        prefix: Option<String>,
+        /// How long to wait for each URL, e.g. 5s or 1m
+        #[arg(long, value_parser = parse_duration, default_value = "10s")]
+        timeout: Duration,
+        /// How many URLs to check at once
+        #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..=64))]
+        concurrency: u16,
+        /// Redirects to follow before a URL counts as failed
+        #[arg(long, default_value_t = verify::DEFAULT_MAX_REDIRECTS)]
+        max_redirects: u32,
+        #[arg(long, value_enum, default_value_t)]
+        format: report::ReportFormat,
         #[command(flatten)]
         common: CommonArgs,
     },
     List {
+        /// Only names starting with this, ignoring case
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Only names with this tag, ignoring case
+        #[arg(long, conflicts_with = "prefix")]
+        tag: Option<String>,
+        #[command(flatten)]
+        color: ColorArgs,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// List names matching a glob or a regex
+    Search {
+        /// Whole name, ignoring case, with `*` for any run of characters
+        /// and `?` for one, e.g. `ab-*`
+        #[arg(long, required_unless_present = "regex")]
+        glob: Option<String>,
+        /// Part of the name, e.g. `^ab-[0-9]+$`. Scans every row, so it is
+        /// slower than --glob
+        #[arg(long, value_parser = shorty::regex::Regex::new, conflicts_with = "glob")]
+        regex: Option<shorty::regex::Regex>,
+        #[command(flatten)]
+        color: ColorArgs,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// List names whose URL points at a host or its subdomains
+    FindHost {
+        /// E.g. `example.com`, also matching `www.example.com`
+        host: String,
+        #[command(flatten)]
+        color: ColorArgs,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Remove a short URL or alias
+    Delete {
+        #[arg(value_parser = |s: &str| ShortUrlName::try_from(s), required_unless_present = "from_file")]
+        name: Option<ShortUrlName>,
+        /// Read names, one per line, from this file instead. Blank lines
+        /// and lines starting with `#` are ignored.
+        #[arg(long, conflicts_with = "name")]
+        from_file: Option<PathBuf>,
+        /// Move to the trash instead of removing
+        #[arg(long)]
+        trash: bool,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Replace the tags of a short URL. No tags removes all of them.
+    Tag {
+        #[arg(value_parser = |s: &str| ShortUrlName::try_from(s))]
+        name: ShortUrlName,
+        tags: Vec<String>,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Print short URLs as they are added or changed, until stopped with
+    /// Ctrl-C. Each line is flushed when printed, so stopping it loses
+    /// nothing
+    Watch {
+        /// How often to look for changes, e.g. 500ms or 5s
+        #[arg(long, value_parser = parse_duration, default_value = "1s")]
+        interval: Duration,
+        /// Time zone for the timestamps, e.g. Europe/Oslo
+        #[arg(long, default_value_t = Tz::UTC)]
+        timezone: Tz,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Make another name for a short URL, or point an existing alias at
+    /// another short URL
+    Alias {
+        #[arg(value_parser = |s: &str| ShortUrlName::try_from(s))]
+        alias: ShortUrlName,
+        #[arg(value_parser = |s: &str| ShortUrlName::try_from(s))]
+        canonical: ShortUrlName,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Set the last modified time of a short URL to now
+    Touch {
+        #[arg(value_parser = |s: &str| ShortUrlName::try_from(s))]
+        name: ShortUrlName,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    Recent {
+        #[arg(long, default_value_t = 10)]
+        limit: u64,
+        /// All short URLs modified this recently instead, e.g. 90s, 10m,
+        /// 2h or 1d
+        #[arg(long, value_parser = parse_duration, conflicts_with = "limit")]
+        within: Option<Duration>,
+        /// Time zone for the timestamps, e.g. Europe/Oslo
+        #[arg(long, default_value_t = Tz::UTC)]
+        timezone: Tz,
         #[command(flatten)]
         common: CommonArgs,
     },
     Export {
+        #[arg(long, value_enum, default_value_t)]
+        format: ExportFormat,
+        /// Indent JSON output. Ignored for other formats.
+        #[arg(long)]
+        pretty: bool,
+        /// Add the title, as a fourth column or a `title` field
+        #[arg(long)]
+        with_title: bool,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Read short URLs from standard input, as written by `export`
+    Import {
+        /// Input format. Detected from the first line when omitted.
+        #[arg(long, value_enum)]
+        format: Option<import::ImportFormat>,
+        #[command(flatten)]
+        policy: PolicyArgs,
+        /// Which row to import when a name occurs more than once,
+        /// ignoring case
+        #[arg(long, value_enum, default_value_t)]
+        on_duplicate: import::OnDuplicate,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Count short URLs
+    Stats {
+        /// Count per target host, most common first
+        #[arg(long)]
+        by_host: bool,
+        #[arg(long, value_enum, default_value_t, requires = "by_host")]
+        format: report::ReportFormat,
+        #[command(flatten)]
+        color: ColorArgs,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Manage the quotes shown on the home page
+    #[command(subcommand)]
+    Quote(QuoteCommand),
+    /// Manage short URLs deleted with `--trash`
+    #[command(subcommand)]
+    Trash(TrashCommand),
+    /// Take a short URL out of the trash
+    Restore {
+        #[arg(value_parser = |s: &str| ShortUrlName::try_from(s))]
+        name: ShortUrlName,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Write a QR code of each short URL to `<name>.svg`
+    QrExport {
+        /// Directory to write to, created if missing
+        #[arg(long)]
+        output_dir: PathBuf,
+        /// Public URL the names are appended to, e.g. `https://sho.rt/`
+        #[arg(long, value_parser = |s: &str| Url::parse_with(s, UrlPolicy::PERMISSIVE))]
+        base_url: Url,
+        /// Only these names, comma separated. Defaults to all
+        #[arg(long, value_delimiter = ',', value_parser = |s: &str| ShortUrlName::try_from(s))]
+        names: Vec<ShortUrlName>,
+        /// Error correction level: L, M, Q or H
+        #[arg(long, env = "SHORTY_QR_EC_LEVEL", default_value_t)]
+        qr_ec_level: QrEcLevel,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Print the SQL schema of a database
+    Schema {
+        /// Print the schema the migrations create instead. Any database
+        /// given is ignored.
+        #[arg(long)]
+        latest: bool,
+        #[arg(long, env = "SHORTY_DB", required_unless_present = "latest")]
+        database: Option<PathBuf>,
+    },
+    /// Print the database as SQL statements that rebuild it, e.g. with
+    /// `sqlite3 copy.db < dump.sql`
+    Dump {
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Remove all short URLs
+    Clear {
+        /// Confirm that everything should be removed
+        #[arg(long)]
+        yes: bool,
+        /// Remove the quotations too
+        #[arg(long)]
+        include_quotes: bool,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// List target URLs that more than one name points at
+    Duplicates {
+        #[arg(long, value_enum, default_value_t)]
+        format: report::ReportFormat,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Recover after an unclean shutdown: fold the write-ahead log into
+    /// the database and check it for corruption
+    Doctor {
         #[command(flatten)]
         common: CommonArgs,
     },
     Migrate {
+        /// Retry this many times if the database is locked
+        #[arg(long, default_value_t = 0)]
+        busy_retries: u32,
+        /// Milliseconds to wait for a lock on the first attempt, doubled
+        /// on every retry
+        #[arg(long, default_value_t = 5000)]
+        busy_timeout: u64,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Run get, set, list and delete commands interactively
+    Shell {
+        /// Move deleted short URLs to the trash instead of removing them
+        #[arg(long)]
+        trash: bool,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Print a shell completion script
+    Completion { shell: clap_complete::Shell },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum TrashCommand {
+    /// List short URLs in the trash, most recently deleted first
+    List {
+        /// Time zone for the timestamps, e.g. Europe/Oslo
+        #[arg(long, default_value_t = Tz::UTC)]
+        timezone: Tz,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Remove short URLs from the trash for good
+    Empty {
+        /// Only those deleted at least this long ago, e.g. 90s, 10m, 2h
+        /// or 1d
+        #[arg(long, value_parser = parse_duration, default_value = "30d")]
+        older_than: Duration,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum QuoteCommand {
+    /// Add a quote
+    Add {
+        quote: String,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    /// Add quotes from a file, one per line, or separated by `---` lines
+    /// for quotes spanning several lines
+    Import {
+        file: PathBuf,
+        /// Collection to add the quotes to
+        #[arg(long, default_value = "default")]
+        collection: String,
         #[command(flatten)]
         common: CommonArgs,
     },
 }
 
 impl Command {
+    #[allow(clippy::too_many_lines)]
     fn execute(self) -> Result<(), anyhow::Error> {
         match self {
-            Self::Set { name, url, common } => {
-                let mut repo = open_writable_repository(common.database)?;
-                if !repo.has_latest_migrations()? {
-                    return Err(anyhow!("migrations needed"));
+            Self::Set {
+                name,
+                url,
+                from_file,
+                title,
+                policy,
+                no_migrations_check,
+                common,
+            } => {
+                let mut repo = common.open_writable()?;
+                ensure_writable(&repo)?;
+                if !no_migrations_check {
+                    ensure_migrated(&repo)?;
                 }
-                repo.insert_url(&name, &url)?;
-                eprintln!("url saved");
+                match (name, url, from_file) {
+                    (Some(name), Some(url), None) => {
+                        set_url(&mut repo, &name, &url, title, policy.url_policy())
+                    }
+                    (None, None, Some(path)) => {
+                        set_from_file(&mut repo, &path, policy.url_policy())
+                    }
+                    _ => unreachable!("rejected by clap"),
+                }
+            }
+            Self::New {
+                url,
+                length,
+                policy,
+                common,
+            } => {
+                let mut repo = open_migrated_repository(&common)?;
+                ensure_writable(&repo)?;
+                let name = new_url(&mut repo, &url, length, policy.url_policy())?;
+                println!("{name}");
                 Ok(())
             }
-            Self::Get { name, common } => {
-                let repo = open_readonly_repository(common.database)?;
-                let out = RefCell::new(std::io::stdout().lock());
-                match repo.get_url(&name)? {
-                    Some(url) => {
-                        writeln!(*out.borrow_mut(), "{}", url.url)?;
-                        Ok(())
+            Self::Get {
+                name,
+                format,
+                base_url,
+                qr_ec_level,
+                #[cfg(feature = "verify")]
+                probe,
+                common,
+            } => {
+                let repo = common.open_readonly()?;
+                let mut out = anstream::stdout().lock();
+                #[cfg_attr(not(feature = "verify"), allow(unused_variables))]
+                let url = match (format, base_url) {
+                    (GetFormat::Url, None) => get_url(&repo, &name, &mut out)?,
+                    (GetFormat::Url, Some(_)) => {
+                        return Err(anyhow!("--base-url needs --format qr"));
                     }
-                    None => Err(anyhow!("url not found")),
+                    (GetFormat::Qr, base_url) => {
+                        get_qr(&repo, &name, base_url.as_ref(), qr_ec_level, &mut out)?
+                    }
+                };
+                #[cfg(feature = "verify")]
+                if probe.probe {
+                    let status = verify::probe(&url, probe.timeout)?;
+                    eprintln!("{status}");
+                }
+                Ok(())
+            }
+            #[cfg(feature = "verify")]
+            Self::Verify {
+                prefix,
+                timeout,
+                concurrency,
+                max_redirects,
+                format,
+                common,
+            } => {
+                let repo = common.open_readonly()?;
+                let verifier = verify::Verifier::new(timeout, max_redirects);
+                verify_urls(
+                    &repo,
+                    prefix.as_deref().unwrap_or_default(),
+                    &verifier,
+                    concurrency.into(),
+                    format,
+                )
+            }
+            Self::List {
+                prefix,
+                tag,
+                color,
+                common,
+            } => {
+                let repo = common.open_readonly()?;
+                #[allow(clippy::option_if_let_else)]
+                match tag {
+                    Some(tag) => list_tagged(&repo, &tag, &mut color.stdout()),
+                    None => list_names(&repo, prefix.as_deref(), &mut color.stdout()),
+                }
+            }
+            Self::Search {
+                glob,
+                regex,
+                color,
+                common,
+            } => {
+                let repo = common.open_readonly()?;
+                let pattern = match (glob, regex) {
+                    (Some(glob), None) => Pattern::Glob(glob),
+                    (None, Some(regex)) => Pattern::Regex(regex),
+                    _ => unreachable!("rejected by clap"),
+                };
+                search(&repo, &pattern, &mut color.stdout())
+            }
+            Self::FindHost {
+                host,
+                color,
+                common,
+            } => {
+                let repo = common.open_readonly()?;
+                find_host(&repo, &host, &mut color.stdout())
+            }
+            Self::Delete {
+                name,
+                from_file,
+                trash,
+                common,
+            } => {
+                let mut repo = common
+                    .open_options()
+                    .with_soft_delete(trash)
+                    .open_writable(&common.database)?;
+                ensure_migrated(&repo)?;
+                match (name, from_file) {
+                    (Some(name), None) => delete_url(&mut repo, &name),
+                    (None, Some(path)) => delete_from_file(&mut repo, &path),
+                    _ => unreachable!("rejected by clap"),
+                }
+            }
+            Self::Tag { name, tags, common } => {
+                let mut repo = open_migrated_repository(&common)?;
+                if repo.get_url(&name)?.is_none() {
+                    return Err(anyhow!("url not found"));
+                }
+                repo.set_tags(&name, &tags)?;
+                eprintln!("tags saved");
+                Ok(())
+            }
+            Self::Watch {
+                interval,
+                timezone,
+                common,
+            } => {
+                // Read-only and flushed line by line, so the default Ctrl-C
+                // handling, which ends the process, loses nothing
+                let repo = common.open_readonly()?;
+                let mut out = std::io::stdout().lock();
+                watch(&repo, interval, &mut |short_url| {
+                    write_recent(&mut out, short_url, timezone)?;
+                    Ok(out.flush()?)
+                })
+            }
+            Self::Alias {
+                alias,
+                canonical,
+                common,
+            } => {
+                let mut repo = open_migrated_repository(&common)?;
+                repo.add_alias(&alias, &canonical)?;
+                eprintln!("alias saved");
+                Ok(())
+            }
+            Self::Touch { name, common } => {
+                let mut repo = open_migrated_repository(&common)?;
+                if repo.touch(&name)? {
+                    eprintln!("url touched");
+                    Ok(())
+                } else {
+                    Err(anyhow!("url not found"))
                 }
             }
-            Self::List { common } => {
-                let repo = open_readonly_repository(common.database)?;
+            Self::Recent {
+                limit,
+                within,
+                timezone,
+                common,
+            } => {
+                let repo = common.open_readonly()?;
                 let out = RefCell::new(std::io::stdout().lock());
-                repo.for_each_name(&|name| Ok(writeln!(*out.borrow_mut(), "{name}")?))?;
+                match within {
+                    Some(within) => repo.modified_since(within.as_secs(), &|short_url| {
+                        write_recent(&mut *out.borrow_mut(), &short_url, timezone)
+                    }),
+                    None => repo.recent(limit)?.iter().try_for_each(|short_url| {
+                        write_recent(&mut *out.borrow_mut(), short_url, timezone)
+                    }),
+                }
+            }
+            Self::Export {
+                format,
+                pretty,
+                with_title,
+                common,
+            } => {
+                let repo = common.open_readonly()?;
+                let mut out = std::io::stdout().lock();
+                match format {
+                    ExportFormat::Csv => repo.export_delimited_with(&mut out, b',', with_title),
+                    ExportFormat::Tsv => repo.export_delimited_with(&mut out, b'\t', with_title),
+                    ExportFormat::Json => export_json(&repo, pretty, with_title),
+                }
+            }
+            Self::Import {
+                format,
+                policy,
+                on_duplicate,
+                common,
+            } => {
+                let mut input = String::new();
+                std::io::stdin().lock().read_to_string(&mut input)?;
+                let mut repo = open_migrated_repository(&common)?;
+                let imported =
+                    import::import(&mut repo, &input, format, policy.url_policy(), on_duplicate)?;
+                for (name, url, _) in imported.dropped {
+                    eprintln!("duplicate {name} -> {url} skipped");
+                }
+                eprintln!("{} urls imported", imported.count);
+                Ok(())
+            }
+            Self::Stats {
+                by_host,
+                format,
+                color,
+                common,
+            } => {
+                let repo = common.open_readonly()?;
+                let mut out = color.stdout();
+                if by_host {
+                    let rows: Vec<_> = count_by_host(&repo)?
+                        .into_iter()
+                        .map(|(host, count)| HostCount { host, count })
+                        .collect();
+                    format.report(out, &rows)?;
+                } else {
+                    let count = repo.count_by_prefix("")?;
+                    writeln!(out, "{COUNT_STYLE}{count}{COUNT_STYLE:#}")?;
+                }
+                Ok(())
+            }
+            Self::Quote(QuoteCommand::Add { quote, common }) => {
+                let mut repo = open_migrated_repository(&common)?;
+                if repo.insert_quotation(&quote)? {
+                    eprintln!("quote saved");
+                } else {
+                    eprintln!("quote already exists");
+                }
                 Ok(())
             }
-            Self::Export { common } => {
-                let repo = open_readonly_repository(common.database)?;
-                let wtr = RefCell::new(
-                    WriterBuilder::new()
-                        .terminator(Terminator::CRLF)
-                        .from_writer(std::io::stdout()),
+            Self::Quote(QuoteCommand::Import {
+                file,
+                collection,
+                common,
+            }) => {
+                let mut repo = open_migrated_repository(&common)?;
+                let quotes = import::parse_quotes(&std::fs::read_to_string(file)?);
+                let count = repo.insert_quotations(&collection, &quotes)?;
+                eprintln!(
+                    "{count} quotes imported, {} already existed",
+                    quotes.len() - count
                 );
-                (*wtr.borrow_mut()).write_record(["shorturl", "url", "last_modified"])?;
-                repo.for_each_short_url(&|short_url| {
-                    (*wtr.borrow_mut()).write_record([
-                        &short_url.name.to_string(),
-                        &short_url.url.to_string(),
-                        &short_url
-                            .last_modified
-                            .map_or(String::new(), |x| x.to_string()),
-                    ])?;
-                    (*wtr.borrow_mut()).flush()?;
+                Ok(())
+            }
+            Self::Trash(TrashCommand::List { timezone, common }) => {
+                let repo = common.open_readonly()?;
+                let mut out = std::io::stdout().lock();
+                for (short_url, deleted_at) in repo.trashed()? {
+                    write!(out, "{short_url}")?;
+                    if let Some(iso8601) = deleted_at.iso8601_in_tz(timezone) {
+                        write!(out, " (deleted: {iso8601})")?;
+                    }
+                    writeln!(out)?;
+                }
+                Ok(())
+            }
+            Self::Trash(TrashCommand::Empty { older_than, common }) => {
+                let mut repo = open_migrated_repository(&common)?;
+                eprintln!("{} urls removed", repo.empty_trash(older_than.as_secs())?);
+                Ok(())
+            }
+            Self::Restore { name, common } => {
+                let mut repo = open_migrated_repository(&common)?;
+                if repo.restore_url(&name)? {
+                    eprintln!("url restored");
                     Ok(())
-                })?;
+                } else {
+                    Err(anyhow!("url not found in trash"))
+                }
+            }
+            Self::QrExport {
+                output_dir,
+                base_url,
+                names,
+                qr_ec_level,
+                common,
+            } => {
+                let repo = common.open_readonly()?;
+                std::fs::create_dir_all(&output_dir)?;
+                let count = qr_export(&repo, &output_dir, &base_url, &names, qr_ec_level)?;
+                eprintln!("{count} QR codes written to {}", output_dir.display());
+                Ok(())
+            }
+            Self::Schema { latest, database } => {
+                let schema = match database {
+                    Some(database) if !latest => open_readonly_repository(database)?.schema()?,
+                    _ => {
+                        let mut repo = open_writable_in_memory_repository()?;
+                        repo.migrate()?;
+                        repo.schema()?
+                    }
+                };
+                let mut out = std::io::stdout().lock();
+                for sql in schema {
+                    writeln!(out, "{sql};")?;
+                }
+                Ok(())
+            }
+            Self::Dump { common } => {
+                let repo = common.open_readonly()?;
+                repo.dump_sql(&mut std::io::stdout().lock())
+            }
+            Self::Clear {
+                yes,
+                include_quotes,
+                common,
+            } => {
+                if !yes {
+                    return Err(anyhow!("refusing to clear the database without --yes"));
+                }
+                let mut repo = open_migrated_repository(&common)?;
+                eprintln!("{} urls removed", repo.clear_urls()?);
+                if include_quotes {
+                    eprintln!("{} quotes removed", repo.clear_quotations()?);
+                }
                 Ok(())
             }
-            Self::Migrate { common } => {
-                let mut repo = open_writable_repository(common.database)?;
-                repo.migrate()
+            Self::Duplicates { format, common } => {
+                let repo = common.open_readonly()?;
+                let rows: Vec<_> = repo
+                    .duplicate_urls()?
+                    .into_iter()
+                    .map(|(url, names)| DuplicateGroup {
+                        url: url.to_string(),
+                        names: names.iter().map(ToString::to_string).collect(),
+                    })
+                    .collect();
+                format.report(std::io::stdout().lock(), &rows)
+            }
+            Self::Doctor { common } => {
+                let mut repo = common.open_writable()?;
+                ensure_writable(&repo)?;
+                let frames = repo.recover()?;
+                eprintln!("{frames} WAL frames checkpointed");
+                eprintln!("integrity check: ok");
+                Ok(())
+            }
+            Self::Migrate {
+                busy_retries,
+                busy_timeout,
+                common,
+            } => {
+                let mut repo = common.open_writable()?;
+                repo.migrate_with_retry(BusyRetry {
+                    retries: busy_retries,
+                    timeout: Duration::from_millis(busy_timeout),
+                })
+            }
+            Self::Shell { trash, common } => {
+                let mut repo = common
+                    .open_options()
+                    .with_soft_delete(trash)
+                    .open_writable(&common.database)?;
+                ensure_migrated(&repo)?;
+                shell::run(&mut repo)
+            }
+            Self::Completion { shell } => {
+                let mut cmd = Cli::command();
+                let name = cmd.get_name().to_string();
+                clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+                Ok(())
             }
         }
     }
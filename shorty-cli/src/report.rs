@@ -0,0 +1,86 @@
+use std::io::Write;
+
+use shorty::anyhow;
+
+/// Output format of the reporting commands.
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// One line per row
+    #[default]
+    Text,
+    /// A JSON array with one object per row
+    Json,
+    /// A header line, then one record per row
+    Csv,
+}
+
+/// One row of a report. The JSON form comes from `Serialize`.
+pub trait ReportRow: serde::Serialize {
+    /// Write the row as a line of text.
+    ///
+    /// # Errors
+    ///
+    /// If writing to `out` fails.
+    fn write_text(&self, out: &mut dyn Write) -> anyhow::Result<()>;
+
+    /// Column names of [`ReportRow::csv_record`].
+    const CSV_HEADER: &'static [&'static str];
+
+    /// The row as CSV fields; lists are joined with spaces.
+    fn csv_record(&self) -> Vec<String>;
+}
+
+/// Writes all rows of a report in one format.
+pub trait Reporter {
+    /// # Errors
+    ///
+    /// If writing or serializing fails.
+    fn report<R: ReportRow>(&mut self, rows: &[R]) -> anyhow::Result<()>;
+}
+
+pub struct TextReporter<W>(pub W);
+
+impl<W: Write> Reporter for TextReporter<W> {
+    fn report<R: ReportRow>(&mut self, rows: &[R]) -> anyhow::Result<()> {
+        rows.iter().try_for_each(|row| row.write_text(&mut self.0))
+    }
+}
+
+pub struct JsonReporter<W>(pub W);
+
+impl<W: Write> Reporter for JsonReporter<W> {
+    fn report<R: ReportRow>(&mut self, rows: &[R]) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.0, rows)?;
+        writeln!(self.0)?;
+        Ok(())
+    }
+}
+
+pub struct CsvReporter<W>(pub W);
+
+impl<W: Write> Reporter for CsvReporter<W> {
+    fn report<R: ReportRow>(&mut self, rows: &[R]) -> anyhow::Result<()> {
+        let mut writer = csv::Writer::from_writer(&mut self.0);
+        writer.write_record(R::CSV_HEADER)?;
+        for row in rows {
+            writer.write_record(row.csv_record())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl ReportFormat {
+    /// Write `rows` to `out` with the [`Reporter`] for this format.
+    ///
+    /// # Errors
+    ///
+    /// If writing or serializing fails.
+    pub fn report<R: ReportRow>(self, out: impl Write, rows: &[R]) -> anyhow::Result<()> {
+        match self {
+            Self::Text => TextReporter(out).report(rows),
+            Self::Json => JsonReporter(out).report(rows),
+            Self::Csv => CsvReporter(out).report(rows),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use clap::Parser as _;
+use rustyline::{DefaultEditor, error::ReadlineError};
+use shorty::anyhow;
+use shorty::repository::WritableRepository;
+use shorty::types::{ShortUrlName, Url, UrlPolicy};
+
+#[derive(Debug, clap::Parser)]
+#[command(name = "shorty", no_binary_name = true, disable_version_flag = true)]
+enum ShellCommand {
+    Get {
+        #[arg(value_parser = |s: &str| ShortUrlName::try_from(s))]
+        name: ShortUrlName,
+    },
+    Set {
+        #[arg(value_parser = |s: &str| ShortUrlName::try_from(s))]
+        name: ShortUrlName,
+        #[arg(value_parser = |s: &str| Url::parse_with(s, UrlPolicy::PERMISSIVE))]
+        url: Url,
+        #[command(flatten)]
+        policy: crate::PolicyArgs,
+    },
+    List {
+        /// Only names starting with this, ignoring case
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+    Delete {
+        #[arg(value_parser = |s: &str| ShortUrlName::try_from(s))]
+        name: ShortUrlName,
+    },
+    /// Leave the shell
+    #[command(alias = "quit")]
+    Exit,
+}
+
+impl ShellCommand {
+    fn execute(self, repo: &mut impl WritableRepository) -> Result<(), anyhow::Error> {
+        let out = &mut anstream::stdout().lock();
+        match self {
+            Self::Get { name } => crate::get_url(repo, &name, out).map(drop),
+            Self::Set { name, url, policy } => {
+                crate::set_url(repo, &name, &url, None, policy.url_policy())
+            }
+            Self::List { prefix } => crate::list_names(repo, prefix.as_deref(), out),
+            Self::Delete { name } => crate::delete_url(repo, &name),
+            Self::Exit => Ok(()),
+        }
+    }
+}
+
+/// Read commands line by line until `exit` or end of input.
+///
+/// A failing command is reported on stderr and does not end the session.
+pub fn run(repo: &mut impl WritableRepository) -> Result<(), anyhow::Error> {
+    let mut editor = DefaultEditor::new()?;
+    loop {
+        let line = match editor.readline("shorty> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let args: Vec<_> = line.split_whitespace().collect();
+        if args.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+        match ShellCommand::try_parse_from(args) {
+            Ok(ShellCommand::Exit) => return Ok(()),
+            Ok(command) => {
+                if let Err(err) = command.execute(repo) {
+                    eprintln!("error: {err:#}");
+                }
+            }
+            Err(err) => err.print()?,
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use shorty::anyhow::{self, anyhow};
+use shorty::types::{ShortUrlName, Url};
+use ureq::http::StatusCode;
+
+/// Redirects followed before a URL counts as failed, unless told
+/// otherwise.
+pub const DEFAULT_MAX_REDIRECTS: u32 = 5;
+
+/// Sends `HEAD` requests through one agent, so checks of URLs on the
+/// same host reuse its kept-alive connections.
+#[derive(Debug, Clone)]
+pub struct Verifier {
+    agent: ureq::Agent,
+    max_redirects: u32,
+}
+
+impl Verifier {
+    #[must_use]
+    pub fn new(timeout: Duration, max_redirects: u32) -> Self {
+        let config = ureq::Agent::config_builder()
+            .timeout_global(Some(timeout))
+            .http_status_as_error(false)
+            // Followed by `check`, which tells a loop from a long chain
+            .max_redirects(0)
+            .build();
+        Self {
+            agent: ureq::Agent::new_with_config(config),
+            max_redirects,
+        }
+    }
+
+    /// Send a `HEAD` request to `url`, following redirects, and return
+    /// the final status.
+    ///
+    /// Any HTTP status counts as an answer, only transport and redirect
+    /// failures are errors.
+    ///
+    /// # Errors
+    ///
+    /// If a host cannot be reached or does not answer within the timeout,
+    /// a redirect has no valid `Location`, leads back to a URL already
+    /// visited, or there are more than `max_redirects` of them.
+    pub fn check(&self, url: &Url) -> Result<StatusCode, anyhow::Error> {
+        let mut url = <&shorty::url::Url>::from(url).clone();
+        let mut visited = vec![url.clone()];
+        loop {
+            let response = self.agent.head(url.as_str()).call()?;
+            let status = response.status();
+            if !matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308) {
+                return Ok(status);
+            }
+            let location = response
+                .headers()
+                .get(ureq::http::header::LOCATION)
+                .and_then(|x| x.to_str().ok())
+                .ok_or_else(|| anyhow!("{status} without Location from {url}"))?;
+            url = url.join(location)?;
+            if visited.contains(&url) {
+                return Err(anyhow!("redirect loop at {url}"));
+            }
+            if visited.len() > self.max_redirects as usize {
+                return Err(anyhow!("more than {} redirects", self.max_redirects));
+            }
+            visited.push(url.clone());
+        }
+    }
+}
+
+/// Send a `HEAD` request to `url`, following redirects, and return the
+/// final status.
+///
+/// # Errors
+///
+/// See [`Verifier::check`].
+pub fn probe(url: &Url, timeout: Duration) -> Result<StatusCode, anyhow::Error> {
+    Verifier::new(timeout, DEFAULT_MAX_REDIRECTS).check(url)
+}
+
+/// Check the URLs of `short_urls` with up to `concurrency` requests at a
+/// time, and return the results in the same order.
+///
+/// `progress` is called with the number checked so far and the total
+/// after every check.
+pub fn check_all(
+    verifier: &Verifier,
+    short_urls: &[(ShortUrlName, Url)],
+    concurrency: usize,
+    progress: &mut dyn FnMut(usize, usize),
+) -> Vec<Result<StatusCode, anyhow::Error>> {
+    let next = &AtomicUsize::new(0);
+    let mut results: Vec<_> = short_urls.iter().map(|_| None).collect();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..concurrency.min(short_urls.len()) {
+            let sender = sender.clone();
+            scope.spawn(move || {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((_, url)) = short_urls.get(index) else {
+                        break;
+                    };
+                    if sender.send((index, verifier.check(url))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // Ends the receiving loop once every worker is done
+        drop(sender);
+        for (checked, (index, result)) in receiver.iter().enumerate() {
+            results[index] = Some(result);
+            progress(checked + 1, short_urls.len());
+        }
+    });
+    results.into_iter().flatten().collect()
+}
@@ -8,6 +8,7 @@ mod test {
             Repository, WritableRepository,
             sqlite::{open_readonly_repository, open_writable_repository},
         },
+        testutil::{database_path, seeded_repo},
         types::{ShortUrlName, Url},
     };
     use tempfile::tempdir;
@@ -51,6 +52,14 @@ mod test {
         cmd
     }
 
+    fn recent(db_path: &PathBuf) -> assert_cmd::Command {
+        let mut cmd = base_command();
+        cmd.arg("recent");
+        cmd.arg("--database");
+        cmd.arg(db_path);
+        cmd
+    }
+
     fn export(db_path: &PathBuf) -> assert_cmd::Command {
         let mut cmd = base_command();
         cmd.arg("export");
@@ -74,19 +83,287 @@ mod test {
     }
 
     #[test]
-    fn test_get() {
+    fn test_schema_ahead() {
         let temp_dir = tempdir().expect("Failed to create temp dir");
         let db_path = temp_dir.path().join("test.db");
-        assert!(!db_path.exists());
+        migrate(&db_path);
+        shorty::rusqlite::Connection::open(&db_path)
+            .unwrap()
+            .execute_batch("PRAGMA user_version = 1000")
+            .unwrap();
+
+        let mut cmd = base_command();
+        cmd.arg("migrate").arg("--database").arg(&db_path);
+        cmd.assert().failure().stderr(predicates::str::contains(
+            "Database schema is newer than this binary",
+        ));
+        set(
+            &db_path,
+            &"aa".try_into().unwrap(),
+            &"https://example.com".try_into().unwrap(),
+        )
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "database schema is newer than this binary",
+        ));
+    }
+
+    #[test]
+    fn test_set_invalid_input() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+
+        let mut cmd = base_command();
+        cmd.args(["set", "abc$", "https://example.com", "--database"])
+            .arg(&db_path);
+        cmd.assert().failure().stderr(predicates::str::contains(
+            "short URL name \"abc$\" contains '$'",
+        ));
+
+        let mut cmd = base_command();
+        cmd.args(["set", "abc", "https://127.0.0.1/", "--database"])
+            .arg(&db_path);
+        cmd.assert().failure().stderr(predicates::str::contains(
+            "URL \"https://127.0.0.1/\" points to a loopback or private network IP address",
+        ));
+    }
+
+    #[test]
+    fn test_get() {
+        let (_repo, temp_dir) = seeded_repo(&[("aa", "https://example.com")]);
+        let db_path = database_path(&temp_dir);
 
         let name = "aa".try_into().unwrap();
-        let url: Url = "https://example.com".try_into().unwrap();
-        let mut repo = open_writable_repository(&db_path).unwrap();
-        repo.migrate().unwrap();
-        repo.insert_url(&name, &url).unwrap();
+        let mut cmd = get(&db_path, &name);
+        cmd.assert().success().stdout("https://example.com/\n");
+    }
+
+    #[test]
+    fn test_get_qr() {
+        let (_repo, temp_dir) = seeded_repo(&[("aa", "https://example.com")]);
+        let db_path = database_path(&temp_dir);
+        let name = "aa".try_into().unwrap();
+
+        let target = get(&db_path, &name)
+            .args(["--format", "qr"])
+            .output()
+            .unwrap();
+        assert!(target.status.success());
+        let target = String::from_utf8(target.stdout).unwrap();
+        assert!(target.contains('\u{2588}'));
+        assert!(target.contains('\u{2580}') || target.contains('\u{2584}'));
+
+        let short = get(&db_path, &name)
+            .args(["--format", "qr", "--base-url", "https://sho.rt/"])
+            .output()
+            .unwrap();
+        assert!(short.status.success());
+        assert_ne!(String::from_utf8(short.stdout).unwrap(), target);
 
         let mut cmd = get(&db_path, &name);
-        cmd.assert().success().stdout(format!("{url}\n"));
+        cmd.args(["--base-url", "https://sho.rt/"]);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("--base-url needs --format qr"));
+    }
+
+    /// Serve one request with `status`, returning the request head.
+    #[cfg(feature = "verify")]
+    fn mock_server(status: &str) -> (String, std::thread::JoinHandle<String>) {
+        use std::io::{BufRead as _, BufReader, Write as _};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+        let url = format!(
+            "http://{}/alive",
+            listener.local_addr().expect("No local address")
+        );
+        let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("Failed to accept");
+            let mut head = String::new();
+            let mut reader = BufReader::new(stream.try_clone().expect("Failed to clone stream"));
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("Failed to read");
+                if line.trim().is_empty() {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            stream
+                .write_all(response.as_bytes())
+                .expect("Failed to write");
+            head
+        });
+        (url, handle)
+    }
+
+    #[test]
+    #[cfg(feature = "verify")]
+    fn test_get_probe() {
+        use shorty::types::UrlPolicy;
+
+        let (mut repo, temp_dir) = seeded_repo(&[]);
+        let db_path = database_path(&temp_dir);
+        let name: ShortUrlName = "aa".try_into().unwrap();
+        let (url, server) = mock_server("503 Service Unavailable");
+        repo.insert_url(
+            &name,
+            &Url::parse_with(&url, UrlPolicy::PERMISSIVE).unwrap(),
+        )
+        .unwrap();
+
+        let mut cmd = get(&db_path, &name);
+        cmd.args(["--probe", "--timeout", "5s"]);
+        cmd.assert()
+            .success()
+            .stdout(format!("{url}\n"))
+            .stderr("503 Service Unavailable\n");
+        assert!(
+            server
+                .join()
+                .unwrap()
+                .starts_with("HEAD /alive HTTP/1.1\r\n")
+        );
+
+        // Nothing listens on the port any more
+        let mut cmd = get(&db_path, &name);
+        cmd.args(["--probe", "--timeout", "5s"]);
+        cmd.assert().failure().stdout(format!("{url}\n"));
+
+        let mut cmd = get(&db_path, &name);
+        cmd.args(["--timeout", "5s"]);
+        cmd.assert().failure();
+    }
+
+    /// Serve `/ok` (200), `/missing` (404), `/moved` (redirect to `/ok`)
+    /// and `/loop` (redirect to itself) with keep-alive, returning the
+    /// base URL and the number of connections accepted so far.
+    #[cfg(feature = "verify")]
+    fn mock_site() -> (String, std::sync::Arc<core::sync::atomic::AtomicUsize>) {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        use std::io::{BufRead as _, BufReader, Write as _};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+        let base = format!(
+            "http://{}",
+            listener.local_addr().expect("No local address")
+        );
+        let connections = std::sync::Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.expect("Failed to accept");
+                counter.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || {
+                    let mut reader =
+                        BufReader::new(stream.try_clone().expect("Failed to clone stream"));
+                    loop {
+                        let mut request_line = String::new();
+                        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                            return;
+                        }
+                        loop {
+                            let mut line = String::new();
+                            if reader.read_line(&mut line).unwrap_or(0) == 0
+                                || line.trim().is_empty()
+                            {
+                                break;
+                            }
+                        }
+                        let status = match request_line.split_whitespace().nth(1) {
+                            Some("/ok") => "200 OK",
+                            Some("/moved") => "301 Moved Permanently\r\nLocation: /ok",
+                            Some("/loop") => "302 Found\r\nLocation: /loop",
+                            _ => "404 Not Found",
+                        };
+                        let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+                        if stream.write_all(response.as_bytes()).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (base, connections)
+    }
+
+    #[test]
+    #[cfg(feature = "verify")]
+    fn test_verify() {
+        use core::sync::atomic::Ordering;
+        use shorty::types::UrlPolicy;
+
+        let (mut repo, temp_dir) = seeded_repo(&[]);
+        let db_path = database_path(&temp_dir);
+        let (base, connections) = mock_site();
+        for name in ["ok", "missing", "moved", "loop"] {
+            repo.insert_url(
+                &name.try_into().unwrap(),
+                &Url::parse_with(&format!("{base}/{name}"), UrlPolicy::PERMISSIVE).unwrap(),
+            )
+            .unwrap();
+        }
+        let verify = || {
+            let mut cmd = base_command();
+            cmd.arg("verify")
+                .arg("--database")
+                .arg(&db_path)
+                .args(["--timeout", "5s"]);
+            cmd
+        };
+
+        verify()
+            .args(["--concurrency", "1"])
+            .assert()
+            .failure()
+            .stdout(format!(
+                "loop {base}/loop: redirect loop at {base}/loop\n\
+                 missing {base}/missing: 404 Not Found\n"
+            ))
+            .stderr(predicates::str::contains("\r1/4\r2/4\r3/4\r4/4\n"))
+            .stderr(predicates::str::contains("2 of 4 URLs failed"));
+        // One agent, so one kept-alive connection serves every check
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+        verify()
+            .args(["--format", "json"])
+            .assert()
+            .failure()
+            .stdout(format!(
+                "[{{\"name\":\"loop\",\"url\":\"{base}/loop\",\"status\":null,\
+                 \"reason\":\"redirect loop at {base}/loop\"}},\
+                 {{\"name\":\"missing\",\"url\":\"{base}/missing\",\"status\":404,\
+                 \"reason\":\"404 Not Found\"}}]\n"
+            ));
+
+        verify()
+            .args(["--format", "csv"])
+            .assert()
+            .failure()
+            .stdout(format!(
+                "name,url,status,reason\n\
+                 loop,{base}/loop,,redirect loop at {base}/loop\n\
+                 missing,{base}/missing,404,404 Not Found\n"
+            ));
+
+        verify()
+            .args(["--concurrency", "4", "--max-redirects", "0"])
+            .assert()
+            .failure()
+            .stdout(predicates::str::contains(format!(
+                "moved {base}/moved: more than 0 redirects\n"
+            )))
+            .stderr(predicates::str::contains("3 of 4 URLs failed"));
+
+        verify()
+            .args(["--prefix", "o"])
+            .assert()
+            .success()
+            .stdout("")
+            .stderr("\r1/1\n");
     }
 
     #[test]
@@ -110,20 +387,487 @@ mod test {
         assert_eq!(url, short_url.url);
     }
 
+    #[test]
+    fn test_new() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+
+        let output = base_command()
+            .arg("new")
+            .arg("--database")
+            .arg(&db_path)
+            .arg("https://example.com")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(output.stderr.is_empty());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let name: ShortUrlName = stdout.trim_end().try_into().unwrap();
+        assert_eq!(name.to_string().len(), 6);
+
+        let repo = open_readonly_repository(&db_path).unwrap();
+        let short_url = repo.get_url(&name).unwrap().unwrap();
+        assert_eq!(short_url.url.to_string(), "https://example.com/");
+    }
+
+    #[test]
+    fn test_new_crowded() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        let url: Url = "https://example.com".try_into().unwrap();
+        let mut repo = open_writable_repository(&db_path).unwrap();
+        repo.migrate().unwrap();
+        // Over 1% of the 36^2 = 1296 names of length 2
+        for i in 0..13 {
+            let name = format!("long-name-{i}").as_str().try_into().unwrap();
+            repo.insert_url(&name, &url).unwrap();
+        }
+        drop(repo);
+
+        base_command()
+            .arg("new")
+            .arg("--database")
+            .arg(&db_path)
+            .arg("--length")
+            .arg("2")
+            .arg(url.to_string())
+            .assert()
+            .success()
+            .stderr(predicates::str::contains(
+                "warning: 13 short URLs are over 1% of the 1296 names of length 2",
+            ));
+    }
+
+    #[test]
+    fn test_set_no_migrations_check() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        let name = "aa".try_into().unwrap();
+        let url = "https://example.com".try_into().unwrap();
+
+        // No tables at all: the insert is attempted and fails
+        set(&db_path, &name, &url)
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("migrations needed"));
+        set(&db_path, &name, &url)
+            .arg("--no-migrations-check")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("no such table: urls"));
+
+        // Partially migrated: the insert goes through
+        migrate(&db_path);
+        shorty::rusqlite::Connection::open(&db_path)
+            .unwrap()
+            .execute_batch("PRAGMA user_version = 2")
+            .unwrap();
+        set(&db_path, &name, &url).assert().failure();
+        set(&db_path, &name, &url)
+            .arg("--no-migrations-check")
+            .assert()
+            .success();
+        let repo = open_readonly_repository(&db_path).unwrap();
+        assert!(repo.get_url(&name).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_set_from_file() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+        let list_path = temp_dir.path().join("urls.txt");
+        std::fs::write(
+            &list_path,
+            "# Seed list\n\
+             aa https://example.com/a\n\
+             \n\
+             b https://example.com/b\n\
+             cc   https://example.com/c\n\
+             dd ftp://example.com/d\n\
+             ee\n\
+             ff -> https://example.com/f\n",
+        )
+        .unwrap();
+
+        let mut cmd = base_command();
+        cmd.arg("set")
+            .arg("--from-file")
+            .arg(&list_path)
+            .arg("--database")
+            .arg(&db_path);
+        cmd.assert()
+            .success()
+            .stderr(predicates::str::contains(
+                ":4: short URL name \"b\" is too short",
+            ))
+            .stderr(predicates::str::contains(
+                ":6: URL \"ftp://example.com/d\" must start with http:// or https://",
+            ))
+            .stderr(predicates::str::contains(":7: expected `name url`"))
+            .stderr(predicates::str::contains("3 urls saved, 3 lines skipped"));
+
+        let repo = open_readonly_repository(&db_path).unwrap();
+        let short_url = repo.get_url(&"cc".try_into().unwrap()).unwrap().unwrap();
+        assert_eq!(short_url.url.to_string(), "https://example.com/c");
+        assert!(repo.get_url(&"dd".try_into().unwrap()).unwrap().is_none());
+        let short_url = repo.get_url(&"ff".try_into().unwrap()).unwrap().unwrap();
+        assert_eq!(short_url.url.to_string(), "https://example.com/f");
+    }
+
+    #[test]
+    fn test_set_private_ip() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+
+        let mut cmd = base_command();
+        cmd.args(["set", "aa", "http://192.168.1.1/", "--database"])
+            .arg(&db_path);
+        cmd.assert().failure();
+
+        cmd.arg("--allow-private-ip");
+        cmd.assert().success();
+
+        let repo = open_readonly_repository(&db_path).unwrap();
+        let short_url = repo.get_url(&"aa".try_into().unwrap()).unwrap().unwrap();
+        assert_eq!(short_url.url.to_string(), "http://192.168.1.1/");
+    }
+
+    #[test]
+    fn test_set_qr_ec_level() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+        // Fits a QR code at the default level M, but not at H
+        let url = format!("https://example.com/{}", "a".repeat(2048 - 20));
+
+        base_command()
+            .args(["set", "aa", &url, "--qr-ec-level", "h", "--database"])
+            .arg(&db_path)
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains(
+                "URL of 2048 bytes is longer than the limit of 1273",
+            ));
+        base_command()
+            .args(["new", &url, "--database"])
+            .arg(&db_path)
+            .env("SHORTY_QR_EC_LEVEL", "H")
+            .assert()
+            .failure();
+
+        base_command()
+            .args(["set", "aa", &url, "--database"])
+            .arg(&db_path)
+            .assert()
+            .success();
+        base_command()
+            .args([
+                "get",
+                "aa",
+                "--format",
+                "qr",
+                "--qr-ec-level",
+                "m",
+                "--database",
+            ])
+            .arg(&db_path)
+            .assert()
+            .success();
+    }
+
     #[test]
     fn test_list() {
+        let (_repo, temp_dir) = seeded_repo(&[("aa", "https://example.com")]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = list(&db_path);
+        cmd.assert().success().stdout("aa\n");
+    }
+
+    #[test]
+    fn test_search() {
+        let (_repo, temp_dir) = seeded_repo(&[
+            ("ab-1", "https://example.com"),
+            ("ab-22", "https://example.com"),
+            ("xab", "https://example.com"),
+        ]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = base_command();
+        cmd.args(["search", "--glob", "AB-*", "--database"])
+            .arg(&db_path);
+        cmd.assert().success().stdout("ab-1\nab-22\n");
+
+        let mut cmd = base_command();
+        cmd.args(["search", "--regex", "^ab-[0-9]$", "--database"])
+            .arg(&db_path);
+        cmd.assert().success().stdout("ab-1\n");
+
+        let mut cmd = base_command();
+        cmd.args(["search", "--regex", "(", "--database"])
+            .arg(&db_path);
+        cmd.assert().failure();
+    }
+
+    #[test]
+    fn test_find_host() {
+        let (_repo, temp_dir) = seeded_repo(&[
+            ("aa", "https://example.com/a"),
+            ("bb", "https://cdn.example.com/b"),
+            ("cc", "https://example.org"),
+        ]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = base_command();
+        cmd.args(["find-host", "example.com", "--database"])
+            .arg(&db_path);
+        cmd.assert()
+            .success()
+            .stdout("aa\thttps://example.com/a\nbb\thttps://cdn.example.com/b\n");
+    }
+
+    #[test]
+    fn test_tag() {
+        let (_repo, temp_dir) =
+            seeded_repo(&[("aa", "https://example.com"), ("bb", "https://example.com")]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = base_command();
+        cmd.args(["tag", "aa", "work", "docs", "--database"])
+            .arg(&db_path);
+        cmd.assert().success();
+
+        let mut cmd = list(&db_path);
+        cmd.arg("--tag").arg("work");
+        cmd.assert().success().stdout("aa\n");
+
+        let mut cmd = list(&db_path);
+        cmd.arg("--tag").arg("none");
+        cmd.assert().success().stdout("");
+
+        let mut cmd = base_command();
+        cmd.args(["tag", "cc", "work", "--database"]).arg(&db_path);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("url not found"));
+    }
+
+    #[test]
+    fn test_touch() {
+        let (_repo, temp_dir) = seeded_repo(&[("aa", "https://example.com")]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = base_command();
+        cmd.args(["touch", "aa", "--database"]).arg(&db_path);
+        cmd.assert().success().stderr("url touched\n");
+
+        let mut cmd = base_command();
+        cmd.args(["touch", "bb", "--database"]).arg(&db_path);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("url not found"));
+    }
+
+    #[test]
+    fn test_doctor() {
+        let (_repo, temp_dir) = seeded_repo(&[("aa", "https://example.com")]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = base_command();
+        cmd.args(["doctor", "--database"]).arg(&db_path);
+        cmd.assert()
+            .success()
+            .stderr("0 WAL frames checkpointed\nintegrity check: ok\n");
+    }
+
+    #[test]
+    fn test_alias() {
+        let (_repo, temp_dir) = seeded_repo(&[("aa", "https://example.com")]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = base_command();
+        cmd.args(["alias", "bb", "aa", "--database"]).arg(&db_path);
+        cmd.assert().success().stderr("alias saved\n");
+        get(&db_path, &"bb".try_into().unwrap())
+            .assert()
+            .success()
+            .stdout("https://example.com/\n");
+
+        let mut cmd = base_command();
+        cmd.args(["alias", "cc", "bb", "--database"]).arg(&db_path);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("short URL bb not found"));
+        let mut cmd = base_command();
+        cmd.args(["alias", "aa", "bb", "--database"]).arg(&db_path);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("aa is a short URL"));
+
+        // Deleting the alias keeps the short URL it points at
+        let mut cmd = base_command();
+        cmd.args(["delete", "bb", "--database"]).arg(&db_path);
+        cmd.assert().success();
+        get(&db_path, &"bb".try_into().unwrap()).assert().failure();
+        get(&db_path, &"aa".try_into().unwrap())
+            .assert()
+            .success()
+            .stdout("https://example.com/\n");
+    }
+
+    #[test]
+    fn test_list_color() {
+        let (_repo, temp_dir) = seeded_repo(&[("aa", "https://example.com")]);
+        let db_path = database_path(&temp_dir);
+
+        // Not a terminal, so no escape codes
+        let mut cmd = list(&db_path);
+        cmd.arg("--color").arg("auto");
+        cmd.assert().success().stdout("aa\n");
+
+        let mut cmd = list(&db_path);
+        cmd.arg("--color").arg("always");
+        cmd.assert().success().stdout("\x1b[1maa\x1b[0m\n");
+
+        let mut cmd = list(&db_path);
+        cmd.arg("--color").arg("never");
+        cmd.assert().success().stdout("aa\n");
+    }
+
+    #[test]
+    fn test_list_prefix() {
+        let (_repo, temp_dir) = seeded_repo(&[
+            ("ab-1", "https://example.com"),
+            ("ab-2", "https://example.com"),
+            ("abc", "https://example.com"),
+        ]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = list(&db_path);
+        cmd.arg("--prefix").arg("ab-");
+        cmd.assert().success().stdout("ab-1\nab-2\n");
+    }
+
+    #[test]
+    fn test_recent() {
         let temp_dir = tempdir().expect("Failed to create temp dir");
         let db_path = temp_dir.path().join("test.db");
-        assert!(!db_path.exists());
 
         let name = "aa".try_into().unwrap();
         let url: Url = "https://example.com".try_into().unwrap();
         let mut repo = open_writable_repository(&db_path).unwrap();
         repo.migrate().unwrap();
         repo.insert_url(&name, &url).unwrap();
+        let short_url = repo.get_url(&name).unwrap().unwrap();
+        let iso8601 = short_url.last_modified.unwrap().iso8601().unwrap();
 
-        let mut cmd = list(&db_path);
-        cmd.assert().success().stdout(format!("{name}\n"));
+        let mut cmd = recent(&db_path);
+        cmd.arg("--limit").arg("1");
+        cmd.assert()
+            .success()
+            .stdout(format!("{name} -> {url} ({iso8601})\n"));
+    }
+
+    #[test]
+    fn test_watch() {
+        use std::io::BufRead as _;
+
+        let (mut repo, temp_dir) = seeded_repo(&[("aa", "https://example.com")]);
+        let db_path = database_path(&temp_dir);
+        // Rows of the second it starts in are printed, so start after it
+        std::thread::sleep(core::time::Duration::from_millis(1100));
+        let program = base_command().get_program().to_owned();
+        let mut child = std::process::Command::new(program)
+            .args(["watch", "--interval", "50ms", "--database"])
+            .arg(&db_path)
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::BufReader::new(stdout).lines() {
+                let _ = tx.send(line.unwrap());
+            }
+        });
+
+        std::thread::sleep(core::time::Duration::from_millis(300));
+        repo.insert_url(
+            &"bb".try_into().unwrap(),
+            &"https://example.org".try_into().unwrap(),
+        )
+        .unwrap();
+        let first = rx.recv_timeout(core::time::Duration::from_secs(5));
+        std::thread::sleep(core::time::Duration::from_millis(300));
+        repo.insert_url(
+            &"cc".try_into().unwrap(),
+            &"https://example.net".try_into().unwrap(),
+        )
+        .unwrap();
+        let second = rx.recv_timeout(core::time::Duration::from_secs(5));
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(
+            first
+                .as_deref()
+                .is_ok_and(|x| x.starts_with("bb -> https://example.org/ (")),
+            "{first:?}"
+        );
+        assert!(
+            second
+                .as_deref()
+                .is_ok_and(|x| x.starts_with("cc -> https://example.net/ (")),
+            "{second:?}"
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_recent_timezone() {
+        let (_repo, temp_dir) = seeded_repo(&[("aa", "https://example.com")]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = recent(&db_path);
+        cmd.args(["--timezone", "Asia/Kolkata"]);
+        cmd.assert()
+            .success()
+            .stdout(predicates::str::ends_with("+05:30)\n"));
+
+        let mut cmd = recent(&db_path);
+        cmd.assert()
+            .success()
+            .stdout(predicates::str::ends_with("Z)\n"));
+
+        let mut cmd = recent(&db_path);
+        cmd.args(["--timezone", "Mars/Olympus_Mons"]);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("failed to parse timezone"));
+    }
+
+    #[test]
+    fn test_recent_within() {
+        let (_repo, temp_dir) = seeded_repo(&[("aa", "https://example.com")]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = recent(&db_path);
+        cmd.arg("--within").arg("10m");
+        cmd.assert()
+            .success()
+            .stdout(predicates::str::starts_with("aa -> https://example.com/ ("));
+
+        let mut cmd = recent(&db_path);
+        cmd.arg("--within").arg("10x");
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("invalid unit"));
+
+        let mut cmd = recent(&db_path);
+        cmd.args(["--within", "1h", "--limit", "1"]);
+        cmd.assert().failure();
     }
 
     #[test]
@@ -150,4 +894,775 @@ mod test {
         );
         cmd.assert().success().stdout(expected);
     }
+
+    #[test]
+    fn test_export_tsv() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+
+        let name = "aa".try_into().unwrap();
+        let url: Url = "https://example.com/?a=1,2".try_into().unwrap();
+        let mut repo = open_writable_repository(&db_path).unwrap();
+        repo.migrate().unwrap();
+        repo.insert_url(&name, &url).unwrap();
+        let short_url = repo.get_url(&name).unwrap().unwrap();
+        let last_modified = short_url.last_modified.unwrap();
+
+        let mut cmd = export(&db_path);
+        cmd.arg("--format").arg("tsv");
+        cmd.assert().success().stdout(format!(
+            "shorturl\turl\tlast_modified\r\naa\t{url}\t{last_modified}\r\n"
+        ));
+
+        let mut cmd = export(&db_path);
+        cmd.arg("--format").arg("csv");
+        cmd.assert().success().stdout(format!(
+            "shorturl,url,last_modified\r\naa,\"{url}\",{last_modified}\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_export_json_pretty() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+
+        let name = "aa".try_into().unwrap();
+        let url: Url = "https://example.com".try_into().unwrap();
+        let mut repo = open_writable_repository(&db_path).unwrap();
+        repo.migrate().unwrap();
+        repo.insert_url(&name, &url).unwrap();
+        let last_modified = repo.get_url(&name).unwrap().unwrap().last_modified.unwrap();
+
+        let mut cmd = export(&db_path);
+        cmd.arg("--format").arg("json");
+        cmd.assert().success().stdout(format!(
+            "[{{\"name\":\"aa\",\"url\":\"{url}\",\"last_modified\":{last_modified}}}]\n"
+        ));
+
+        let mut cmd = export(&db_path);
+        cmd.arg("--format").arg("json").arg("--pretty");
+        let output = cmd.assert().success().get_output().stdout.clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.trim_end().contains('\n'));
+        let name_pos = output.find("\"name\"").unwrap();
+        let url_pos = output.find("\"url\"").unwrap();
+        let last_modified_pos = output.find("\"last_modified\"").unwrap();
+        assert!(name_pos < url_pos && url_pos < last_modified_pos);
+    }
+
+    #[test]
+    fn test_set_title_export() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+        let titled = "aa".try_into().unwrap();
+        let untitled = "bb".try_into().unwrap();
+        let url: Url = "https://example.com".try_into().unwrap();
+        set(&db_path, &titled, &url)
+            .args(["--title", "Example, Inc."])
+            .assert()
+            .success();
+        set(&db_path, &untitled, &url).assert().success();
+
+        let repo = open_readonly_repository(&db_path).unwrap();
+        let aa = repo.get_url(&titled).unwrap().unwrap();
+        assert_eq!(aa.title.as_deref(), Some("Example, Inc."));
+        let bb = repo.get_url(&untitled).unwrap().unwrap();
+        assert_eq!(bb.title, None);
+        let (aa_modified, bb_modified) = (aa.last_modified.unwrap(), bb.last_modified.unwrap());
+
+        export(&db_path).assert().success().stdout(format!(
+            "shorturl,url,last_modified\r\n\
+             aa,{url},{aa_modified}\r\n\
+             bb,{url},{bb_modified}\r\n"
+        ));
+        export(&db_path)
+            .arg("--with-title")
+            .assert()
+            .success()
+            .stdout(format!(
+                "shorturl,url,last_modified,title\r\n\
+                 aa,{url},{aa_modified},\"Example, Inc.\"\r\n\
+                 bb,{url},{bb_modified},\r\n"
+            ));
+
+        let output = export(&db_path)
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        assert!(!String::from_utf8(output).unwrap().contains("title"));
+        export(&db_path)
+            .args(["--format", "json", "--with-title"])
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("\"title\":\"Example, Inc.\""));
+    }
+
+    fn import(db_path: &PathBuf) -> assert_cmd::Command {
+        let mut cmd = base_command();
+        cmd.arg("import");
+        cmd.arg("--database");
+        cmd.arg(db_path);
+        cmd
+    }
+
+    fn assert_imported(db_path: &PathBuf) {
+        let repo = open_readonly_repository(db_path).expect("Failed to open database");
+        for (name, url) in [
+            ("aa", "https://example.com/a"),
+            ("bb", "https://example.com/b"),
+        ] {
+            let name = ShortUrlName::try_from(name).expect("Invalid name");
+            let short_url = repo
+                .get_url(&name)
+                .expect("Failed to get url")
+                .expect("Url not imported");
+            assert_eq!(short_url.url.to_string(), url);
+        }
+    }
+
+    #[test]
+    fn test_import_detect_csv() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+
+        let mut cmd = import(&db_path);
+        cmd.write_stdin(
+            "shorturl,url,last_modified\r\n\
+             aa,https://example.com/a,1\r\n\
+             bb,https://example.com/b,\r\n",
+        );
+        cmd.assert().success();
+        assert_imported(&db_path);
+    }
+
+    #[test]
+    fn test_import_detect_ndjson() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+
+        let mut cmd = import(&db_path);
+        cmd.write_stdin(
+            "\n{\"name\":\"aa\",\"url\":\"https://example.com/a\"}\n\
+             {\"name\":\"bb\",\"url\":\"https://example.com/b\",\"last_modified\":null}\n",
+        );
+        cmd.assert().success();
+        assert_imported(&db_path);
+    }
+
+    #[test]
+    fn test_import_title() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+
+        let mut cmd = import(&db_path);
+        cmd.write_stdin(
+            "shorturl,url,last_modified,title\r\n\
+             aa,https://example.com/a,1,\"Example, Inc.\"\r\n\
+             bb,https://example.com/b,,\r\n",
+        );
+        cmd.assert().success();
+        let mut cmd = import(&db_path);
+        cmd.write_stdin("{\"name\":\"cc\",\"url\":\"https://example.com/c\",\"title\":\"C\"}\n");
+        cmd.assert().success();
+
+        let repo = open_readonly_repository(&db_path).expect("Failed to open database");
+        let title = |name: &str| {
+            repo.get_url(&name.try_into().unwrap())
+                .unwrap()
+                .unwrap()
+                .title
+        };
+        assert_eq!(title("aa").as_deref(), Some("Example, Inc."));
+        assert_eq!(title("bb"), None);
+        assert_eq!(title("cc").as_deref(), Some("C"));
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let (mut repo, source_dir) = seeded_repo(&[
+            ("aa", "https://example.com/a"),
+            ("bb", "https://example.com/b"),
+        ]);
+        repo.set_title(&"aa".try_into().unwrap(), Some("Example, Inc.\tA"))
+            .unwrap();
+        let source = database_path(&source_dir);
+
+        for args in [
+            &["--format", "csv"][..],
+            &["--format", "tsv"],
+            &["--format", "json"],
+            &["--format", "json", "--pretty"],
+        ] {
+            for with_title in [false, true] {
+                let mut cmd = export(&source);
+                cmd.args(args);
+                if with_title {
+                    cmd.arg("--with-title");
+                }
+                let output = cmd.assert().success().get_output().stdout.clone();
+
+                let (_repo, temp_dir) = seeded_repo(&[]);
+                let db_path = database_path(&temp_dir);
+                let mut cmd = import(&db_path);
+                cmd.write_stdin(output);
+                cmd.assert().success();
+                assert_imported(&db_path);
+
+                let repo = open_readonly_repository(&db_path).expect("Failed to open database");
+                let title = repo
+                    .get_url(&"aa".try_into().unwrap())
+                    .unwrap()
+                    .unwrap()
+                    .title;
+                let expected = with_title.then_some("Example, Inc.\tA");
+                assert_eq!(title.as_deref(), expected, "{args:?} {with_title}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_import_unknown_format() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+
+        let input = "aa,https://example.com/a\n";
+        let mut cmd = import(&db_path);
+        cmd.write_stdin(input);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("cannot detect input format"));
+
+        // Forcing CSV treats the first line as the header, leaving no rows
+        let mut cmd = import(&db_path);
+        cmd.arg("--format").arg("csv").write_stdin(input);
+        cmd.assert().failure().stderr(predicates::str::contains(
+            "no rows found in input with the given --format",
+        ));
+    }
+
+    #[test]
+    fn test_import_rolls_back() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+        let conn = shorty::rusqlite::Connection::open(&db_path).unwrap();
+        conn.execute_batch(
+            "CREATE TRIGGER fail_cd BEFORE INSERT ON urls WHEN NEW.shorturl = 'cd' \
+             BEGIN SELECT RAISE(ABORT, 'no cd'); END",
+        )
+        .unwrap();
+        drop(conn);
+
+        let input = "shorturl,url,last_modified\n\
+                     ab,https://example.com/ab,\n\
+                     cd,https://example.com/cd,\n";
+        let mut cmd = import(&db_path);
+        cmd.write_stdin(input);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("no cd"));
+
+        // The row inserted before the failure is rolled back
+        let repo = open_readonly_repository(&db_path).unwrap();
+        assert!(repo.get_url(&"ab".try_into().unwrap()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_import_on_duplicate() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+        let input = "shorturl,url,last_modified\n\
+                     AB,https://example.com/first,\n\
+                     cd,https://example.com/cd,\n\
+                     ab,https://example.com/last,\n";
+        let stored = || {
+            let repo = open_readonly_repository(&db_path).expect("Failed to open database");
+            repo.get_url(&"ab".try_into().unwrap())
+                .expect("Failed to get url")
+                .map(|x| x.url.to_string())
+        };
+
+        let mut cmd = import(&db_path);
+        cmd.args(["--on-duplicate", "error"]).write_stdin(input);
+        cmd.assert().failure().stderr(predicates::str::contains(
+            "duplicate name ab in input, also given as AB",
+        ));
+        assert_eq!(stored(), None);
+
+        let mut cmd = import(&db_path);
+        cmd.args(["--on-duplicate", "first"]).write_stdin(input);
+        cmd.assert()
+            .success()
+            .stderr("duplicate ab -> https://example.com/last skipped\n2 urls imported\n");
+        assert_eq!(stored().as_deref(), Some("https://example.com/first"));
+
+        // Last is the default
+        let mut cmd = import(&db_path);
+        cmd.write_stdin(input);
+        cmd.assert()
+            .success()
+            .stderr("duplicate AB -> https://example.com/first skipped\n2 urls imported\n");
+        assert_eq!(stored().as_deref(), Some("https://example.com/last"));
+    }
+
+    #[test]
+    fn test_shell() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+
+        let mut cmd = base_command();
+        cmd.arg("shell").arg("--database").arg(&db_path);
+        cmd.write_stdin(
+            "set aa https://example.com/a\n\
+             set bb https://example.com/b\n\
+             \n\
+             get AA\n\
+             bogus\n\
+             delete bb\n\
+             get bb\n\
+             list\n\
+             exit\n\
+             list\n",
+        );
+        cmd.assert()
+            .success()
+            .stdout("https://example.com/a\naa\n")
+            .stderr(predicates::str::contains("unrecognized subcommand 'bogus'"))
+            .stderr(predicates::str::contains("url deleted"))
+            .stderr(predicates::str::contains("error: url not found"));
+    }
+
+    #[test]
+    fn test_delete_from_file() {
+        let (_repo, temp_dir) = seeded_repo(&[
+            ("aa", "https://example.com/a"),
+            ("bb", "https://example.com/b"),
+            ("cc", "https://example.com/c"),
+        ]);
+        let db_path = database_path(&temp_dir);
+        let names_path = temp_dir.path().join("names.txt");
+        std::fs::write(&names_path, "# cleanup\naa\n\nBB\nzz\nb\n").unwrap();
+
+        let mut cmd = base_command();
+        cmd.args(["delete", "--from-file"])
+            .arg(&names_path)
+            .arg("--database")
+            .arg(&db_path);
+        cmd.assert()
+            .success()
+            .stderr(predicates::str::contains(
+                ":6: short URL name \"b\" is too short",
+            ))
+            .stderr(predicates::str::contains("2 urls deleted, 1 lines skipped"));
+
+        list(&db_path).assert().success().stdout("cc\n");
+
+        let mut cmd = base_command();
+        cmd.args(["delete", "cc", "--database"]).arg(&db_path);
+        cmd.assert().success().stderr("url deleted\n");
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("url not found"));
+    }
+
+    #[test]
+    fn test_reserved_name() {
+        let (_repo, temp_dir) = seeded_repo(&[("aa", "https://example.com/a")]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = base_command();
+        cmd.args(["set", "random", "https://example.com/", "--database"])
+            .arg(&db_path);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("\"random\" is reserved"));
+
+        // Stored before the name was reserved
+        shorty::rusqlite::Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO urls (shorturl, url) VALUES ('random', 'https://example.com/r')",
+            )
+            .unwrap();
+        list(&db_path).assert().success().stdout("aa\nrandom\n");
+        let mut cmd = base_command();
+        cmd.args(["delete", "random", "--database"]).arg(&db_path);
+        cmd.assert().success().stderr("url deleted\n");
+    }
+
+    #[test]
+    fn test_trash() {
+        let (_repo, temp_dir) = seeded_repo(&[
+            ("aa", "https://example.com/a"),
+            ("bb", "https://example.com/b"),
+        ]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = base_command();
+        cmd.args(["shell", "--trash", "--database"]).arg(&db_path);
+        cmd.write_stdin("delete aa\ndelete bb\n");
+        cmd.assert().success();
+
+        let mut cmd = base_command();
+        cmd.args(["trash", "list", "--database"]).arg(&db_path);
+        cmd.assert()
+            .success()
+            .stdout(predicates::str::contains(
+                "aa -> https://example.com/a (deleted: ",
+            ))
+            .stdout(predicates::str::contains(
+                "bb -> https://example.com/b (deleted: ",
+            ));
+
+        let mut cmd = base_command();
+        cmd.args(["trash", "list", "--timezone", "Asia/Kolkata", "--database"])
+            .arg(&db_path);
+        cmd.assert()
+            .success()
+            .stdout(predicates::str::ends_with("+05:30)\n"));
+
+        let mut cmd = base_command();
+        cmd.args(["restore", "AA", "--database"]).arg(&db_path);
+        cmd.assert().success().stderr("url restored\n");
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("url not found in trash"));
+        get(&db_path, &"aa".try_into().unwrap())
+            .assert()
+            .success()
+            .stdout("https://example.com/a\n");
+
+        // Deleted just now, so kept by default
+        let mut cmd = base_command();
+        cmd.args(["trash", "empty", "--database"]).arg(&db_path);
+        cmd.assert().success().stderr("0 urls removed\n");
+
+        let mut cmd = base_command();
+        cmd.args(["trash", "empty", "--older-than", "0s", "--database"])
+            .arg(&db_path);
+        cmd.assert().success().stderr("1 urls removed\n");
+
+        let mut cmd = base_command();
+        cmd.args(["trash", "list", "--database"]).arg(&db_path);
+        cmd.assert().success().stdout("");
+    }
+
+    #[test]
+    fn test_stats_by_host() {
+        let (_repo, temp_dir) = seeded_repo(&[
+            ("aa", "https://b.example.com/1"),
+            ("bb", "https://a.example.com/1"),
+            ("cc", "https://b.example.com/2"),
+            ("dd", "http://c.example.com/"),
+        ]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = base_command();
+        cmd.arg("stats").arg("--database").arg(&db_path);
+        cmd.assert().success().stdout("4\n");
+
+        cmd.arg("--by-host");
+        cmd.assert()
+            .success()
+            .stdout("2\tb.example.com\n1\ta.example.com\n1\tc.example.com\n");
+
+        cmd.arg("--format").arg("json");
+        cmd.assert().success().stdout(concat!(
+            r#"[{"host":"b.example.com","count":2},"#,
+            r#"{"host":"a.example.com","count":1},"#,
+            r#"{"host":"c.example.com","count":1}]"#,
+            "\n"
+        ));
+    }
+
+    #[test]
+    fn test_quote_add() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+
+        let mut cmd = base_command();
+        cmd.args(["quote", "add", "Don't panic", "--database"])
+            .arg(&db_path);
+        cmd.assert().success().stderr("quote saved\n");
+        cmd.assert().success().stderr("quote already exists\n");
+
+        let repo = open_readonly_repository(&db_path).unwrap();
+        assert_eq!(repo.get_random_quote().unwrap(), "Don't panic");
+    }
+
+    #[test]
+    fn test_quote_import() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+        let lines = temp_dir.path().join("lines.txt");
+        std::fs::write(&lines, "Don't panic\n\n  Mostly harmless \nDon't panic\n").unwrap();
+
+        let mut cmd = base_command();
+        cmd.args(["quote", "import", "--collection", "adams"])
+            .arg(&lines)
+            .arg("--database")
+            .arg(&db_path);
+        cmd.assert()
+            .success()
+            .stderr("2 quotes imported, 1 already existed\n");
+        cmd.assert()
+            .success()
+            .stderr("0 quotes imported, 3 already existed\n");
+
+        let blocks = temp_dir.path().join("blocks.txt");
+        std::fs::write(
+            &blocks,
+            "Roses are red,\nviolets are blue\n---\n\n---\nMostly harmless\n",
+        )
+        .unwrap();
+        let mut cmd = base_command();
+        cmd.args(["quote", "import"])
+            .arg(&blocks)
+            .arg("--database")
+            .arg(&db_path);
+        cmd.assert()
+            .success()
+            .stderr("2 quotes imported, 0 already existed\n");
+
+        let mut repo = open_writable_repository(&db_path).unwrap();
+        let mut quotes: Vec<String> = repo
+            .transaction(|tx| {
+                let mut stmt =
+                    tx.prepare("SELECT quote FROM quotations WHERE collection = 'default'")?;
+                let quotes = stmt
+                    .query_map([], |row| row.get(0))?
+                    .collect::<Result<_, _>>()?;
+                Ok(quotes)
+            })
+            .unwrap();
+        quotes.sort();
+        assert_eq!(
+            quotes,
+            ["Mostly harmless", "Roses are red,\nviolets are blue"]
+        );
+    }
+
+    #[test]
+    fn test_clear() {
+        let (mut repo, temp_dir) = seeded_repo(&[
+            ("aa", "https://example.com/a"),
+            ("bb", "https://example.com/b"),
+        ]);
+        repo.insert_quotation("Mostly harmless").unwrap();
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = base_command();
+        cmd.arg("clear").arg("--database").arg(&db_path);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("without --yes"));
+        assert!(repo.get_url(&"aa".try_into().unwrap()).unwrap().is_some());
+
+        let mut cmd = base_command();
+        cmd.args(["clear", "--yes", "--database"]).arg(&db_path);
+        cmd.assert().success().stderr("2 urls removed\n");
+        assert!(repo.get_url(&"aa".try_into().unwrap()).unwrap().is_none());
+        assert_eq!(repo.get_random_quote().unwrap(), "Mostly harmless");
+
+        let mut cmd = base_command();
+        cmd.args(["clear", "--yes", "--include-quotes", "--database"])
+            .arg(&db_path);
+        cmd.assert()
+            .success()
+            .stderr("0 urls removed\n1 quotes removed\n");
+    }
+
+    #[test]
+    fn test_qr_export() {
+        let (_repo, temp_dir) = seeded_repo(&[
+            ("aa", "https://example.com/a"),
+            ("bb", "https://example.com/b"),
+            ("cc", "https://example.com/c"),
+        ]);
+        let db_path = database_path(&temp_dir);
+        let output_dir = temp_dir.path().join("qr");
+
+        let mut cmd = base_command();
+        cmd.args([
+            "qr-export",
+            "--base-url",
+            "https://sho.rt/",
+            "--names",
+            "aa,BB",
+        ])
+        .arg("--output-dir")
+        .arg(&output_dir)
+        .arg("--database")
+        .arg(&db_path);
+        cmd.assert()
+            .success()
+            .stderr(predicates::str::contains("2 QR codes written"));
+
+        for name in ["aa", "bb"] {
+            let svg = std::fs::read_to_string(output_dir.join(format!("{name}.svg"))).unwrap();
+            assert!(svg.starts_with("<?xml"));
+            assert!(svg.contains("<svg"));
+        }
+        assert!(!output_dir.join("cc.svg").exists());
+
+        let mut cmd = base_command();
+        cmd.args([
+            "qr-export",
+            "--base-url",
+            "https://sho.rt/",
+            "--names",
+            "zz",
+        ])
+        .arg("--output-dir")
+        .arg(&output_dir)
+        .arg("--database")
+        .arg(&db_path);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("url not found: zz"));
+
+        let mut cmd = base_command();
+        cmd.args(["qr-export", "--base-url", "https://sho.rt/"])
+            .arg("--output-dir")
+            .arg(&output_dir)
+            .arg("--database")
+            .arg(&db_path);
+        cmd.assert()
+            .success()
+            .stderr(predicates::str::contains("3 QR codes written"));
+        assert!(output_dir.join("cc.svg").exists());
+    }
+
+    #[test]
+    fn test_verbose_trace() {
+        use predicates::prelude::PredicateBooleanExt as _;
+
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        let name: ShortUrlName = "aa".try_into().unwrap();
+        migrate(&db_path);
+        set(&db_path, &name, &"https://example.com/".try_into().unwrap())
+            .assert()
+            .success();
+
+        get(&db_path, &name)
+            .assert()
+            .success()
+            .stderr(predicates::str::contains("SELECT").not());
+        get(&db_path, &name)
+            .arg("--verbose")
+            .assert()
+            .success()
+            .stderr(
+                predicates::str::is_match(r"(?m)^sql: \d+\.\d{3}ms SELECT .* FROM urls").unwrap(),
+            );
+        get(&db_path, &name)
+            .env("SHORTY_TRACE", "1")
+            .assert()
+            .success()
+            .stderr(predicates::str::contains("SELECT"));
+    }
+
+    #[test]
+    fn test_dump() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        let name: ShortUrlName = "aa".try_into().unwrap();
+        migrate(&db_path);
+        set(&db_path, &name, &"https://example.com/".try_into().unwrap())
+            .assert()
+            .success();
+
+        let mut cmd = base_command();
+        cmd.arg("dump").arg("--database").arg(&db_path);
+        let dump = cmd.assert().success().get_output().stdout.clone();
+        let dump = String::from_utf8(dump).unwrap();
+        assert!(dump.starts_with("BEGIN TRANSACTION;\n"));
+        assert!(dump.ends_with("COMMIT;\n"));
+
+        let copy_path = temp_dir.path().join("copy.db");
+        shorty::rusqlite::Connection::open(&copy_path)
+            .unwrap()
+            .execute_batch(&dump)
+            .unwrap();
+        get(&copy_path, &name)
+            .assert()
+            .success()
+            .stdout("https://example.com/\n");
+    }
+
+    #[test]
+    fn test_schema() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let db_path = temp_dir.path().join("test.db");
+        migrate(&db_path);
+
+        let mut cmd = base_command();
+        cmd.arg("schema").arg("--database").arg(&db_path);
+        let current = cmd.assert().success().get_output().stdout.clone();
+        let current = String::from_utf8(current).unwrap();
+        assert!(current.contains("CREATE TABLE urls ("));
+        assert!(current.contains(");\n"));
+
+        let mut cmd = base_command();
+        cmd.arg("schema").arg("--latest").env_remove("SHORTY_DB");
+        cmd.assert().success().stdout(current.clone());
+
+        // An exported database doesn't get in the way
+        let mut cmd = base_command();
+        cmd.arg("schema")
+            .arg("--latest")
+            .env("SHORTY_DB", temp_dir.path().join("missing.db"));
+        cmd.assert().success().stdout(current);
+
+        let mut cmd = base_command();
+        cmd.arg("schema").env_remove("SHORTY_DB");
+        cmd.assert().failure();
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let (_repo, temp_dir) = seeded_repo(&[
+            ("aa", "https://example.com/shared"),
+            ("bb", "https://example.com/shared"),
+            ("cc", "https://example.com/unique"),
+        ]);
+        let db_path = database_path(&temp_dir);
+
+        let mut cmd = base_command();
+        cmd.arg("duplicates").arg("--database").arg(&db_path);
+        cmd.assert()
+            .success()
+            .stdout("aa, bb -> https://example.com/shared\n");
+
+        cmd.arg("--format").arg("json");
+        let output = cmd.assert().success().get_output().stdout.clone();
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{"url": "https://example.com/shared", "names": ["aa", "bb"]}])
+        );
+    }
+
+    #[test]
+    fn test_completion_bash() {
+        let mut cmd = base_command();
+        cmd.arg("completion").arg("bash");
+        let output = cmd.assert().success().get_output().stdout.clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("complete -F _shorty"));
+        assert!(output.contains("shorty"));
+    }
 }
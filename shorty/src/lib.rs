@@ -1,4 +1,10 @@
+#[cfg(feature = "qr")]
+pub mod qr;
 pub mod repository;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
 pub mod types;
 pub use anyhow;
+pub use regex;
+pub use rusqlite;
 pub use url;
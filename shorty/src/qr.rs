@@ -0,0 +1,218 @@
+//! QR codes for short URLs, shared by the web and command line
+//! frontends.
+
+use core::{fmt, str::FromStr};
+
+use qrcode::{
+    render::{svg, unicode},
+    EcLevel, QrCode,
+};
+
+pub use qrcode::types::QrError;
+
+use crate::types::UrlPolicy;
+
+/// QR code error correction level.
+///
+/// Higher levels survive more damage (smudges, small prints), but need
+/// more modules for the same data, so the code gets denser and each
+/// module smaller at a fixed image size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum QrEcLevel {
+    /// Recovers about 7% of the code.
+    L,
+    /// Recovers about 15% of the code.
+    #[default]
+    M,
+    /// Recovers about 25% of the code.
+    Q,
+    /// Recovers about 30% of the code.
+    H,
+}
+
+impl QrEcLevel {
+    /// Most bytes of arbitrary data that fit the largest QR code at this
+    /// level.
+    #[must_use]
+    pub const fn max_len(self) -> usize {
+        match self {
+            Self::L => 2953,
+            Self::M => 2331,
+            Self::Q => 1663,
+            Self::H => 1273,
+        }
+    }
+}
+
+impl fmt::Display for QrEcLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A string that is none of `L`, `M`, `Q` and `H`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidQrEcLevel(pub String);
+
+impl fmt::Display for InvalidQrEcLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "QR code error correction level {:?} is not one of L, M, Q or H",
+            self.0
+        )
+    }
+}
+
+impl core::error::Error for InvalidQrEcLevel {}
+
+/// Parses `L`, `M`, `Q` or `H`, ignoring case.
+impl FromStr for QrEcLevel {
+    type Err = InvalidQrEcLevel;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "L" => Ok(Self::L),
+            "M" => Ok(Self::M),
+            "Q" => Ok(Self::Q),
+            "H" => Ok(Self::H),
+            _ => Err(InvalidQrEcLevel(s.to_string())),
+        }
+    }
+}
+
+impl UrlPolicy {
+    /// This policy with [`UrlPolicy::max_len`] lowered, if needed, so
+    /// that every URL it accepts fits a QR code at `ec_level`.
+    #[must_use]
+    pub fn fitting_qr(self, ec_level: QrEcLevel) -> Self {
+        let qr_max_len = ec_level.max_len();
+        Self {
+            max_len: Some(self.max_len.map_or(qr_max_len, |x| x.min(qr_max_len))),
+            ..self
+        }
+    }
+}
+
+impl From<QrEcLevel> for EcLevel {
+    fn from(value: QrEcLevel) -> Self {
+        match value {
+            QrEcLevel::L => Self::L,
+            QrEcLevel::M => Self::M,
+            QrEcLevel::Q => Self::Q,
+            QrEcLevel::H => Self::H,
+        }
+    }
+}
+
+/// Render `data` as a QR code in an SVG document.
+///
+/// # Errors
+///
+/// If `data` is too long for a QR code at `ec_level`.
+pub fn qr_svg<D>(data: D, ec_level: QrEcLevel) -> Result<String, QrError>
+where
+    D: AsRef<[u8]>,
+{
+    let code = QrCode::with_error_correction_level(data, ec_level.into())?;
+    let image = code
+        .render()
+        .min_dimensions(200, 200)
+        .dark_color(svg::Color("#000000"))
+        .light_color(svg::Color("#ffffff"))
+        .build();
+    Ok(image)
+}
+
+/// Render `data` as a QR code in Unicode half blocks, two modules per
+/// character, for printing to a terminal.
+///
+/// # Errors
+///
+/// If `data` is too long for a QR code at `ec_level`.
+pub fn qr_text<D>(data: D, ec_level: QrEcLevel) -> Result<String, QrError>
+where
+    D: AsRef<[u8]>,
+{
+    let code = QrCode::with_error_correction_level(data, ec_level.into())?;
+    Ok(code.render::<unicode::Dense1x2>().build())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn url(len: usize) -> String {
+        format!("https://example.com/{}", "a".repeat(len - 20))
+    }
+
+    #[test]
+    fn test_qr_fits_max_url_len() {
+        let url = url(UrlPolicy::DEFAULT_MAX_LEN);
+        assert!(qr_svg(&url, QrEcLevel::default()).is_ok());
+        assert!(qr_svg(&url, QrEcLevel::H).is_err());
+    }
+
+    #[test]
+    fn test_qr_ec_level_max_len() {
+        for level in [QrEcLevel::L, QrEcLevel::M, QrEcLevel::Q, QrEcLevel::H] {
+            assert!(qr_svg(url(level.max_len()), level).is_ok(), "{level}");
+            assert!(qr_svg(url(level.max_len() + 1), level).is_err(), "{level}");
+        }
+    }
+
+    #[test]
+    fn test_url_policy_fitting_qr() {
+        use crate::types::{InvalidUrl, Url};
+
+        // The default limit is below what fits at the default level
+        let policy = UrlPolicy::default().fitting_qr(QrEcLevel::default());
+        assert_eq!(policy, UrlPolicy::default());
+        assert!(Url::parse_with(&url(UrlPolicy::DEFAULT_MAX_LEN), policy).is_ok());
+
+        let policy = UrlPolicy::default().fitting_qr(QrEcLevel::H);
+        assert!(matches!(
+            Url::parse_with(&url(UrlPolicy::DEFAULT_MAX_LEN), policy),
+            Err(InvalidUrl::LengthExceeded { max_len: 1273, .. })
+        ));
+        let url = Url::parse_with(&url(1273), policy).unwrap();
+        assert!(qr_svg(url.to_string(), QrEcLevel::H).is_ok());
+
+        assert_eq!(
+            UrlPolicy::PERMISSIVE.fitting_qr(QrEcLevel::L).max_len,
+            Some(2953)
+        );
+    }
+
+    #[test]
+    fn test_qr_ec_level_from_str() {
+        assert_eq!("h".parse(), Ok(QrEcLevel::H));
+        assert_eq!("Q".parse(), Ok(QrEcLevel::Q));
+        assert_eq!(QrEcLevel::L.to_string(), "L");
+        let err = "x".parse::<QrEcLevel>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "QR code error correction level \"x\" is not one of L, M, Q or H"
+        );
+    }
+
+    #[test]
+    fn test_qr_svg_ec_level() {
+        let m = qr_svg("https://example.com/abc", QrEcLevel::M).unwrap();
+        let h = qr_svg("https://example.com/abc", QrEcLevel::H).unwrap();
+        assert!(h.starts_with("<?xml"));
+        assert!(h.contains("<svg"));
+        assert_ne!(m, h);
+    }
+
+    #[test]
+    fn test_qr_text() {
+        let text = qr_text("https://example.com/abc", QrEcLevel::M).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        // Two rows of modules per line
+        assert_eq!(lines.len(), lines[0].chars().count().div_ceil(2));
+        assert!(text
+            .chars()
+            .all(|c| " \u{2580}\u{2584}\u{2588}\n".contains(c)));
+    }
+}
@@ -0,0 +1,5 @@
+-- sqlfluff:dialect:sqlite
+
+DROP TRIGGER delete_tags_on_delete;
+DROP INDEX tags_tag;
+DROP TABLE tags;
@@ -0,0 +1,16 @@
+-- sqlfluff:dialect:sqlite
+
+CREATE TABLE tags (
+    shorturl TEXT NOT NULL COLLATE nocase,
+    tag TEXT NOT NULL COLLATE nocase,
+    PRIMARY KEY (shorturl, tag)
+) STRICT;
+
+CREATE INDEX tags_tag ON tags (tag);
+
+CREATE TRIGGER delete_tags_on_delete
+AFTER DELETE ON urls
+FOR EACH ROW
+BEGIN
+DELETE FROM tags WHERE shorturl = old.shorturl;
+END;
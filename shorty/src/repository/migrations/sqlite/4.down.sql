@@ -0,0 +1,4 @@
+-- sqlfluff:dialect:sqlite
+
+ALTER TABLE urls
+DROP COLUMN title;
@@ -0,0 +1,5 @@
+-- sqlfluff:dialect:sqlite
+
+DELETE FROM urls WHERE deleted_at IS NOT NULL;
+ALTER TABLE urls
+DROP COLUMN deleted_at;
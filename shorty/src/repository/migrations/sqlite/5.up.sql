@@ -0,0 +1,4 @@
+-- sqlfluff:dialect:sqlite
+
+ALTER TABLE urls
+ADD COLUMN deleted_at INTEGER;
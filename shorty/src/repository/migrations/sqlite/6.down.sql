@@ -0,0 +1,6 @@
+-- sqlfluff:dialect:sqlite
+
+DROP TRIGGER reject_alias_as_url;
+DROP TRIGGER delete_aliases_on_delete;
+DROP INDEX aliases_shorturl;
+DROP TABLE aliases;
@@ -0,0 +1,25 @@
+-- sqlfluff:dialect:sqlite
+
+CREATE TABLE aliases (
+    alias TEXT PRIMARY KEY COLLATE nocase,
+    shorturl TEXT NOT NULL COLLATE nocase
+    CHECK (LENGTH(alias) >= 2)
+    CHECK (LENGTH(alias) <= 16)
+) STRICT;
+
+CREATE INDEX aliases_shorturl ON aliases (shorturl);
+
+CREATE TRIGGER delete_aliases_on_delete
+AFTER DELETE ON urls
+FOR EACH ROW
+BEGIN
+DELETE FROM aliases WHERE shorturl = old.shorturl;
+END;
+
+CREATE TRIGGER reject_alias_as_url
+BEFORE INSERT ON urls
+FOR EACH ROW
+WHEN EXISTS (SELECT 1 FROM aliases WHERE alias = new.shorturl)
+BEGIN
+SELECT RAISE(ABORT, 'name is an alias');
+END;
@@ -1,13 +1,233 @@
-use crate::types::{ShortUrl, ShortUrlName, Url};
+use core::{cell::RefCell, fmt, time::Duration};
 
+use crate::types::{ShortUrl, ShortUrlName, UnixTimestamp, Url};
+
+pub mod replicated;
 pub mod sqlite;
 
+/// Errors with a meaning beyond "the database failed".
+///
+/// Returned wrapped in `anyhow::Error`; use `downcast_ref` to inspect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepositoryError {
+    /// The operation was aborted because its deadline passed.
+    Interrupted,
+    /// The database schema is missing; run the migrations first.
+    Migration,
+    /// Another connection kept the database locked through all retries.
+    Locked,
+    /// The database schema is newer than this binary knows; a newer
+    /// version was deployed, so leave the database alone.
+    SchemaAhead,
+}
+
+/// How the database schema compares to the migrations of this binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    /// All migrations have been run, and no others.
+    UpToDate,
+    /// Some migrations have not been run yet.
+    Pending,
+    /// Migrated by a newer binary, with migrations this one lacks.
+    Ahead,
+}
+
+/// How [`Repository::content_version`] tells datasets apart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContentVersionMethod {
+    /// Number of short URLs and newest modification time, read from one
+    /// row. Misses a change that keeps both, e.g. one name deleted and
+    /// another added within the same second.
+    #[default]
+    Summary,
+    /// Hash of every name, URL and modification time. Catches every
+    /// change to those columns, but reads the whole table.
+    Hash,
+}
+
+/// How long to keep trying when the database is locked by another
+/// connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusyRetry {
+    /// Attempts after the first one.
+    pub retries: u32,
+    /// How long the first attempt waits for the lock. Each retry waits
+    /// twice as long as the one before.
+    pub timeout: Duration,
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Interrupted => write!(f, "Database operation interrupted"),
+            Self::Migration => write!(f, "Database not migrated"),
+            Self::Locked => write!(f, "Could not acquire exclusive lock on the database"),
+            Self::SchemaAhead => write!(f, "Database schema is newer than this binary"),
+        }
+    }
+}
+
+impl core::error::Error for RepositoryError {}
+
+/// Outcome of [`Repository::get_or_404_status`], one variant per
+/// response a web front end has to give.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrlStatus {
+    /// Stored under exactly the requested spelling.
+    Found(ShortUrl),
+    /// Stored under a differently cased name; redirect to it.
+    OtherCase(ShortUrl),
+    /// No such name: 404.
+    NotFound,
+}
+
+/// Which names [`Repository::find_by_pattern`] selects.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// The whole name matches, ignoring case. `*` matches any run of
+    /// characters and `?` a single one. Translated to `LIKE`.
+    Glob(String),
+    /// Some part of the name matches, like `grep`. Case sensitive unless
+    /// the regex starts with `(?i)`. Tested on every row, so it is
+    /// slower than a glob.
+    Regex(regex::Regex),
+}
+
+/// Order of the items of a [`Page`] from [`Repository::search`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchSort {
+    #[default]
+    Name,
+    /// Most recently modified first. Rows without a timestamp sort
+    /// last.
+    Recent,
+}
+
+/// Filters and paging for [`Repository::search`]. A short URL must
+/// match every filter that is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// The name starts with this, ignoring case.
+    pub prefix: Option<String>,
+    /// Tagged with this, ignoring case.
+    pub tag: Option<String>,
+    /// The target's host is this or a subdomain of it, ignoring case.
+    pub host: Option<String>,
+    /// At most this many items, all of them if `None`.
+    pub limit: Option<u64>,
+    /// Skip this many matches.
+    pub offset: u64,
+    pub sort: SearchSort,
+}
+
+/// One page of [`Repository::search`] results.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Page {
+    pub items: Vec<ShortUrl>,
+    /// Matches on all pages, for computing the number of pages.
+    pub total: u64,
+}
+
+/// Changes [`Repository::resolve`] makes to the stored target URL, in
+/// the order of the fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResolveOpts<'a> {
+    /// Replace `http` with `https`.
+    pub https_upgrade: bool,
+    /// Put this path in front of the target's path, see
+    /// [`Url::with_base_path`].
+    pub base_path: Option<&'a str>,
+    /// Add this already encoded query after the target's own, see
+    /// [`Url::with_extra_query`].
+    pub forward_query: Option<&'a str>,
+}
+
+impl ResolveOpts<'_> {
+    /// `url` with these changes made.
+    #[must_use]
+    pub fn apply(&self, url: &Url) -> Url {
+        let mut url = url.clone();
+        if self.https_upgrade {
+            url = url.with_https();
+        }
+        if let Some(base_path) = self.base_path {
+            url = url.with_base_path(base_path);
+        }
+        if let Some(query) = self.forward_query {
+            url = url.with_extra_query(query);
+        }
+        url
+    }
+}
+
 pub trait Repository {
+    /// The short URL `name`, following an alias to the short URL it
+    /// points at, see [`WritableRepository::add_alias`].
+    ///
     /// # Errors
     ///
     /// May return a `RepositoryError` if database communication fail.
     fn get_url(&self, name: &ShortUrlName) -> Result<Option<ShortUrl>, anyhow::Error>;
 
+    /// When `name` was last modified, without loading the rest of the
+    /// row. `None` if `name` does not exist spelled exactly like this, or
+    /// has no timestamp; a differently cased name is left to
+    /// [`Repository::get_or_404_status`], which redirects it.
+    ///
+    /// # Errors
+    ///
+    /// May return a `RepositoryError` if database communication fail.
+    fn last_modified(&self, name: &ShortUrlName) -> Result<Option<UnixTimestamp>, anyhow::Error>;
+
+    /// Like [`Repository::get_url`], but also tells whether `name` is
+    /// spelled exactly like the stored name, not just equal ignoring case.
+    ///
+    /// # Errors
+    ///
+    /// May return a `RepositoryError` if database communication fail.
+    fn get_url_ci_exact(
+        &self,
+        name: &ShortUrlName,
+    ) -> Result<Option<(ShortUrl, bool)>, anyhow::Error> {
+        Ok(self.get_url(name)?.map(|short_url| {
+            let exact = short_url.name.as_ref() == name.as_ref();
+            (short_url, exact)
+        }))
+    }
+
+    /// Look up `name` and return its target with `opts` applied, ready
+    /// for a `Location` header. `None` if `name` does not exist.
+    ///
+    /// # Errors
+    ///
+    /// May return a `RepositoryError` if database communication fail.
+    fn resolve(
+        &self,
+        name: &ShortUrlName,
+        opts: ResolveOpts<'_>,
+    ) -> Result<Option<http::Uri>, anyhow::Error> {
+        let Some(short_url) = self.get_url(name)? else {
+            return Ok(None);
+        };
+        Ok(Some(http::Uri::try_from(
+            opts.apply(&short_url.url).to_string(),
+        )?))
+    }
+
+    /// Look up `name` and classify the result, so callers match on every
+    /// outcome, including not found.
+    ///
+    /// # Errors
+    ///
+    /// May return a `RepositoryError` if database communication fail.
+    fn get_or_404_status(&self, name: &ShortUrlName) -> Result<UrlStatus, anyhow::Error> {
+        Ok(match self.get_url_ci_exact(name)? {
+            Some((short_url, true)) => UrlStatus::Found(short_url),
+            Some((short_url, false)) => UrlStatus::OtherCase(short_url),
+            None => UrlStatus::NotFound,
+        })
+    }
+
     /// # Errors
     ///
     /// May return a `Error` if database communication fail.
@@ -16,6 +236,24 @@ pub trait Repository {
         callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
     ) -> anyhow::Result<()>;
 
+    /// All short URLs as an iterator, for use with iterator adapters.
+    ///
+    /// The rows are read into memory up front, so stopping early saves
+    /// no database work; use [`Repository::for_each_short_url`] for large
+    /// tables.
+    ///
+    /// # Errors
+    ///
+    /// May return a `Error` if database communication fail.
+    fn iter_short_urls(&self) -> anyhow::Result<impl Iterator<Item = anyhow::Result<ShortUrl>>> {
+        let rows = RefCell::new(Vec::new());
+        self.for_each_short_url(&|short_url| {
+            rows.borrow_mut().push(Ok(short_url));
+            Ok(())
+        })?;
+        Ok(rows.into_inner().into_iter())
+    }
+
     // fn for_each_short_url<F>(&self, callback: F) -> anyhow::Result<()>
     // where
     //     F: Fn(ShortUrl) -> anyhow::Result<()>;
@@ -31,13 +269,237 @@ pub trait Repository {
     // where
     //     F: Fn(ShortUrlName) -> anyhow::Result<()>;
 
+    /// Calls `callback` for every short URL whose name starts with
+    /// `prefix`, ignoring case.
+    ///
+    /// # Errors
+    ///
+    /// May return a `Error` if database communication fail.
+    fn find_by_prefix(
+        &self,
+        prefix: &str,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()>;
+
+    /// Calls `callback` for every short URL whose name matches `pattern`,
+    /// ordered by name.
+    ///
+    /// Neither kind of pattern can use an index: a glob is a `LIKE` that
+    /// may start with a wildcard, and a regex scans the whole table.
+    ///
+    /// # Errors
+    ///
+    /// May return a `Error` if database communication fail.
+    fn find_by_pattern(
+        &self,
+        pattern: &Pattern,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()>;
+
+    /// Calls `callback` for every short URL whose host is `host` or a
+    /// subdomain of it, ignoring case.
+    ///
+    /// This scans every row and takes the host from the parsed URL. A
+    /// denormalized host column would allow an index, but needs a
+    /// migration and must be kept in sync on every write, which is not
+    /// worth it for a check run now and then.
+    ///
+    /// # Errors
+    ///
+    /// May return a `Error` if database communication fail.
+    fn find_by_host(
+        &self,
+        host: &str,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let subdomain = format!(".{host}");
+        self.for_each_short_url(&|short_url| {
+            let matches = short_url
+                .url
+                .host()
+                .is_some_and(|x| x == host || x.ends_with(&subdomain));
+            if matches {
+                callback(short_url)
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    /// Number of names starting with `prefix`, ignoring case.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn count_by_prefix(&self, prefix: &str) -> Result<u64, anyhow::Error>;
+
+    /// The `limit` most recently modified short URLs, newest first.
+    /// Rows without a timestamp sort last.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn recent(&self, limit: u64) -> Result<Vec<ShortUrl>, anyhow::Error>;
+
+    /// Calls `callback` for every short URL modified in the last
+    /// `secs_ago` seconds, newest first.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn modified_since(
+        &self,
+        secs_ago: u64,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()>;
+
+    /// Tags of `name`, sorted. Empty if the name has no tags or does
+    /// not exist.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn tags_for(&self, name: &ShortUrlName) -> Result<Vec<String>, anyhow::Error>;
+
+    /// Calls `callback` for every short URL tagged `tag`, ignoring case,
+    /// ordered by name.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn find_by_tag(
+        &self,
+        tag: &str,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()>;
+
+    /// Short URLs matching all filters of `query`, one page of them,
+    /// with the number of matches on all pages. Combines the filters of
+    /// [`Repository::find_by_prefix`], [`Repository::find_by_tag`] and
+    /// [`Repository::find_by_host`].
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn search(&self, query: SearchQuery) -> Result<Page, anyhow::Error>;
+
+    /// Target URLs shared by more than one name, with the names pointing
+    /// at each. Groups are ordered by URL, names alphabetically.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn duplicate_urls(&self) -> Result<Vec<(Url, Vec<ShortUrlName>)>, anyhow::Error>;
+
+    /// Short URLs in the trash with when they were deleted, most
+    /// recently deleted first. See [`WritableRepository::delete_url`].
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn trashed(&self) -> Result<Vec<(ShortUrl, UnixTimestamp)>, anyhow::Error>;
+
+    /// A number that changes when the short URLs change, e.g. for an
+    /// `ETag` covering all of them. Tags and titles only count through
+    /// the modification time they set.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn content_version(&self, method: ContentVersionMethod) -> Result<u64, anyhow::Error>;
+
     /// # Errors
     /// May return a `RepositoryError` if database communication fails.
     fn get_random_quote(&self) -> Result<String, anyhow::Error>;
 
+    /// A randomly chosen short URL, `None` if there are none.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn get_random_url(&self) -> Result<Option<ShortUrl>, anyhow::Error>;
+
     /// # Errors
     /// May return a `RepositoryError` if database communication fails.
-    fn has_latest_migrations(&self) -> Result<bool, anyhow::Error>;
+    fn has_latest_migrations(&self) -> Result<bool, anyhow::Error> {
+        Ok(self.migration_state()? == MigrationState::UpToDate)
+    }
+
+    /// Whether the schema is behind, at or ahead of this binary.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn migration_state(&self) -> Result<MigrationState, anyhow::Error>;
+
+    /// Whether writes can succeed. `false` for a repository opened
+    /// read-only, and for a writable open that SQLite had to downgrade,
+    /// e.g. because the file is not writable.
+    fn is_writable(&self) -> bool;
+
+    /// The `CREATE` statements of the tables, indexes and triggers in
+    /// the database, tables first.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn schema(&self) -> Result<Vec<String>, anyhow::Error>;
+
+    /// Write the schema, every row and the migration version as SQL
+    /// statements that rebuild the database when run on an empty one,
+    /// e.g. with `sqlite3 copy.db < dump.sql`.
+    ///
+    /// Unlike a backup, which copies the database file, the dump is
+    /// plain text that can be read, diffed and edited.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication or
+    /// writing fails.
+    fn dump_sql(&self, out: &mut dyn std::io::Write) -> Result<(), anyhow::Error>;
+
+    /// Write all short URLs as CSV with a header row and CRLF line endings.
+    ///
+    /// # Errors
+    ///
+    /// May return a `Error` if database communication or writing fail.
+    fn export_csv(&self, out: &mut dyn std::io::Write) -> anyhow::Result<()> {
+        self.export_delimited(out, b',')
+    }
+
+    /// Like [`Repository::export_csv`], but with `delimiter` between fields.
+    ///
+    /// # Errors
+    ///
+    /// May return a `Error` if database communication or writing fail.
+    fn export_delimited(&self, out: &mut dyn std::io::Write, delimiter: u8) -> anyhow::Result<()> {
+        self.export_delimited_with(out, delimiter, false)
+    }
+
+    /// Like [`Repository::export_delimited`], with a fourth `title`
+    /// column if `with_title` is set.
+    ///
+    /// # Errors
+    ///
+    /// May return a `Error` if database communication or writing fail.
+    fn export_delimited_with(
+        &self,
+        out: &mut dyn std::io::Write,
+        delimiter: u8,
+        with_title: bool,
+    ) -> anyhow::Result<()> {
+        let wtr = RefCell::new(
+            csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .terminator(csv::Terminator::CRLF)
+                .from_writer(out),
+        );
+        let header = ["shorturl", "url", "last_modified", "title"];
+        let columns = if with_title { 4 } else { 3 };
+        wtr.borrow_mut().write_record(&header[..columns])?;
+        self.for_each_short_url(&|short_url| {
+            let record = [
+                short_url.name.to_string(),
+                short_url.url.to_string(),
+                short_url
+                    .last_modified
+                    .map_or(String::new(), |x| x.to_string()),
+                short_url.title.unwrap_or_default(),
+            ];
+            wtr.borrow_mut().write_record(&record[..columns])?;
+            Ok(())
+        })?;
+        wtr.into_inner().flush()?;
+        Ok(())
+    }
 }
 
 pub trait WritableRepository: Repository {
@@ -46,11 +508,186 @@ pub trait WritableRepository: Repository {
     /// May return a `RepositoryError` if the migration fails.
     fn migrate(&mut self) -> Result<(), anyhow::Error>;
 
+    /// Like [`WritableRepository::migrate`], but retries while another
+    /// connection holds a lock on the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RepositoryError::Locked`] if the lock could not be
+    /// acquired, or a `RepositoryError` if the migration fails.
+    fn migrate_with_retry(&mut self, retry: BusyRetry) -> Result<(), anyhow::Error>;
+
     /// # Errors
     /// May return a `RepositoryError` if database communication fails.
     fn insert_url(&mut self, name: &ShortUrlName, url: &Url) -> Result<(), anyhow::Error>;
 
+    /// Like [`WritableRepository::insert_url`] for each pair, in a single
+    /// transaction. Nothing is saved if one insert fails.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn insert_urls(&mut self, urls: &[(ShortUrlName, Url)]) -> Result<(), anyhow::Error>;
+
+    /// Like [`WritableRepository::insert_urls`], also setting the title of
+    /// each row that has one. A row without a title keeps the old title.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn insert_urls_with_titles(
+        &mut self,
+        urls: &[(ShortUrlName, Url, Option<String>)],
+    ) -> Result<(), anyhow::Error>;
+
+    /// Insert `name` unless it already exists, in a single transaction.
+    ///
+    /// Returns the stored row and whether it was created. An existing
+    /// name keeps its URL, even if it differs from `url`.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn get_or_insert(
+        &mut self,
+        name: &ShortUrlName,
+        url: &Url,
+    ) -> Result<(ShortUrl, bool), anyhow::Error>;
+
+    /// Replace the tags of `name` with `tags`. Does nothing if `name`
+    /// does not exist.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn set_tags(&mut self, name: &ShortUrlName, tags: &[String]) -> Result<(), anyhow::Error>;
+
+    /// Remove `name`, ignoring case. Returns whether it existed.
+    ///
+    /// If the repository was opened with soft delete, the short URL is
+    /// moved to the trash instead: it is hidden from every other method
+    /// until [`WritableRepository::restore_url`] or
+    /// [`WritableRepository::empty_trash`].
+    ///
+    /// If `name` is an alias, see [`WritableRepository::add_alias`], the
+    /// alias is removed instead, also with soft delete, as there is no
+    /// trash for aliases. The short URL it points at is kept.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn delete_url(&mut self, name: &ShortUrlName) -> Result<bool, anyhow::Error>;
+
+    /// Like [`WritableRepository::delete_url`] for each of `names`, in a
+    /// single transaction. Returns the number of short URLs and aliases
+    /// removed, so names that do not exist are not counted.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn delete_many(&mut self, names: &[ShortUrlName]) -> Result<u64, anyhow::Error>;
+
+    /// Take `name` out of the trash, ignoring case. Returns whether it
+    /// was there.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn restore_url(&mut self, name: &ShortUrlName) -> Result<bool, anyhow::Error>;
+
+    /// Remove the short URLs that were moved to the trash at least
+    /// `secs_ago` seconds ago. Returns the number removed.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn empty_trash(&mut self, secs_ago: u64) -> Result<u64, anyhow::Error>;
+
+    /// Add `quote` to the default collection. Returns `false` if it was
+    /// already there.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn insert_quotation(&mut self, quote: &str) -> Result<bool, anyhow::Error>;
+
+    /// Add `quotes` to `collection` in one transaction. Returns the
+    /// number added; quotes already in the collection are skipped.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn insert_quotations(
+        &mut self,
+        collection: &str,
+        quotes: &[String],
+    ) -> Result<usize, anyhow::Error>;
+
+    /// Set or, with `None`, remove the title of `name`. Returns whether
+    /// `name` exists.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn set_title(
+        &mut self,
+        name: &ShortUrlName,
+        title: Option<&str>,
+    ) -> Result<bool, anyhow::Error>;
+
+    /// Set the last modified time of `name` to now, leaving the URL
+    /// as is. Returns whether `name` exists.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn touch(&mut self, name: &ShortUrlName) -> Result<bool, anyhow::Error>;
+
+    /// Exchange the URLs of `a` and `b` in one transaction, so neither
+    /// is ever seen pointing at the other's old URL alone.
+    ///
+    /// # Errors
+    /// If `a` or `b` does not exist, or a `RepositoryError` if database
+    /// communication fails.
+    fn swap_targets(&mut self, a: &ShortUrlName, b: &ShortUrlName) -> Result<(), anyhow::Error>;
+
+    /// Make `alias` another name for the short URL `canonical`, or point
+    /// an existing alias there instead.
+    ///
+    /// [`Repository::get_url`] resolves an alias to the URL, title and
+    /// modification time of `canonical`, keeping `alias` as the name.
+    /// Only short URLs can be aliased, so there are no chains or cycles,
+    /// and an alias is removed with the short URL it points at, or on its
+    /// own with [`WritableRepository::delete_url`].
+    ///
+    /// # Errors
+    /// If `canonical` is not a short URL or `alias` is one, or a
+    /// `RepositoryError` if database communication fails.
+    fn add_alias(
+        &mut self,
+        alias: &ShortUrlName,
+        canonical: &ShortUrlName,
+    ) -> Result<(), anyhow::Error>;
+
+    /// Check the database after an unclean shutdown. Copies any pages
+    /// left in the write-ahead log into the database and truncates the
+    /// log, then checks the whole database for corruption. Returns the
+    /// number of log frames copied, 0 if the database is not in WAL mode.
+    ///
+    /// # Errors
+    /// [`RepositoryError::Locked`] if another connection blocks the
+    /// checkpoint, an error listing the problems if the integrity check
+    /// finds any, or a `RepositoryError` if database communication fails.
+    fn recover(&mut self) -> Result<u64, anyhow::Error>;
+
+    /// Move the quotations in collection `old` to collection `new`,
+    /// ignoring case. Returns the number moved.
+    ///
+    /// A quote that is already in `new` is skipped and stays in `old`,
+    /// so renaming never loses or duplicates quotes.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn rename_collection(&mut self, old: &str, new: &str) -> Result<u64, anyhow::Error>;
+
+    /// Remove every short URL and its tags. Returns the number of URLs
+    /// removed.
+    ///
+    /// # Errors
+    /// May return a `RepositoryError` if database communication fails.
+    fn clear_urls(&mut self) -> Result<u64, anyhow::Error>;
+
+    /// Remove every quotation. Returns the number removed.
+    ///
     /// # Errors
     /// May return a `RepositoryError` if database communication fails.
-    fn insert_quotation(&mut self, collection: &str) -> Result<(), anyhow::Error>;
+    fn clear_quotations(&mut self) -> Result<u64, anyhow::Error>;
 }
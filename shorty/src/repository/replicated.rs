@@ -0,0 +1,409 @@
+//! Spread reads over read-only copies of the database.
+//!
+//! SQLite has no replication of its own, so a replica is a separate
+//! database file kept up to date by another tool, e.g. one restored and
+//! followed with `litestream`. Replicas lag behind the primary, so a
+//! read may miss a write made moments before; use the primary directly
+//! where that matters.
+
+use core::cell::Cell;
+
+use crate::types::{ShortUrl, ShortUrlName, UnixTimestamp, Url};
+
+use super::{
+    BusyRetry, ContentVersionMethod, MigrationState, Page, Pattern, Repository, SearchQuery,
+    WritableRepository,
+};
+
+/// A repository that reads from `replicas` in turn and writes to
+/// `primary`.
+///
+/// A read that fails on a replica is retried on the primary, as is
+/// every read when there are no replicas. Callback based reads are only
+/// retried if the replica failed before calling the callback, so no row
+/// is seen twice.
+///
+/// Migration state and writability are those of the primary.
+pub struct ReplicatedRepository<P, R> {
+    primary: P,
+    replicas: Vec<R>,
+    next: Cell<usize>,
+}
+
+impl<P, R> ReplicatedRepository<P, R> {
+    pub const fn new(primary: P, replicas: Vec<R>) -> Self {
+        Self {
+            primary,
+            replicas,
+            next: Cell::new(0),
+        }
+    }
+
+    pub const fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    /// The replica to read from next, `None` if there are none.
+    fn next_replica(&self) -> Option<&R> {
+        if self.replicas.is_empty() {
+            return None;
+        }
+        let index = self.next.get() % self.replicas.len();
+        self.next.set(index + 1);
+        self.replicas.get(index)
+    }
+
+    fn read<T>(
+        &self,
+        replica: impl FnOnce(&R) -> anyhow::Result<T>,
+        primary: impl FnOnce(&P) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        match self.next_replica().map(replica) {
+            Some(Ok(result)) => Ok(result),
+            Some(Err(_)) | None => primary(&self.primary),
+        }
+    }
+
+    fn read_each<T>(
+        &self,
+        callback: &dyn Fn(T) -> anyhow::Result<()>,
+        replica: impl FnOnce(&R, &dyn Fn(T) -> anyhow::Result<()>) -> anyhow::Result<()>,
+        primary: impl FnOnce(&P, &dyn Fn(T) -> anyhow::Result<()>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        if let Some(repo) = self.next_replica() {
+            let called = Cell::new(false);
+            let result = replica(repo, &|x| {
+                called.set(true);
+                callback(x)
+            });
+            if result.is_ok() || called.get() {
+                return result;
+            }
+        }
+        primary(&self.primary, callback)
+    }
+}
+
+impl<P: Repository, R: Repository> Repository for ReplicatedRepository<P, R> {
+    fn get_url(&self, name: &ShortUrlName) -> Result<Option<ShortUrl>, anyhow::Error> {
+        self.read(|r| r.get_url(name), |p| p.get_url(name))
+    }
+
+    fn last_modified(&self, name: &ShortUrlName) -> Result<Option<UnixTimestamp>, anyhow::Error> {
+        self.read(|r| r.last_modified(name), |p| p.last_modified(name))
+    }
+
+    fn get_url_ci_exact(
+        &self,
+        name: &ShortUrlName,
+    ) -> Result<Option<(ShortUrl, bool)>, anyhow::Error> {
+        self.read(|r| r.get_url_ci_exact(name), |p| p.get_url_ci_exact(name))
+    }
+
+    fn for_each_short_url(
+        &self,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.read_each(
+            callback,
+            |r, f| r.for_each_short_url(f),
+            |p, f| p.for_each_short_url(f),
+        )
+    }
+
+    fn for_each_name(
+        &self,
+        callback: &dyn Fn(ShortUrlName) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.read_each(
+            callback,
+            |r, f| r.for_each_name(f),
+            |p, f| p.for_each_name(f),
+        )
+    }
+
+    fn find_by_prefix(
+        &self,
+        prefix: &str,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.read_each(
+            callback,
+            |r, f| r.find_by_prefix(prefix, f),
+            |p, f| p.find_by_prefix(prefix, f),
+        )
+    }
+
+    fn find_by_pattern(
+        &self,
+        pattern: &Pattern,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.read_each(
+            callback,
+            |r, f| r.find_by_pattern(pattern, f),
+            |p, f| p.find_by_pattern(pattern, f),
+        )
+    }
+
+    fn count_by_prefix(&self, prefix: &str) -> Result<u64, anyhow::Error> {
+        self.read(|r| r.count_by_prefix(prefix), |p| p.count_by_prefix(prefix))
+    }
+
+    fn recent(&self, limit: u64) -> Result<Vec<ShortUrl>, anyhow::Error> {
+        self.read(|r| r.recent(limit), |p| p.recent(limit))
+    }
+
+    fn modified_since(
+        &self,
+        secs_ago: u64,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.read_each(
+            callback,
+            |r, f| r.modified_since(secs_ago, f),
+            |p, f| p.modified_since(secs_ago, f),
+        )
+    }
+
+    fn tags_for(&self, name: &ShortUrlName) -> Result<Vec<String>, anyhow::Error> {
+        self.read(|r| r.tags_for(name), |p| p.tags_for(name))
+    }
+
+    fn find_by_tag(
+        &self,
+        tag: &str,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.read_each(
+            callback,
+            |r, f| r.find_by_tag(tag, f),
+            |p, f| p.find_by_tag(tag, f),
+        )
+    }
+
+    fn search(&self, query: SearchQuery) -> Result<Page, anyhow::Error> {
+        self.read(|r| r.search(query.clone()), |p| p.search(query.clone()))
+    }
+
+    fn duplicate_urls(&self) -> Result<Vec<(Url, Vec<ShortUrlName>)>, anyhow::Error> {
+        self.read(Repository::duplicate_urls, Repository::duplicate_urls)
+    }
+
+    fn trashed(&self) -> Result<Vec<(ShortUrl, UnixTimestamp)>, anyhow::Error> {
+        self.read(Repository::trashed, Repository::trashed)
+    }
+
+    fn content_version(&self, method: ContentVersionMethod) -> Result<u64, anyhow::Error> {
+        self.read(|r| r.content_version(method), |p| p.content_version(method))
+    }
+
+    fn get_random_quote(&self) -> Result<String, anyhow::Error> {
+        self.read(Repository::get_random_quote, Repository::get_random_quote)
+    }
+
+    fn get_random_url(&self) -> Result<Option<ShortUrl>, anyhow::Error> {
+        self.read(Repository::get_random_url, Repository::get_random_url)
+    }
+
+    fn migration_state(&self) -> Result<MigrationState, anyhow::Error> {
+        self.primary.migration_state()
+    }
+
+    fn is_writable(&self) -> bool {
+        self.primary.is_writable()
+    }
+
+    fn schema(&self) -> Result<Vec<String>, anyhow::Error> {
+        self.primary.schema()
+    }
+
+    // Not retried, as the replica may have written part of the dump
+    fn dump_sql(&self, out: &mut dyn std::io::Write) -> Result<(), anyhow::Error> {
+        self.primary.dump_sql(out)
+    }
+}
+
+impl<P: WritableRepository, R: Repository> WritableRepository for ReplicatedRepository<P, R> {
+    fn migrate(&mut self) -> Result<(), anyhow::Error> {
+        self.primary.migrate()
+    }
+
+    fn migrate_with_retry(&mut self, retry: BusyRetry) -> Result<(), anyhow::Error> {
+        self.primary.migrate_with_retry(retry)
+    }
+
+    fn insert_url(&mut self, name: &ShortUrlName, url: &Url) -> Result<(), anyhow::Error> {
+        self.primary.insert_url(name, url)
+    }
+
+    fn insert_urls(&mut self, urls: &[(ShortUrlName, Url)]) -> Result<(), anyhow::Error> {
+        self.primary.insert_urls(urls)
+    }
+
+    fn insert_urls_with_titles(
+        &mut self,
+        urls: &[(ShortUrlName, Url, Option<String>)],
+    ) -> Result<(), anyhow::Error> {
+        self.primary.insert_urls_with_titles(urls)
+    }
+
+    fn get_or_insert(
+        &mut self,
+        name: &ShortUrlName,
+        url: &Url,
+    ) -> Result<(ShortUrl, bool), anyhow::Error> {
+        self.primary.get_or_insert(name, url)
+    }
+
+    fn set_tags(&mut self, name: &ShortUrlName, tags: &[String]) -> Result<(), anyhow::Error> {
+        self.primary.set_tags(name, tags)
+    }
+
+    fn delete_url(&mut self, name: &ShortUrlName) -> Result<bool, anyhow::Error> {
+        self.primary.delete_url(name)
+    }
+
+    fn delete_many(&mut self, names: &[ShortUrlName]) -> Result<u64, anyhow::Error> {
+        self.primary.delete_many(names)
+    }
+
+    fn restore_url(&mut self, name: &ShortUrlName) -> Result<bool, anyhow::Error> {
+        self.primary.restore_url(name)
+    }
+
+    fn empty_trash(&mut self, secs_ago: u64) -> Result<u64, anyhow::Error> {
+        self.primary.empty_trash(secs_ago)
+    }
+
+    fn insert_quotation(&mut self, quote: &str) -> Result<bool, anyhow::Error> {
+        self.primary.insert_quotation(quote)
+    }
+
+    fn insert_quotations(
+        &mut self,
+        collection: &str,
+        quotes: &[String],
+    ) -> Result<usize, anyhow::Error> {
+        self.primary.insert_quotations(collection, quotes)
+    }
+
+    fn set_title(
+        &mut self,
+        name: &ShortUrlName,
+        title: Option<&str>,
+    ) -> Result<bool, anyhow::Error> {
+        self.primary.set_title(name, title)
+    }
+
+    fn touch(&mut self, name: &ShortUrlName) -> Result<bool, anyhow::Error> {
+        self.primary.touch(name)
+    }
+
+    fn swap_targets(&mut self, a: &ShortUrlName, b: &ShortUrlName) -> Result<(), anyhow::Error> {
+        self.primary.swap_targets(a, b)
+    }
+
+    fn add_alias(
+        &mut self,
+        alias: &ShortUrlName,
+        canonical: &ShortUrlName,
+    ) -> Result<(), anyhow::Error> {
+        self.primary.add_alias(alias, canonical)
+    }
+
+    fn recover(&mut self) -> Result<u64, anyhow::Error> {
+        self.primary.recover()
+    }
+
+    fn rename_collection(&mut self, old: &str, new: &str) -> Result<u64, anyhow::Error> {
+        self.primary.rename_collection(old, new)
+    }
+
+    fn clear_urls(&mut self) -> Result<u64, anyhow::Error> {
+        self.primary.clear_urls()
+    }
+
+    fn clear_quotations(&mut self) -> Result<u64, anyhow::Error> {
+        self.primary.clear_quotations()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::cell::RefCell;
+
+    use rusqlite::Connection;
+
+    use super::ReplicatedRepository;
+    use crate::{
+        repository::{sqlite::Sqlite3Repo, Repository, WritableRepository},
+        types::{ShortUrlName, Url},
+    };
+
+    fn repo_with(url: &str) -> Sqlite3Repo {
+        let mut repo = Sqlite3Repo::new(Connection::open_in_memory().unwrap()).unwrap();
+        repo.migrate().unwrap();
+        repo.insert_url(
+            &ShortUrlName::try_from("aa").unwrap(),
+            &Url::try_from(url).unwrap(),
+        )
+        .unwrap();
+        repo
+    }
+
+    fn url_of(repo: &impl Repository, name: &str) -> Option<String> {
+        repo.get_url(&ShortUrlName::try_from(name).unwrap())
+            .unwrap()
+            .map(|x| x.url.to_string())
+    }
+
+    #[test]
+    fn test_replicated_repository() {
+        let mut repo = ReplicatedRepository::new(
+            repo_with("https://primary.example.com/"),
+            vec![
+                repo_with("https://one.example.com/"),
+                repo_with("https://two.example.com/"),
+            ],
+        );
+        let one = Some("https://one.example.com/".to_string());
+        let two = Some("https://two.example.com/".to_string());
+        assert_eq!(url_of(&repo, "aa"), one);
+        assert_eq!(url_of(&repo, "aa"), two);
+        assert_eq!(url_of(&repo, "aa"), one);
+
+        repo.insert_url(
+            &ShortUrlName::try_from("bb").unwrap(),
+            &Url::try_from("https://new.example.com/").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            url_of(repo.primary(), "bb"),
+            Some("https://new.example.com/".to_string())
+        );
+        assert_eq!(url_of(&repo, "bb"), None);
+        assert!(repo.has_latest_migrations().unwrap());
+    }
+
+    #[test]
+    fn test_replicated_repository_fallback() {
+        let unmigrated = Sqlite3Repo::new(Connection::open_in_memory().unwrap()).unwrap();
+        let repo =
+            ReplicatedRepository::new(repo_with("https://primary.example.com/"), vec![unmigrated]);
+        let primary = Some("https://primary.example.com/".to_string());
+        assert_eq!(url_of(&repo, "aa"), primary);
+
+        let names = RefCell::new(Vec::new());
+        repo.for_each_name(&|name| {
+            names.borrow_mut().push(name.to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(names.into_inner(), vec!["aa"]);
+
+        let no_replicas: ReplicatedRepository<_, Sqlite3Repo> =
+            ReplicatedRepository::new(repo_with("https://primary.example.com/"), vec![]);
+        assert_eq!(url_of(&no_replicas, "aa"), primary);
+    }
+}
@@ -1,19 +1,133 @@
 use core::result::Result;
-use std::path::Path;
+use core::time::Duration;
+use std::{path::Path, time::SystemTime};
 
 use crate::types::{ShortUrl, ShortUrlName, UnixTimestamp, Url};
-use rusqlite::{Connection, OpenFlags, OptionalExtension, TransactionBehavior};
+use rusqlite::{
+    trace::{TraceEvent, TraceEventCodes},
+    Connection, ErrorCode, OpenFlags, OptionalExtension, TransactionBehavior,
+};
+use xxhash_rust::xxh3::Xxh3;
 
-use super::{Repository, WritableRepository};
+use super::{
+    BusyRetry, ContentVersionMethod, MigrationState, Page, Pattern, Repository, RepositoryError,
+    SearchQuery, SearchSort, WritableRepository,
+};
 
+/// Number of virtual machine instructions between deadline checks.
+const DEADLINE_CHECK_INTERVAL: i32 = 1000;
+
+/// How long to wait for a lock, rusqlite's default.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most `?` parameters in one statement on SQLite before 3.32.
+const MAX_PARAMS: usize = 999;
+
+// Names starting with `?1`, ignoring case, as a range on the `nocase`
+// primary key index. Unlike `LIKE ?1 || '%'`, a range is a search of the
+// index rather than a scan. Names are ASCII, so all characters sort
+// before `char(127)`.
+const FIND_BY_PREFIX: &str = "SELECT shorturl, url, last_modified, title FROM urls \
+                              WHERE shorturl >= ?1 AND shorturl < ?1 || char(127) \
+                              AND deleted_at IS NULL ORDER BY shorturl";
+const COUNT_BY_PREFIX: &str = "SELECT COUNT(*) FROM urls \
+                               WHERE shorturl >= ?1 AND shorturl < ?1 || char(127) \
+                               AND deleted_at IS NULL";
+
+fn map_sqlite_error(err: rusqlite::Error) -> anyhow::Error {
+    match &err {
+        rusqlite::Error::SqliteFailure(e, _) if e.code == ErrorCode::OperationInterrupted => {
+            RepositoryError::Interrupted.into()
+        }
+        rusqlite::Error::SqliteFailure(e, _) if e.code == ErrorCode::DatabaseBusy => {
+            RepositoryError::Locked.into()
+        }
+        _ => err.into(),
+    }
+}
+
+/// Like [`map_sqlite_error`], and a missing table is
+/// [`RepositoryError::Migration`] if `conn` has pending migrations. On an
+/// up to date schema it is a bug, and passed through.
+fn map_query_error(conn: &Connection) -> impl Fn(rusqlite::Error) -> anyhow::Error + '_ {
+    move |err| match &err {
+        rusqlite::Error::SqliteFailure(_, Some(msg))
+            if msg.starts_with("no such table")
+                && migration_state(conn).is_ok_and(|x| x == MigrationState::Pending) =>
+        {
+            RepositoryError::Migration.into()
+        }
+        _ => map_sqlite_error(err),
+    }
+}
+
+fn migration_state(conn: &Connection) -> Result<MigrationState, rusqlite::Error> {
+    let user_version: usize =
+        conn.query_row("SELECT user_version FROM pragma_user_version", [], |row| {
+            row.get(0)
+        })?;
+    Ok(match user_version.cmp(&migrations().len()) {
+        core::cmp::Ordering::Less => MigrationState::Pending,
+        core::cmp::Ordering::Equal => MigrationState::UpToDate,
+        core::cmp::Ordering::Greater => MigrationState::Ahead,
+    })
+}
+
+/// `value` as an SQL literal, with quotes in text doubled.
+fn sql_literal(value: rusqlite::types::ValueRef<'_>) -> String {
+    use rusqlite::types::ValueRef;
+    match value {
+        ValueRef::Null => "NULL".to_string(),
+        ValueRef::Integer(x) => x.to_string(),
+        // Out of range literals are read as infinity
+        ValueRef::Real(x) if x.is_infinite() => {
+            if x > 0.0 { "9e999" } else { "-9e999" }.to_string()
+        }
+        ValueRef::Real(x) => format!("{x:?}"),
+        ValueRef::Text(x) => format!("'{}'", String::from_utf8_lossy(x).replace('\'', "''")),
+        ValueRef::Blob(x) => {
+            use core::fmt::Write as _;
+            x.iter().fold("X'".to_string(), |mut hex, b| {
+                let _ = write!(hex, "{b:02X}");
+                hex
+            }) + "'"
+        }
+    }
+}
+
+/// A repository in an SQLite database, as returned by the functions
+/// that open one for writing.
 #[derive(Debug)]
-pub(crate) struct Sqlite3Repo {
+pub struct Sqlite3Repo {
     conn: Connection,
+    /// Whether `delete_url` moves rows to the trash instead of removing them.
+    soft_delete: bool,
 }
 
 impl Sqlite3Repo {
-    pub(crate) const fn new(conn: rusqlite::Connection) -> Self {
-        Self { conn }
+    /// # Errors
+    ///
+    /// Will return `Err` if the SQL functions used by the queries cannot be
+    /// registered on `conn`.
+    pub(crate) fn new(conn: rusqlite::Connection) -> Result<Self, anyhow::Error> {
+        use rusqlite::functions::FunctionFlags;
+
+        // Hosts aren't stored, so `search` parses them like `find_by_host`
+        conn.create_scalar_function(
+            "url_host",
+            1,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| {
+                let url = ctx.get::<String>(0)?;
+                Ok(url::Url::parse(&url)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_string)))
+            },
+        )?;
+        Ok(Self {
+            conn,
+            soft_delete: false,
+        })
     }
 
     /// # Errors
@@ -25,41 +139,192 @@ impl Sqlite3Repo {
         flags: Option<OpenFlags>,
     ) -> Result<Self, anyhow::Error> {
         let conn = Connection::open_with_flags(path, flags.unwrap_or_default())?;
-        Ok(Self::new(conn))
+        Self::new(conn)
+    }
+
+    /// Run `f` in a transaction on the connection.
+    ///
+    /// The transaction is committed if `f` returns `Ok`, and rolled back
+    /// if it returns an error.
+    ///
+    /// # Errors
+    /// The error returned by `f`, or a `RepositoryError` if database
+    /// communication fails.
+    pub fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&rusqlite::Transaction<'_>) -> Result<T, anyhow::Error>,
+    ) -> Result<T, anyhow::Error> {
+        let tx = self.conn.transaction().map_err(map_sqlite_error)?;
+        // Dropping `tx` without committing rolls it back
+        let value = f(&tx)?;
+        tx.commit().map_err(map_sqlite_error)?;
+        Ok(value)
+    }
+
+    fn run_migrations(&mut self) -> Result<(), anyhow::Error> {
+        // EXCLUSIVE ensures that it starts with an exclusive write lock. No other
+        // readers will be allowed. This generally shouldn't be needed if there is
+        // a file lock, but might be helpful in cases where cargo's `FileLock`
+        // failed.
+        let migrations = migrations();
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Exclusive)?;
+        let user_version: usize =
+            tx.query_row("SELECT user_version FROM pragma_user_version", [], |row| {
+                row.get(0)
+            })?;
+        if user_version > migrations.len() {
+            return Err(RepositoryError::SchemaAhead.into());
+        }
+        if user_version < migrations.len() {
+            for migration in &migrations[user_version..] {
+                tx.execute_batch(migration)?;
+            }
+            tx.pragma_update(None, "user_version", u32::try_from(migrations.len())?)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Abort any statement still running after `deadline`, and stop
+    /// waiting for a lock by then.
+    ///
+    /// The progress handler is not called while SQLite waits for a lock,
+    /// so the busy timeout is cut to the time left instead.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the progress handler cannot be installed.
+    pub(crate) fn set_deadline(&self, deadline: SystemTime) -> Result<(), anyhow::Error> {
+        let left = deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        self.conn.busy_timeout(left.min(BUSY_TIMEOUT))?;
+        self.conn.progress_handler(
+            DEADLINE_CHECK_INTERVAL,
+            Some(move || SystemTime::now() >= deadline),
+        )?;
+        Ok(())
+    }
+
+    /// Access to `self` that puts the current busy timeout back when
+    /// dropped, for code that changes it temporarily.
+    fn keep_busy_timeout(&mut self) -> Result<BusyTimeoutGuard<'_>, anyhow::Error> {
+        let millis: u64 = self
+            .conn
+            .pragma_query_value(None, "busy_timeout", |row| row.get(0))?;
+        Ok(BusyTimeoutGuard {
+            previous: Duration::from_millis(millis),
+            repo: self,
+        })
+    }
+}
+
+/// See [`Sqlite3Repo::keep_busy_timeout`].
+struct BusyTimeoutGuard<'a> {
+    repo: &'a mut Sqlite3Repo,
+    previous: Duration,
+}
+
+impl core::ops::Deref for BusyTimeoutGuard<'_> {
+    type Target = Sqlite3Repo;
+
+    fn deref(&self) -> &Self::Target {
+        self.repo
+    }
+}
+
+impl core::ops::DerefMut for BusyTimeoutGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.repo
+    }
+}
+
+impl Drop for BusyTimeoutGuard<'_> {
+    fn drop(&mut self) {
+        // Only fails on a closed connection, which has no timeout to keep
+        let _ = self.repo.conn.busy_timeout(self.previous);
+    }
+}
+
+/// Translate a glob to a `LIKE ... ESCAPE '\\'` pattern: `*` becomes `%`,
+/// `?` becomes `_`, and everything else is literal.
+fn glob_to_like(glob: &str) -> String {
+    let mut like = String::with_capacity(glob.len());
+    for c in glob.chars() {
+        match c {
+            '*' => like.push('%'),
+            '?' => like.push('_'),
+            '%' | '_' | '\\' => {
+                like.push('\\');
+                like.push(c);
+            }
+            _ => like.push(c),
+        }
     }
+    like
+}
+
+fn is_busy(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::DatabaseBusy
+    )
+}
+
+fn short_url_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<ShortUrl> {
+    Ok(ShortUrl {
+        name: row.get::<_, ShortUrlName>(0)?,
+        url: row.get::<_, Url>(1)?,
+        last_modified: row.get::<_, Option<UnixTimestamp>>(2)?,
+        title: row.get::<_, Option<String>>(3)?,
+    })
 }
 
 impl Repository for Sqlite3Repo {
     fn get_url(&self, id: &ShortUrlName) -> Result<Option<ShortUrl>, anyhow::Error> {
-        let query = "SELECT shortUrl, url, last_modified FROM urls WHERE shortUrl = ?";
+        // Names and aliases never overlap, so at most one row matches
+        let query = "SELECT shortUrl, url, last_modified, title FROM urls \
+                     WHERE shortUrl = ?1 AND deleted_at IS NULL \
+                     UNION ALL \
+                     SELECT alias, url, last_modified, title FROM aliases \
+                     JOIN urls USING (shorturl) \
+                     WHERE alias = ?1 AND deleted_at IS NULL";
+        self.conn
+            .query_row(query, rusqlite::params![id.as_ref()], short_url_from_row)
+            .optional()
+            .map_err(map_query_error(&self.conn))
+    }
+
+    fn last_modified(&self, name: &ShortUrlName) -> Result<Option<UnixTimestamp>, anyhow::Error> {
+        let query = "SELECT last_modified FROM urls \
+                     WHERE shortUrl = ?1 COLLATE BINARY AND deleted_at IS NULL \
+                     UNION ALL \
+                     SELECT last_modified FROM aliases JOIN urls USING (shorturl) \
+                     WHERE alias = ?1 COLLATE BINARY AND deleted_at IS NULL";
         Ok(self
             .conn
-            .query_row(query, rusqlite::params![id.as_ref()], |row| {
-                Ok(ShortUrl {
-                    name: row.get::<_, ShortUrlName>(0)?,
-                    url: row.get::<_, Url>(1)?,
-                    last_modified: row.get::<_, Option<UnixTimestamp>>(2)?,
-                })
-            })
-            .optional()?)
+            .query_row(query, [name], |row| row.get::<_, Option<UnixTimestamp>>(0))
+            .optional()
+            .map_err(map_query_error(&self.conn))?
+            .flatten())
     }
 
     fn for_each_short_url(
         &self,
         callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
     ) -> anyhow::Result<()> {
-        let query = "SELECT shorturl, url, last_modified FROM urls";
-        let mut stmt = self.conn.prepare(query)?;
-        let rows = stmt.query_map([], |row| {
-            Ok(ShortUrl {
-                name: row.get::<_, ShortUrlName>(0)?,
-                url: row.get::<_, Url>(1)?,
-                last_modified: row.get::<_, Option<UnixTimestamp>>(2)?,
-            })
-        })?;
+        let query = "SELECT shorturl, url, last_modified, title FROM urls WHERE deleted_at IS NULL";
+        let mut stmt = self
+            .conn
+            .prepare(query)
+            .map_err(map_query_error(&self.conn))?;
+        let rows = stmt
+            .query_map([], short_url_from_row)
+            .map_err(map_query_error(&self.conn))?;
         for row in rows {
-            let Ok(row) = row else { continue };
-            callback(row)?;
+            callback(row.map_err(map_query_error(&self.conn))?)?;
         }
         Ok(())
     }
@@ -68,69 +333,444 @@ impl Repository for Sqlite3Repo {
         &self,
         callback: &dyn Fn(ShortUrlName) -> anyhow::Result<()>,
     ) -> anyhow::Result<()> {
-        let query = "SELECT shortUrl FROM urls";
-        let mut stmt = self.conn.prepare(query)?;
-        let rows = stmt.query_map([], |row| {
-            let value: ShortUrlName = row.get(0)?;
-            Ok(value)
-        })?;
+        let query = "SELECT shortUrl FROM urls WHERE deleted_at IS NULL";
+        let mut stmt = self
+            .conn
+            .prepare(query)
+            .map_err(map_query_error(&self.conn))?;
+        let rows = stmt
+            .query_map([], |row| {
+                let value: ShortUrlName = row.get(0)?;
+                Ok(value)
+            })
+            .map_err(map_query_error(&self.conn))?;
         for row in rows {
-            let Ok(row) = row else { continue };
+            callback(row.map_err(map_query_error(&self.conn))?)?;
+        }
+        Ok(())
+    }
+
+    fn find_by_prefix(
+        &self,
+        prefix: &str,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare(FIND_BY_PREFIX)
+            .map_err(map_query_error(&self.conn))?;
+        let rows = stmt
+            .query_map([prefix], short_url_from_row)
+            .map_err(map_query_error(&self.conn))?;
+        for row in rows {
+            callback(row.map_err(map_query_error(&self.conn))?)?;
+        }
+        Ok(())
+    }
+
+    fn find_by_pattern(
+        &self,
+        pattern: &Pattern,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let (query, like) = match pattern {
+            Pattern::Glob(glob) => (
+                "SELECT shorturl, url, last_modified, title FROM urls \
+                 WHERE shorturl LIKE ? ESCAPE '\\' AND deleted_at IS NULL ORDER BY shorturl",
+                Some(glob_to_like(glob)),
+            ),
+            Pattern::Regex(_) => (
+                "SELECT shorturl, url, last_modified, title FROM urls \
+                 WHERE deleted_at IS NULL ORDER BY shorturl",
+                None,
+            ),
+        };
+        let mut stmt = self
+            .conn
+            .prepare(query)
+            .map_err(map_query_error(&self.conn))?;
+        let rows = match &like {
+            Some(like) => stmt.query_map([like], short_url_from_row),
+            None => stmt.query_map([], short_url_from_row),
+        }
+        .map_err(map_query_error(&self.conn))?;
+        for row in rows {
+            let row = row.map_err(map_query_error(&self.conn))?;
+            if let Pattern::Regex(regex) = pattern {
+                if !regex.is_match(row.name.as_ref()) {
+                    continue;
+                }
+            }
             callback(row)?;
         }
         Ok(())
     }
 
+    fn count_by_prefix(&self, prefix: &str) -> Result<u64, anyhow::Error> {
+        self.conn
+            .query_row(COUNT_BY_PREFIX, [prefix], |row| row.get(0))
+            .map_err(map_query_error(&self.conn))
+    }
+
+    fn content_version(&self, method: ContentVersionMethod) -> Result<u64, anyhow::Error> {
+        let mut hasher = Xxh3::new();
+        match method {
+            ContentVersionMethod::Summary => {
+                let query = "SELECT COUNT(*), MAX(last_modified) FROM urls \
+                             WHERE deleted_at IS NULL";
+                let (count, newest): (u64, Option<u64>) = self
+                    .conn
+                    .query_row(query, [], |row| Ok((row.get(0)?, row.get(1)?)))
+                    .map_err(map_query_error(&self.conn))?;
+                hasher.update(&count.to_le_bytes());
+                hasher.update(&newest.unwrap_or_default().to_le_bytes());
+            }
+            ContentVersionMethod::Hash => {
+                let query = "SELECT shorturl, url, last_modified FROM urls \
+                             WHERE deleted_at IS NULL ORDER BY shorturl";
+                let mut stmt = self
+                    .conn
+                    .prepare(query)
+                    .map_err(map_query_error(&self.conn))?;
+                let mut rows = stmt.query([]).map_err(map_query_error(&self.conn))?;
+                while let Some(row) = rows.next().map_err(map_query_error(&self.conn))? {
+                    let last_modified: Option<u64> = row.get(2)?;
+                    // Separators, so moving bytes between columns changes the hash
+                    hasher.update(row.get_ref(0)?.as_bytes()?);
+                    hasher.update(b"\0");
+                    hasher.update(row.get_ref(1)?.as_bytes()?);
+                    hasher.update(b"\0");
+                    hasher.update(&last_modified.unwrap_or_default().to_le_bytes());
+                }
+            }
+        }
+        Ok(hasher.digest())
+    }
+
+    fn recent(&self, limit: u64) -> Result<Vec<ShortUrl>, anyhow::Error> {
+        let query = "SELECT shortUrl, url, last_modified, title FROM urls \
+                     WHERE deleted_at IS NULL ORDER BY last_modified DESC NULLS LAST LIMIT ?";
+        let mut stmt = self
+            .conn
+            .prepare(query)
+            .map_err(map_query_error(&self.conn))?;
+        let rows = stmt
+            .query_map(rusqlite::params![limit], short_url_from_row)
+            .map_err(map_query_error(&self.conn))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn modified_since(
+        &self,
+        secs_ago: u64,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        let query = "SELECT shorturl, url, last_modified, title FROM urls \
+                     WHERE last_modified >= ? AND deleted_at IS NULL \
+                     ORDER BY last_modified DESC";
+        let mut stmt = self
+            .conn
+            .prepare(query)
+            .map_err(map_query_error(&self.conn))?;
+        let rows = stmt
+            .query_map([now.saturating_sub(secs_ago)], short_url_from_row)
+            .map_err(map_query_error(&self.conn))?;
+        for row in rows {
+            callback(row?)?;
+        }
+        Ok(())
+    }
+
+    fn tags_for(&self, name: &ShortUrlName) -> Result<Vec<String>, anyhow::Error> {
+        let query = "SELECT tag FROM tags WHERE shorturl = ? ORDER BY tag";
+        let mut stmt = self
+            .conn
+            .prepare(query)
+            .map_err(map_query_error(&self.conn))?;
+        let rows = stmt
+            .query_map([name], |row| row.get(0))
+            .map_err(map_query_error(&self.conn))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn find_by_tag(
+        &self,
+        tag: &str,
+        callback: &dyn Fn(ShortUrl) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let query = "SELECT urls.shorturl, url, last_modified, title FROM urls \
+                     JOIN tags ON tags.shorturl = urls.shorturl \
+                     WHERE tag = ? AND deleted_at IS NULL ORDER BY urls.shorturl";
+        let mut stmt = self
+            .conn
+            .prepare(query)
+            .map_err(map_query_error(&self.conn))?;
+        let rows = stmt
+            .query_map([tag], short_url_from_row)
+            .map_err(map_query_error(&self.conn))?;
+        for row in rows {
+            callback(row?)?;
+        }
+        Ok(())
+    }
+
+    fn search(&self, query: SearchQuery) -> Result<Page, anyhow::Error> {
+        use rusqlite::ToSql;
+
+        let host = query
+            .host
+            .map(|host| host.trim_end_matches('.').to_ascii_lowercase());
+        let mut conditions = vec!["deleted_at IS NULL"];
+        let mut params: Vec<(&str, &dyn ToSql)> = Vec::new();
+        if let Some(prefix) = &query.prefix {
+            conditions.push("shorturl >= :prefix AND shorturl < :prefix || char(127)");
+            params.push((":prefix", prefix));
+        }
+        if let Some(tag) = &query.tag {
+            conditions.push(
+                "EXISTS (SELECT 1 FROM tags WHERE tags.shorturl = urls.shorturl AND tag = :tag)",
+            );
+            params.push((":tag", tag));
+        }
+        if let Some(host) = &host {
+            conditions.push(
+                "(url_host(url) = :host \
+                 OR substr(url_host(url), -length(:host) - 1) = '.' || :host)",
+            );
+            params.push((":host", host));
+        }
+        let filter = conditions.join(" AND ");
+        let order = match query.sort {
+            SearchSort::Name => "shorturl",
+            SearchSort::Recent => "last_modified DESC NULLS LAST, shorturl",
+        };
+        // Negative means no limit
+        let limit = query
+            .limit
+            .map_or(-1, |x| i64::try_from(x).unwrap_or(i64::MAX));
+        let offset = i64::try_from(query.offset).unwrap_or(i64::MAX);
+
+        // A read transaction, so the total matches the items
+        let tx = self
+            .conn
+            .unchecked_transaction()
+            .map_err(map_query_error(&self.conn))?;
+        let total = tx
+            .query_row(
+                &format!("SELECT COUNT(*) FROM urls WHERE {filter}"),
+                &*params,
+                |row| row.get(0),
+            )
+            .map_err(map_query_error(&self.conn))?;
+        params.push((":limit", &limit));
+        params.push((":offset", &offset));
+        let mut stmt = tx
+            .prepare(&format!(
+                "SELECT shorturl, url, last_modified, title FROM urls \
+                 WHERE {filter} ORDER BY {order} LIMIT :limit OFFSET :offset"
+            ))
+            .map_err(map_query_error(&self.conn))?;
+        let items = stmt
+            .query_map(&*params, short_url_from_row)
+            .map_err(map_query_error(&self.conn))?
+            .collect::<Result<_, _>>()?;
+        Ok(Page { items, total })
+    }
+
+    fn duplicate_urls(&self) -> Result<Vec<(Url, Vec<ShortUrlName>)>, anyhow::Error> {
+        // Names never contain a comma, so it is safe as a separator
+        let query = "SELECT url, group_concat(shorturl, ',' ORDER BY shorturl) FROM urls \
+                     WHERE deleted_at IS NULL GROUP BY url HAVING COUNT(*) > 1 ORDER BY url";
+        let mut stmt = self
+            .conn
+            .prepare(query)
+            .map_err(map_query_error(&self.conn))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, Url>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(map_query_error(&self.conn))?;
+        let mut duplicates = Vec::new();
+        for row in rows {
+            let (url, names) = row?;
+            let names = names
+                .split(',')
+                .map(ShortUrlName::try_from)
+                .collect::<Result<_, _>>()?;
+            duplicates.push((url, names));
+        }
+        Ok(duplicates)
+    }
+
     fn get_random_quote(&self) -> Result<String, anyhow::Error> {
         let query = "SELECT quote FROM quotations ORDER BY RANDOM() LIMIT 1";
         Ok(self
             .conn
             .query_row(query, rusqlite::params![], |row| row.get(0))
-            .optional()?
+            .optional()
+            .map_err(map_query_error(&self.conn))?
             .unwrap_or_else(|| "Don't panic\n    -- Douglas Adams".to_string()))
     }
 
-    fn has_latest_migrations(&self) -> Result<bool, anyhow::Error> {
-        let migrations = migrations();
-        let user_version: u32 =
-            self.conn
-                .query_row("SELECT user_version FROM pragma_user_version", [], |row| {
-                    row.get(0)
-                })?;
-        Ok(user_version as usize == migrations.len())
+    fn get_random_url(&self) -> Result<Option<ShortUrl>, anyhow::Error> {
+        let query = "SELECT shortUrl, url, last_modified, title FROM urls \
+                     WHERE deleted_at IS NULL ORDER BY RANDOM() LIMIT 1";
+        self.conn
+            .query_row(query, rusqlite::params![], short_url_from_row)
+            .optional()
+            .map_err(map_query_error(&self.conn))
+    }
+
+    fn trashed(&self) -> Result<Vec<(ShortUrl, UnixTimestamp)>, anyhow::Error> {
+        let query = "SELECT shorturl, url, last_modified, title, deleted_at FROM urls \
+                     WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, shorturl";
+        let mut stmt = self
+            .conn
+            .prepare(query)
+            .map_err(map_query_error(&self.conn))?;
+        let rows = stmt
+            .query_map([], |row| Ok((short_url_from_row(row)?, row.get(4)?)))
+            .map_err(map_query_error(&self.conn))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn schema(&self) -> Result<Vec<String>, anyhow::Error> {
+        // Internal objects like sqlite_autoindex_* have no SQL
+        let query = "SELECT sql FROM sqlite_schema WHERE sql IS NOT NULL \
+                     ORDER BY CASE type WHEN 'table' THEN 0 WHEN 'index' THEN 1 ELSE 2 END, \
+                     tbl_name, name";
+        let mut stmt = self
+            .conn
+            .prepare(query)
+            .map_err(map_query_error(&self.conn))?;
+        let rows = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(map_query_error(&self.conn))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn dump_sql(&self, out: &mut dyn std::io::Write) -> Result<(), anyhow::Error> {
+        // A read transaction, so the rows match the schema
+        let tx = self
+            .conn
+            .unchecked_transaction()
+            .map_err(map_query_error(&self.conn))?;
+        let user_version: u32 = tx
+            .query_row("SELECT user_version FROM pragma_user_version", [], |row| {
+                row.get(0)
+            })
+            .map_err(map_query_error(&self.conn))?;
+        // SQLite's own tables last, once the tables they refer to exist
+        let query = "SELECT type, name, sql FROM sqlite_schema WHERE sql IS NOT NULL \
+                     ORDER BY CASE type WHEN 'table' THEN substr(name, 1, 7) = 'sqlite_' \
+                     ELSE 2 END, tbl_name, name";
+        let mut stmt = tx.prepare(query).map_err(map_query_error(&self.conn))?;
+        let objects = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)? == "table",
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .map_err(map_query_error(&self.conn))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        writeln!(out, "BEGIN TRANSACTION;")?;
+        // Indexes and triggers after the rows, so triggers don't touch them
+        for (is_table, name, sql) in &objects {
+            // SQLite reserves the sqlite_ names, so create its tables the
+            // way the sqlite3 shell's .dump does
+            match name.as_str() {
+                "sqlite_sequence" => writeln!(out, "DELETE FROM sqlite_sequence;")?,
+                "sqlite_stat1" => writeln!(out, "ANALYZE sqlite_schema;")?,
+                internal if internal.starts_with("sqlite_") => continue,
+                _ => writeln!(out, "{sql};")?,
+            }
+            if !is_table {
+                continue;
+            }
+            let table = format!("\"{}\"", name.replace('"', "\"\""));
+            let mut stmt = tx
+                .prepare(&format!("SELECT * FROM {table}"))
+                .map_err(map_query_error(&self.conn))?;
+            let columns = stmt.column_count();
+            let mut rows = stmt.query([]).map_err(map_query_error(&self.conn))?;
+            while let Some(row) = rows.next().map_err(map_query_error(&self.conn))? {
+                let values = (0..columns)
+                    .map(|i| row.get_ref(i).map(sql_literal))
+                    .collect::<Result<Vec<_>, _>>()?;
+                writeln!(out, "INSERT INTO {table} VALUES ({});", values.join(", "))?;
+            }
+        }
+        writeln!(out, "PRAGMA user_version = {user_version};")?;
+        writeln!(out, "COMMIT;")?;
+        Ok(())
+    }
+
+    fn is_writable(&self) -> bool {
+        self.conn
+            .is_readonly(rusqlite::MAIN_DB)
+            .is_ok_and(|readonly| !readonly)
+    }
+
+    fn migration_state(&self) -> Result<MigrationState, anyhow::Error> {
+        Ok(migration_state(&self.conn)?)
     }
 }
 
 #[inline]
-const fn migrations() -> [&'static str; 2] {
+const fn migrations() -> [&'static str; 6] {
     [
         include_str!("migrations/sqlite/1.up.sql"),
         include_str!("migrations/sqlite/2.up.sql"),
+        include_str!("migrations/sqlite/3.up.sql"),
+        include_str!("migrations/sqlite/4.up.sql"),
+        include_str!("migrations/sqlite/5.up.sql"),
+        include_str!("migrations/sqlite/6.up.sql"),
     ]
 }
 
+/// Setting a name that is in the trash takes it out again, without the
+/// title, tags and aliases of the old link, see [`forget_trashed`].
+const INSERT_URL: &str = "INSERT INTO urls (shorturl, url) VALUES (?1, ?2) \
+                          ON CONFLICT(shorturl) DO UPDATE SET url = excluded.url, \
+                          title = iif(deleted_at IS NULL, title, NULL), deleted_at = NULL";
+
+/// Run before inserting `name`, as the tags and aliases of a trashed
+/// name belong to the old link.
+fn forget_trashed(conn: &Connection, name: &ShortUrlName) -> rusqlite::Result<()> {
+    let is_trashed: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM urls WHERE shorturl = ? AND deleted_at IS NOT NULL)",
+        [name],
+        |row| row.get(0),
+    )?;
+    if is_trashed {
+        conn.execute("DELETE FROM tags WHERE shorturl = ?", [name])?;
+        conn.execute("DELETE FROM aliases WHERE shorturl = ?", [name])?;
+    }
+    Ok(())
+}
+
 impl WritableRepository for Sqlite3Repo {
     fn migrate(&mut self) -> Result<(), anyhow::Error> {
-        // EXCLUSIVE ensures that it starts with an exclusive write lock. No other
-        // readers will be allowed. This generally shouldn't be needed if there is
-        // a file lock, but might be helpful in cases where cargo's `FileLock`
-        // failed.
-        let migrations = migrations();
-        let tx = self
-            .conn
-            .transaction_with_behavior(TransactionBehavior::Exclusive)?;
-        let user_version: usize =
-            tx.query_row("SELECT user_version FROM pragma_user_version", [], |row| {
-                row.get(0)
-            })?;
-        if user_version < migrations.len() {
-            for migration in &migrations[user_version..] {
-                tx.execute_batch(migration)?;
+        self.run_migrations()
+    }
+
+    fn migrate_with_retry(&mut self, retry: BusyRetry) -> Result<(), anyhow::Error> {
+        let mut repo = self.keep_busy_timeout()?;
+        let mut timeout = retry.timeout;
+        for _ in 0..=retry.retries {
+            repo.conn.busy_timeout(timeout)?;
+            match repo.run_migrations() {
+                Err(err) if is_busy(&err) => timeout = timeout.saturating_mul(2),
+                result => return result,
             }
-            tx.pragma_update(None, "user_version", u32::try_from(migrations.len())?)?;
         }
-        tx.commit()?;
-        Ok(())
+        Err(RepositoryError::Locked.into())
     }
 
     fn insert_url(
@@ -138,57 +778,503 @@ impl WritableRepository for Sqlite3Repo {
         name: &ShortUrlName,
         url: &crate::types::Url,
     ) -> Result<(), anyhow::Error> {
-        let query = "INSERT INTO urls (shorturl, url) VALUES (?1, ?2) ON CONFLICT(shorturl) DO UPDATE SET url = excluded.url";
-        self.conn.execute(query, rusqlite::params![name, url])?;
+        name.check_not_reserved()?;
+        let tx = self.conn.transaction()?;
+        forget_trashed(&tx, name)?;
+        tx.execute(INSERT_URL, rusqlite::params![name, url])?;
+        tx.commit()?;
         Ok(())
     }
 
-    fn insert_quotation(&mut self, collection: &str) -> Result<(), anyhow::Error> {
-        let query = "INSERT INTO quotations (collection, quote) VALUES (?, ?)";
-        self.conn
-            .execute(query, rusqlite::params!["default", collection])?;
+    fn insert_urls(&mut self, urls: &[(ShortUrlName, Url)]) -> Result<(), anyhow::Error> {
+        for (name, _) in urls {
+            name.check_not_reserved()?;
+        }
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(INSERT_URL)?;
+            for (name, url) in urls {
+                forget_trashed(&tx, name)?;
+                stmt.execute(rusqlite::params![name, url])?;
+            }
+        }
+        tx.commit()?;
         Ok(())
     }
-}
 
-/// # Errors
-///
-/// Will return `Err` if `path` cannot be converted to a C-compatible
-/// string or if the underlying SQLite open call fails.
-pub fn open_readonly_repository<P: AsRef<Path>>(path: P) -> Result<impl Repository, anyhow::Error> {
-    Sqlite3Repo::open(path, Some(OpenFlags::SQLITE_OPEN_READ_ONLY))
-}
+    fn insert_urls_with_titles(
+        &mut self,
+        urls: &[(ShortUrlName, Url, Option<String>)],
+    ) -> Result<(), anyhow::Error> {
+        for (name, _, _) in urls {
+            name.check_not_reserved()?;
+        }
+        self.transaction(|tx| {
+            let mut stmt = tx.prepare(INSERT_URL)?;
+            let mut set_title = tx.prepare("UPDATE urls SET title = ?2 WHERE shorturl = ?1")?;
+            for (name, url, title) in urls {
+                forget_trashed(tx, name)?;
+                stmt.execute(rusqlite::params![name, url])?;
+                if let Some(title) = title {
+                    set_title.execute(rusqlite::params![name, title])?;
+                }
+            }
+            Ok(())
+        })
+    }
 
-/// # Errors
-///
-/// Will return `Err` if `path` cannot be converted to a C-compatible
-/// string or if the underlying SQLite open call fails.
-pub fn open_writable_repository<P: AsRef<Path>>(
-    path: P,
-) -> Result<impl WritableRepository, anyhow::Error> {
-    Sqlite3Repo::open(path, None)
-}
+    fn get_or_insert(
+        &mut self,
+        name: &ShortUrlName,
+        url: &Url,
+    ) -> Result<(ShortUrl, bool), anyhow::Error> {
+        name.check_not_reserved()?;
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        forget_trashed(&tx, name)?;
+        let created = tx.execute(
+            "INSERT INTO urls (shorturl, url) VALUES (?1, ?2) ON CONFLICT(shorturl) \
+             DO UPDATE SET url = excluded.url, title = NULL, deleted_at = NULL \
+             WHERE deleted_at IS NOT NULL",
+            rusqlite::params![name, url],
+        )? == 1;
+        let short_url = tx.query_row(
+            "SELECT shorturl, url, last_modified, title FROM urls WHERE shorturl = ?",
+            [name],
+            short_url_from_row,
+        )?;
+        tx.commit()?;
+        Ok((short_url, created))
+    }
 
-/// # Errors
-///
-/// Will return `Err` if the underlying SQLite open call fails.
-#[doc(hidden)]
-pub fn open_writable_in_memory_repository() -> Result<impl WritableRepository, anyhow::Error> {
-    Ok(Sqlite3Repo::new(rusqlite::Connection::open_in_memory()?))
-}
+    fn set_tags(&mut self, name: &ShortUrlName, tags: &[String]) -> Result<(), anyhow::Error> {
+        self.transaction(|tx| {
+            tx.execute("DELETE FROM tags WHERE shorturl = ?", [name])?;
+            // Store the name as spelled in urls, and only if it exists
+            let mut stmt = tx.prepare(
+                "INSERT INTO tags (shorturl, tag) SELECT shorturl, ?2 FROM urls \
+                 WHERE shorturl = ?1 AND deleted_at IS NULL ON CONFLICT DO NOTHING",
+            )?;
+            for tag in tags {
+                stmt.execute(rusqlite::params![name, tag])?;
+            }
+            Ok(())
+        })
+    }
 
-#[cfg(test)]
-mod test {
+    fn delete_url(&mut self, name: &ShortUrlName) -> Result<bool, anyhow::Error> {
+        let query = if self.soft_delete {
+            "UPDATE urls SET deleted_at = unixepoch() WHERE shorturl = ? AND deleted_at IS NULL"
+        } else {
+            "DELETE FROM urls WHERE shorturl = ?"
+        };
+        // Names and aliases never overlap, so at most one row matches
+        let tx = self.conn.transaction().map_err(map_sqlite_error)?;
+        let count = tx.execute(query, [name]).map_err(map_query_error(&tx))?
+            + tx.execute("DELETE FROM aliases WHERE alias = ?", [name])
+                .map_err(map_query_error(&tx))?;
+        tx.commit().map_err(map_sqlite_error)?;
+        Ok(count > 0)
+    }
+
+    fn delete_many(&mut self, names: &[ShortUrlName]) -> Result<u64, anyhow::Error> {
+        let tx = self.conn.transaction().map_err(map_sqlite_error)?;
+        let mut count = 0;
+        for chunk in names.chunks(MAX_PARAMS) {
+            let params = vec!["?"; chunk.len()].join(", ");
+            let query = if self.soft_delete {
+                format!(
+                    "UPDATE urls SET deleted_at = unixepoch() \
+                     WHERE shorturl IN ({params}) AND deleted_at IS NULL"
+                )
+            } else {
+                format!("DELETE FROM urls WHERE shorturl IN ({params})")
+            };
+            count += tx
+                .execute(&query, rusqlite::params_from_iter(chunk))
+                .map_err(map_query_error(&tx))?;
+            count += tx
+                .execute(
+                    &format!("DELETE FROM aliases WHERE alias IN ({params})"),
+                    rusqlite::params_from_iter(chunk),
+                )
+                .map_err(map_query_error(&tx))?;
+        }
+        tx.commit().map_err(map_sqlite_error)?;
+        Ok(count as u64)
+    }
+
+    fn restore_url(&mut self, name: &ShortUrlName) -> Result<bool, anyhow::Error> {
+        let query =
+            "UPDATE urls SET deleted_at = NULL WHERE shorturl = ? AND deleted_at IS NOT NULL";
+        Ok(self
+            .conn
+            .execute(query, [name])
+            .map_err(map_query_error(&self.conn))?
+            > 0)
+    }
+
+    fn empty_trash(&mut self, secs_ago: u64) -> Result<u64, anyhow::Error> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        let query = "DELETE FROM urls WHERE deleted_at <= ?";
+        let count = self
+            .conn
+            .execute(query, [now.saturating_sub(secs_ago)])
+            .map_err(map_query_error(&self.conn))?;
+        Ok(count as u64)
+    }
+
+    fn insert_quotation(&mut self, quote: &str) -> Result<bool, anyhow::Error> {
+        let query = "INSERT INTO quotations (collection, quote) VALUES (?, ?) \
+                     ON CONFLICT(collection, quote) DO NOTHING";
+        Ok(self
+            .conn
+            .execute(query, rusqlite::params!["default", quote])?
+            == 1)
+    }
+
+    fn insert_quotations(
+        &mut self,
+        collection: &str,
+        quotes: &[String],
+    ) -> Result<usize, anyhow::Error> {
+        let tx = self.conn.transaction().map_err(map_sqlite_error)?;
+        let mut count = 0;
+        {
+            let mut stmt = tx
+                .prepare("INSERT OR IGNORE INTO quotations (collection, quote) VALUES (?, ?)")
+                .map_err(map_query_error(&tx))?;
+            for quote in quotes {
+                count += stmt
+                    .execute([collection, quote])
+                    .map_err(map_query_error(&tx))?;
+            }
+        }
+        tx.commit().map_err(map_sqlite_error)?;
+        Ok(count)
+    }
+
+    fn set_title(
+        &mut self,
+        name: &ShortUrlName,
+        title: Option<&str>,
+    ) -> Result<bool, anyhow::Error> {
+        let query = "UPDATE urls SET title = ?2 WHERE shorturl = ?1 AND deleted_at IS NULL";
+        Ok(self
+            .conn
+            .execute(query, rusqlite::params![name, title])
+            .map_err(map_query_error(&self.conn))?
+            > 0)
+    }
+
+    fn touch(&mut self, name: &ShortUrlName) -> Result<bool, anyhow::Error> {
+        let query =
+            "UPDATE urls SET last_modified = unixepoch() WHERE shorturl = ? AND deleted_at IS NULL";
+        Ok(self
+            .conn
+            .execute(query, [name])
+            .map_err(map_query_error(&self.conn))?
+            > 0)
+    }
+
+    fn swap_targets(&mut self, a: &ShortUrlName, b: &ShortUrlName) -> Result<(), anyhow::Error> {
+        let tx = self.conn.transaction().map_err(map_sqlite_error)?;
+        let url = |name: &ShortUrlName| -> Result<String, anyhow::Error> {
+            tx.query_row(
+                "SELECT url FROM urls WHERE shorturl = ? AND deleted_at IS NULL",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(map_query_error(&tx))?
+            .ok_or_else(|| anyhow::anyhow!("short URL {name} not found"))
+        };
+        let (url_a, url_b) = (url(a)?, url(b)?);
+        let query = "UPDATE urls SET url = ?2 WHERE shorturl = ?1";
+        tx.execute(query, rusqlite::params![a, url_b])
+            .map_err(map_query_error(&tx))?;
+        tx.execute(query, rusqlite::params![b, url_a])
+            .map_err(map_query_error(&tx))?;
+        tx.commit().map_err(map_sqlite_error)?;
+        Ok(())
+    }
+
+    fn add_alias(
+        &mut self,
+        alias: &ShortUrlName,
+        canonical: &ShortUrlName,
+    ) -> Result<(), anyhow::Error> {
+        alias.check_not_reserved()?;
+        let tx = self.conn.transaction().map_err(map_sqlite_error)?;
+        // Some(true) for a short URL, Some(false) if it is in the trash
+        let is_url = |name: &ShortUrlName| -> Result<Option<bool>, anyhow::Error> {
+            tx.query_row(
+                "SELECT deleted_at IS NULL FROM urls WHERE shorturl = ?",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(map_query_error(&tx))
+        };
+        // A trashed name is taken, as it may be restored
+        match is_url(alias)? {
+            Some(true) => return Err(anyhow::anyhow!("{alias} is a short URL, not an alias")),
+            Some(false) => return Err(anyhow::anyhow!("{alias} is a short URL in the trash")),
+            None => {}
+        }
+        if is_url(canonical)? != Some(true) {
+            // Covers aliases too, so no chains or cycles
+            return Err(anyhow::anyhow!("short URL {canonical} not found"));
+        }
+        tx.execute(
+            "INSERT INTO aliases (alias, shorturl) VALUES (?1, ?2) \
+             ON CONFLICT(alias) DO UPDATE SET shorturl = excluded.shorturl",
+            [alias, canonical],
+        )
+        .map_err(map_query_error(&tx))?;
+        tx.commit().map_err(map_sqlite_error)?;
+        Ok(())
+    }
+
+    fn recover(&mut self) -> Result<u64, anyhow::Error> {
+        // TRUNCATE reports the emptied log, so count with PASSIVE first.
+        // Both give -1 frames if the database is not in WAL mode.
+        let checkpoint = |mode: &str| -> Result<i64, anyhow::Error> {
+            let (busy, checkpointed): (bool, i64) = self
+                .conn
+                .query_row(&format!("PRAGMA wal_checkpoint({mode})"), [], |row| {
+                    Ok((row.get(0)?, row.get(2)?))
+                })
+                .map_err(map_query_error(&self.conn))?;
+            if busy {
+                return Err(RepositoryError::Locked.into());
+            }
+            Ok(checkpointed)
+        };
+        let checkpointed = checkpoint("PASSIVE")?;
+        checkpoint("TRUNCATE")?;
+        let mut stmt = self
+            .conn
+            .prepare("PRAGMA integrity_check")
+            .map_err(map_query_error(&self.conn))?;
+        let problems = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(map_query_error(&self.conn))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if problems != ["ok"] {
+            return Err(anyhow::anyhow!(
+                "integrity check failed: {}",
+                problems.join("; ")
+            ));
+        }
+        Ok(checkpointed.try_into().unwrap_or_default())
+    }
+
+    fn rename_collection(&mut self, old: &str, new: &str) -> Result<u64, anyhow::Error> {
+        let query = "UPDATE OR IGNORE quotations SET collection = ?2 WHERE collection = ?1";
+        let count = self
+            .conn
+            .execute(query, [old, new])
+            .map_err(map_query_error(&self.conn))?;
+        Ok(count as u64)
+    }
+
+    fn clear_urls(&mut self) -> Result<u64, anyhow::Error> {
+        let tx = self.conn.transaction()?;
+        let count = tx.execute("DELETE FROM urls", [])?;
+        tx.commit()?;
+        Ok(count as u64)
+    }
+
+    fn clear_quotations(&mut self) -> Result<u64, anyhow::Error> {
+        let tx = self.conn.transaction()?;
+        let count = tx.execute("DELETE FROM quotations", [])?;
+        tx.commit()?;
+        Ok(count as u64)
+    }
+}
+
+fn trace_statement(event: TraceEvent<'_>) {
+    if let TraceEvent::Profile(stmt, duration) = event {
+        // One line per statement, also for the multi-line migrations
+        let sql = stmt.sql().split_whitespace().collect::<Vec<_>>().join(" ");
+        eprintln!("sql: {:.3}ms {sql}", duration.as_secs_f64() * 1000.0);
+    }
+}
+
+type ConnectionSetup = dyn Fn(&Connection) -> rusqlite::Result<()>;
+
+/// Opens repositories like the `open_*` functions, but runs a setup
+/// callback on each new connection before it is used, e.g. to register
+/// custom SQL functions or collations.
+///
+/// ```
+/// use shorty::repository::sqlite::OpenOptions;
+///
+/// let repo = OpenOptions::new()
+///     .with_connection_setup(|conn| {
+///         conn.create_collation("reverse", |a, b| b.cmp(a))
+///     })
+///     .open_writable_in_memory()
+///     .unwrap();
+/// # drop(repo);
+/// ```
+#[derive(Default)]
+pub struct OpenOptions {
+    setup: Option<Box<ConnectionSetup>>,
+    soft_delete: bool,
+    trace: bool,
+}
+
+impl OpenOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with_connection_setup(
+        mut self,
+        f: impl Fn(&Connection) -> rusqlite::Result<()> + 'static,
+    ) -> Self {
+        self.setup = Some(Box::new(f));
+        self
+    }
+
+    /// Make [`WritableRepository::delete_url`] move short URLs to the
+    /// trash, from where they can be restored, instead of removing them.
+    #[must_use]
+    pub const fn with_soft_delete(mut self, soft_delete: bool) -> Self {
+        self.soft_delete = soft_delete;
+        self
+    }
+
+    /// Log every SQL statement with how long it ran to stderr, e.g. to
+    /// find queries missing an index.
+    #[must_use]
+    pub const fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    fn setup(&self, conn: Connection) -> Result<Sqlite3Repo, anyhow::Error> {
+        if self.trace {
+            conn.trace_v2(TraceEventCodes::SQLITE_TRACE_PROFILE, Some(trace_statement));
+        }
+        // Before the callback, so it can replace the built-in functions
+        let mut repo = Sqlite3Repo::new(conn)?;
+        if let Some(setup) = &self.setup {
+            setup(&repo.conn)?;
+        }
+        repo.soft_delete = self.soft_delete;
+        Ok(repo)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if `path` cannot be converted to a C-compatible
+    /// string, if the underlying SQLite open call fails or if the setup
+    /// callback fails.
+    pub fn open_readonly<P: AsRef<Path>>(&self, path: P) -> Result<impl Repository, anyhow::Error> {
+        self.setup(Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if `path` cannot be converted to a C-compatible
+    /// string, if the underlying SQLite open call fails or if the setup
+    /// callback fails.
+    pub fn open_writable<P: AsRef<Path>>(&self, path: P) -> Result<Sqlite3Repo, anyhow::Error> {
+        self.setup(Connection::open(path)?)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the underlying SQLite open call fails or if
+    /// the setup callback fails.
+    pub fn open_writable_in_memory(&self) -> Result<Sqlite3Repo, anyhow::Error> {
+        self.setup(Connection::open_in_memory()?)
+    }
+}
+
+impl core::fmt::Debug for OpenOptions {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("OpenOptions")
+            .field("setup", &self.setup.is_some())
+            .field("soft_delete", &self.soft_delete)
+            .field("trace", &self.trace)
+            .finish()
+    }
+}
+
+/// # Errors
+///
+/// Will return `Err` if `path` cannot be converted to a C-compatible
+/// string or if the underlying SQLite open call fails.
+pub fn open_readonly_repository<P: AsRef<Path>>(path: P) -> Result<impl Repository, anyhow::Error> {
+    Sqlite3Repo::open(path, Some(OpenFlags::SQLITE_OPEN_READ_ONLY))
+}
+
+/// Like [`open_readonly_repository`], but statements still running after
+/// `deadline` are aborted with [`RepositoryError::Interrupted`].
+///
+/// # Errors
+///
+/// Will return `Err` if `path` cannot be converted to a C-compatible
+/// string or if the underlying SQLite open call fails.
+pub fn open_readonly_repository_with_deadline<P: AsRef<Path>>(
+    path: P,
+    deadline: Option<SystemTime>,
+) -> Result<impl Repository, anyhow::Error> {
+    let repo = Sqlite3Repo::open(path, Some(OpenFlags::SQLITE_OPEN_READ_ONLY))?;
+    if let Some(deadline) = deadline {
+        repo.set_deadline(deadline)?;
+    }
+    Ok(repo)
+}
+
+/// # Errors
+///
+/// Will return `Err` if `path` cannot be converted to a C-compatible
+/// string or if the underlying SQLite open call fails.
+pub fn open_writable_repository<P: AsRef<Path>>(path: P) -> Result<Sqlite3Repo, anyhow::Error> {
+    Sqlite3Repo::open(path, None)
+}
+
+/// # Errors
+///
+/// Will return `Err` if the underlying SQLite open call fails.
+#[doc(hidden)]
+pub fn open_writable_in_memory_repository() -> Result<Sqlite3Repo, anyhow::Error> {
+    Sqlite3Repo::new(rusqlite::Connection::open_in_memory()?)
+}
+
+#[cfg(test)]
+mod test {
     use rusqlite::Connection;
 
-    use super::Sqlite3Repo;
+    use core::time::Duration;
+
+    use super::{
+        map_query_error, map_sqlite_error, open_readonly_repository, OpenOptions, Sqlite3Repo,
+        COUNT_BY_PREFIX, FIND_BY_PREFIX, MAX_PARAMS,
+    };
     use crate::{
-        repository::{Repository, WritableRepository},
-        types::{ShortUrl, ShortUrlName},
+        repository::{
+            BusyRetry, ContentVersionMethod, MigrationState, Pattern, Repository, RepositoryError,
+            ResolveOpts, SearchQuery, SearchSort, UrlStatus, WritableRepository,
+        },
+        types::{ShortUrl, ShortUrlName, UnixTimestamp, Url},
     };
 
     fn repo() -> Sqlite3Repo {
-        let mut repo = Sqlite3Repo::new(Connection::open_in_memory().unwrap());
+        let mut repo = Sqlite3Repo::new(Connection::open_in_memory().unwrap()).unwrap();
         repo.migrate().unwrap();
         repo
     }
@@ -200,6 +1286,7 @@ mod test {
             name: name.clone(),
             url: "https://example.com".try_into().unwrap(),
             last_modified: None,
+            title: None,
         };
         let mut repo = repo();
 
@@ -224,6 +1311,7 @@ mod test {
             name: name.clone(),
             url: "https://example.com/changed".try_into().unwrap(),
             last_modified: inserted_result.last_modified,
+            title: None,
         };
         repo.insert_url(&short_url.name, &short_url.url).unwrap();
         let result = repo.get_url(&name).unwrap();
@@ -233,4 +1321,1393 @@ mod test {
         assert_eq!(result.name, short_url.name);
         assert_eq!(result.url, short_url.url);
     }
+
+    #[test]
+    fn test_recent() {
+        let repo = repo();
+        repo.conn
+            .execute_batch(
+                "DROP TRIGGER set_last_modified_on_insert;
+                 INSERT INTO urls (shorturl, url, last_modified) VALUES
+                     ('old', 'https://example.com/old', 100),
+                     ('none', 'https://example.com/none', NULL),
+                     ('new', 'https://example.com/new', 300),
+                     ('mid', 'https://example.com/mid', 200);",
+            )
+            .unwrap();
+
+        let names = |limit| {
+            repo.recent(limit)
+                .unwrap()
+                .into_iter()
+                .map(|x| x.name.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(10), ["new", "mid", "old", "none"]);
+        assert_eq!(names(2), ["new", "mid"]);
+    }
+
+    #[test]
+    fn test_modified_since() {
+        let repo = repo();
+        repo.conn
+            .execute_batch(
+                "DROP TRIGGER set_last_modified_on_insert;
+                 INSERT INTO urls (shorturl, url, last_modified) VALUES
+                     ('hour', 'https://example.com/hour', unixepoch() - 3600),
+                     ('none', 'https://example.com/none', NULL),
+                     ('now', 'https://example.com/now', unixepoch()),
+                     ('min', 'https://example.com/min', unixepoch() - 60);",
+            )
+            .unwrap();
+
+        let names = |secs_ago| {
+            let names = core::cell::RefCell::new(Vec::new());
+            repo.modified_since(secs_ago, &|x| {
+                names.borrow_mut().push(x.name.to_string());
+                Ok(())
+            })
+            .unwrap();
+            names.into_inner()
+        };
+        assert_eq!(names(600), ["now", "min"]);
+        assert_eq!(names(7200), ["now", "min", "hour"]);
+        assert_eq!(names(u64::MAX), ["now", "min", "hour"]);
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let mut repo = repo();
+        repo.conn
+            .execute(
+                "INSERT INTO urls (shorturl, url, last_modified) VALUES (?, ?, ?)",
+                rusqlite::params!["aa", "https://example.com/?a=1,2", 1_000],
+            )
+            .unwrap();
+        repo.insert_url(
+            &"bb".try_into().unwrap(),
+            &"https://example.com/".try_into().unwrap(),
+        )
+        .unwrap();
+        let last_modified = repo
+            .get_url(&"bb".try_into().unwrap())
+            .unwrap()
+            .unwrap()
+            .last_modified
+            .unwrap();
+
+        let mut out = Vec::new();
+        repo.export_csv(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "shorturl,url,last_modified\r\n\
+                 aa,\"https://example.com/?a=1,2\",1000\r\n\
+                 bb,https://example.com/,{last_modified}\r\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_connection_setup() {
+        use rusqlite::functions::FunctionFlags;
+
+        let options = OpenOptions::new().with_connection_setup(|conn| {
+            conn.create_scalar_function(
+                "name_rank",
+                1,
+                FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+                |ctx| Ok(ctx.get::<String>(0)?.len()),
+            )
+        });
+        let mut repo = options
+            .setup(Connection::open_in_memory().unwrap())
+            .unwrap();
+        repo.migrate().unwrap();
+        repo.insert_url(
+            &"abc".try_into().unwrap(),
+            &"https://example.com".try_into().unwrap(),
+        )
+        .unwrap();
+
+        let rank: i64 = repo
+            .conn
+            .query_row("SELECT name_rank(shorturl) FROM urls", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rank, 3);
+    }
+
+    #[test]
+    fn test_connection_setup_replaces_url_host() {
+        use rusqlite::functions::FunctionFlags;
+
+        let options = OpenOptions::new().with_connection_setup(|conn| {
+            conn.create_scalar_function(
+                "url_host",
+                1,
+                FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+                |_| Ok("mirror.example"),
+            )
+        });
+        let mut repo = options
+            .setup(Connection::open_in_memory().unwrap())
+            .unwrap();
+        repo.migrate().unwrap();
+        repo.insert_url(
+            &"abc".try_into().unwrap(),
+            &"https://example.com".try_into().unwrap(),
+        )
+        .unwrap();
+
+        let search = |host: &str| {
+            repo.search(SearchQuery {
+                host: Some(host.to_string()),
+                ..Default::default()
+            })
+            .unwrap()
+            .total
+        };
+        assert_eq!(search("mirror.example"), 1);
+        assert_eq!(search("example.com"), 0);
+    }
+
+    #[test]
+    fn test_connection_setup_error() {
+        let options =
+            OpenOptions::new().with_connection_setup(|conn| conn.execute_batch("NOT SQL"));
+        assert!(options.open_writable_in_memory().is_err());
+    }
+
+    #[test]
+    fn test_insert_urls() {
+        let mut repo = repo();
+        let urls = [
+            (
+                "aa".try_into().unwrap(),
+                "https://example.com/a".try_into().unwrap(),
+            ),
+            (
+                "bb".try_into().unwrap(),
+                "https://example.com/b".try_into().unwrap(),
+            ),
+            (
+                "AA".try_into().unwrap(),
+                "https://example.com/c".try_into().unwrap(),
+            ),
+        ];
+        repo.insert_urls(&urls).unwrap();
+        assert_eq!(repo.iter_short_urls().unwrap().count(), 2);
+        let aa = repo.get_url(&urls[0].0).unwrap().unwrap();
+        assert_eq!(aa.url.to_string(), "https://example.com/c");
+    }
+
+    #[test]
+    fn test_get_or_insert() {
+        let mut repo = repo();
+        let name: ShortUrlName = "ab".try_into().unwrap();
+        let url = "https://example.com/a".try_into().unwrap();
+
+        let (short_url, created) = repo.get_or_insert(&name, &url).unwrap();
+        assert!(created);
+        assert_eq!(short_url.url, url);
+        assert!(short_url.last_modified.is_some());
+
+        let other = "https://example.com/b".try_into().unwrap();
+        let upper: ShortUrlName = "AB".try_into().unwrap();
+        let (short_url, created) = repo.get_or_insert(&upper, &other).unwrap();
+        assert!(!created);
+        assert_eq!(short_url.name.as_ref(), "ab");
+        assert_eq!(short_url.url, url);
+        assert_eq!(repo.get_url(&name).unwrap().unwrap().url, url);
+    }
+
+    #[test]
+    fn test_delete_url() {
+        let mut repo = repo();
+        let name: ShortUrlName = "ab".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+
+        assert!(repo.delete_url(&"AB".try_into().unwrap()).unwrap());
+        assert!(repo.get_url(&name).unwrap().is_none());
+        assert!(!repo.delete_url(&name).unwrap());
+    }
+
+    #[test]
+    fn test_clear_urls() {
+        let mut repo = repo();
+        for name in ["aa", "bb"] {
+            repo.insert_url(
+                &name.try_into().unwrap(),
+                &"https://example.com".try_into().unwrap(),
+            )
+            .unwrap();
+        }
+        repo.set_tags(&"aa".try_into().unwrap(), &["docs".to_string()])
+            .unwrap();
+        repo.insert_quotation("Mostly harmless").unwrap();
+
+        assert_eq!(repo.clear_urls().unwrap(), 2);
+        assert_eq!(repo.iter_short_urls().unwrap().count(), 0);
+        let tags: u64 = repo
+            .conn
+            .query_row("SELECT COUNT(*) FROM tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tags, 0);
+        assert_eq!(repo.get_random_quote().unwrap(), "Mostly harmless");
+        assert_eq!(repo.clear_urls().unwrap(), 0);
+
+        assert_eq!(repo.clear_quotations().unwrap(), 1);
+        assert_eq!(repo.clear_quotations().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_content_version() {
+        let mut repo = repo();
+        let versions = |repo: &Sqlite3Repo| {
+            [ContentVersionMethod::Summary, ContentVersionMethod::Hash]
+                .map(|method| repo.content_version(method).unwrap())
+        };
+        let empty = versions(&repo);
+        assert_eq!(versions(&repo), empty);
+
+        let name: ShortUrlName = "aa".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com/".try_into().unwrap())
+            .unwrap();
+        let inserted = versions(&repo);
+        assert_ne!(inserted[0], empty[0]);
+        assert_ne!(inserted[1], empty[1]);
+        assert_eq!(versions(&repo), inserted);
+
+        // Same count and, within the second, the same newest timestamp
+        repo.transaction(|tx| {
+            tx.execute("UPDATE urls SET url = 'https://example.org/'", [])?;
+            Ok(())
+        })
+        .unwrap();
+        assert_ne!(versions(&repo)[1], inserted[1]);
+    }
+
+    #[test]
+    fn test_dump_sql() {
+        let mut repo = repo();
+        let name: ShortUrlName = "aa".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com/?a='b'".try_into().unwrap())
+            .unwrap();
+        repo.set_title(&name, Some("Don't \"panic\"\nnow")).unwrap();
+        repo.set_tags(&name, &["docs".to_string()]).unwrap();
+        repo.insert_quotations("adams", &["It's 42".to_string()])
+            .unwrap();
+        let mut out = Vec::new();
+        repo.dump_sql(&mut out).unwrap();
+        let sql = String::from_utf8(out).unwrap();
+        assert!(sql.contains("'Don''t \"panic\"\nnow'"), "{sql}");
+
+        let copy = Sqlite3Repo::new(Connection::open_in_memory().unwrap()).unwrap();
+        copy.conn.execute_batch(&sql).unwrap();
+        assert_eq!(copy.migration_state().unwrap(), MigrationState::UpToDate);
+        assert_eq!(copy.schema().unwrap(), repo.schema().unwrap());
+        assert_eq!(copy.get_url(&name).unwrap(), repo.get_url(&name).unwrap());
+        assert_eq!(copy.tags_for(&name).unwrap(), ["docs"]);
+        assert_eq!(quotes_in(&copy, "adams"), ["It's 42"]);
+    }
+
+    #[test]
+    fn test_dump_sql_analyzed() {
+        let mut repo = repo();
+        for name in ["aa", "bb", "cc"] {
+            repo.insert_url(
+                &name.try_into().unwrap(),
+                &"https://example.com/".try_into().unwrap(),
+            )
+            .unwrap();
+            repo.set_tags(&name.try_into().unwrap(), &["docs".to_string()])
+                .unwrap();
+        }
+        repo.conn.execute_batch("ANALYZE").unwrap();
+        let stats = |repo: &Sqlite3Repo| -> Vec<(String, Option<String>, String)> {
+            let mut stmt = repo
+                .conn
+                .prepare("SELECT tbl, idx, stat FROM sqlite_stat1 ORDER BY tbl, idx")
+                .unwrap();
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        assert!(!stats(&repo).is_empty());
+
+        let mut out = Vec::new();
+        repo.dump_sql(&mut out).unwrap();
+        let sql = String::from_utf8(out).unwrap();
+        assert!(!sql.contains("CREATE TABLE sqlite_stat1"), "{sql}");
+
+        let copy = Sqlite3Repo::new(Connection::open_in_memory().unwrap()).unwrap();
+        copy.conn.execute_batch(&sql).unwrap();
+        assert_eq!(copy.schema().unwrap(), repo.schema().unwrap());
+        assert_eq!(stats(&copy), stats(&repo));
+        assert_eq!(copy.iter_short_urls().unwrap().count(), 3);
+    }
+
+    fn quotes_in(repo: &Sqlite3Repo, collection: &str) -> Vec<String> {
+        let mut stmt = repo
+            .conn
+            .prepare("SELECT quote FROM quotations WHERE collection = ? ORDER BY quote")
+            .unwrap();
+        stmt.query_map([collection], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_rename_collection() {
+        let mut repo = repo();
+        repo.insert_quotation("Mostly harmless").unwrap();
+        repo.insert_quotation("Don't panic").unwrap();
+
+        assert_eq!(repo.rename_collection("DEFAULT", "adams").unwrap(), 2);
+        assert!(quotes_in(&repo, "default").is_empty());
+        assert_eq!(
+            quotes_in(&repo, "adams"),
+            ["Don't panic", "Mostly harmless"]
+        );
+        assert_eq!(repo.rename_collection("missing", "adams").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_rename_collection_collision() {
+        let mut repo = repo();
+        repo.insert_quotation("Mostly harmless").unwrap();
+        repo.insert_quotation("Don't panic").unwrap();
+        repo.conn
+            .execute(
+                "INSERT INTO quotations (collection, quote) VALUES ('adams', 'Don''t panic')",
+                [],
+            )
+            .unwrap();
+
+        assert_eq!(repo.rename_collection("default", "adams").unwrap(), 1);
+        assert_eq!(quotes_in(&repo, "default"), ["Don't panic"]);
+        assert_eq!(
+            quotes_in(&repo, "adams"),
+            ["Don't panic", "Mostly harmless"]
+        );
+    }
+
+    #[test]
+    fn test_schema() {
+        let empty = Sqlite3Repo::new(Connection::open_in_memory().unwrap()).unwrap();
+        assert!(empty.schema().unwrap().is_empty());
+
+        let schema = repo().schema().unwrap();
+        assert!(schema[0].starts_with("CREATE TABLE aliases"));
+        assert!(schema.iter().any(|x| x.starts_with("CREATE TABLE urls")));
+        assert!(schema
+            .iter()
+            .any(|x| x.starts_with("CREATE INDEX tags_tag")));
+        assert!(schema
+            .iter()
+            .any(|x| x.starts_with("CREATE TRIGGER set_last_modified_on_insert")));
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut repo = repo();
+        let insert = |tx: &rusqlite::Transaction<'_>, name: &str| {
+            tx.execute(
+                "INSERT INTO urls (shorturl, url) VALUES (?, 'https://example.com/')",
+                [name],
+            )
+        };
+
+        let count = repo
+            .transaction(|tx| {
+                insert(tx, "aa")?;
+                insert(tx, "bb")?;
+                Ok(2)
+            })
+            .unwrap();
+        assert_eq!(count, 2);
+        assert!(repo.get_url(&"bb".try_into().unwrap()).unwrap().is_some());
+
+        let result: anyhow::Result<()> = repo.transaction(|tx| {
+            insert(tx, "cc")?;
+            Err(anyhow::anyhow!("changed my mind"))
+        });
+        assert_eq!(result.unwrap_err().to_string(), "changed my mind");
+        assert!(repo.get_url(&"cc".try_into().unwrap()).unwrap().is_none());
+
+        // A failing statement rolls back the ones before it
+        let result = repo.transaction(|tx| {
+            insert(tx, "dd")?;
+            insert(tx, "AA")?;
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert!(repo.get_url(&"dd".try_into().unwrap()).unwrap().is_none());
+        assert_eq!(repo.iter_short_urls().unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_set_title() {
+        let mut repo = repo();
+        let name: ShortUrlName = "ab".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+        assert_eq!(repo.get_url(&name).unwrap().unwrap().title, None);
+
+        assert!(repo
+            .set_title(&"AB".try_into().unwrap(), Some("Example"))
+            .unwrap());
+        assert_eq!(
+            repo.get_url(&name).unwrap().unwrap().title.as_deref(),
+            Some("Example")
+        );
+        // Replacing the URL keeps the title
+        repo.insert_url(&name, &"https://example.org".try_into().unwrap())
+            .unwrap();
+        assert_eq!(
+            repo.get_url(&name).unwrap().unwrap().title.as_deref(),
+            Some("Example")
+        );
+
+        assert!(repo.set_title(&name, None).unwrap());
+        assert_eq!(repo.get_url(&name).unwrap().unwrap().title, None);
+        assert!(!repo
+            .set_title(&"cd".try_into().unwrap(), Some("x"))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_iter_short_urls() {
+        let mut repo = repo();
+        for name in ["aa", "bb", "cc"] {
+            repo.insert_url(
+                &name.try_into().unwrap(),
+                &"https://example.com".try_into().unwrap(),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(repo.iter_short_urls().unwrap().take(2).count(), 2);
+
+        let mut names = repo
+            .iter_short_urls()
+            .unwrap()
+            .map(|short_url| short_url.unwrap().name.to_string())
+            .filter(|name| name != "bb")
+            .take(5)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["aa", "cc"]);
+    }
+
+    fn hold_read_lock(path: &std::path::Path, duration: Duration) -> std::thread::JoinHandle<()> {
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "BEGIN; SELECT * FROM pragma_user_version; SELECT count(*) FROM sqlite_schema;",
+        )
+        .unwrap();
+        std::thread::spawn(move || {
+            std::thread::sleep(duration);
+            conn.execute_batch("COMMIT").unwrap();
+        })
+    }
+
+    #[test]
+    fn test_migration_state() {
+        let mut repo = Sqlite3Repo::new(Connection::open_in_memory().unwrap()).unwrap();
+        assert_eq!(repo.migration_state().unwrap(), MigrationState::Pending);
+        repo.migrate().unwrap();
+        assert_eq!(repo.migration_state().unwrap(), MigrationState::UpToDate);
+        assert!(repo.has_latest_migrations().unwrap());
+
+        let ahead = u32::try_from(super::migrations().len() + 1).unwrap();
+        repo.conn
+            .pragma_update(None, "user_version", ahead)
+            .unwrap();
+        assert_eq!(repo.migration_state().unwrap(), MigrationState::Ahead);
+        assert!(!repo.has_latest_migrations().unwrap());
+        let err = repo.migrate().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(&RepositoryError::SchemaAhead)
+        );
+        // Left as it was
+        assert_eq!(repo.migration_state().unwrap(), MigrationState::Ahead);
+    }
+
+    #[test]
+    fn test_is_writable() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.db");
+        let mut repo = Sqlite3Repo::open(&path, None).unwrap();
+        repo.migrate().unwrap();
+        assert!(repo.is_writable());
+
+        let repo = open_readonly_repository(&path).unwrap();
+        assert!(!repo.is_writable());
+    }
+
+    #[test]
+    fn test_recover() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.db");
+        let mut repo = Sqlite3Repo::open(&path, None).unwrap();
+        assert_eq!(repo.recover().unwrap(), 0);
+
+        repo.conn
+            .pragma_update(None, "journal_mode", "WAL")
+            .unwrap();
+        repo.migrate().unwrap();
+        repo.insert_url(
+            &"aa".try_into().unwrap(),
+            &"https://example.com".try_into().unwrap(),
+        )
+        .unwrap();
+        let wal = temp_dir.path().join("test.db-wal");
+        assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+
+        assert!(repo.recover().unwrap() > 0);
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+        assert!(repo.get_url(&"aa".try_into().unwrap()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_migrate_with_retry() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.db");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch("CREATE TABLE lock_me (x)")
+            .unwrap();
+
+        let reader = hold_read_lock(&path, Duration::from_millis(300));
+        let mut repo = Sqlite3Repo::open(&path, None).unwrap();
+        let busy_timeout = |repo: &Sqlite3Repo| {
+            repo.conn
+                .pragma_query_value(None, "busy_timeout", |row| row.get::<_, u64>(0))
+                .unwrap()
+        };
+        let before = busy_timeout(&repo);
+        let err = repo
+            .migrate_with_retry(BusyRetry {
+                retries: 1,
+                timeout: Duration::from_millis(10),
+            })
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(&RepositoryError::Locked)
+        );
+        assert_eq!(busy_timeout(&repo), before);
+
+        repo.migrate_with_retry(BusyRetry {
+            retries: 8,
+            timeout: Duration::from_millis(10),
+        })
+        .unwrap();
+        reader.join().unwrap();
+        assert!(repo.has_latest_migrations().unwrap());
+        assert_eq!(busy_timeout(&repo), before);
+    }
+
+    #[test]
+    fn test_touch() {
+        let mut repo = repo();
+        let name: ShortUrlName = "ab".try_into().unwrap();
+        let url: Url = "https://example.com".try_into().unwrap();
+        assert!(!repo.touch(&name).unwrap());
+        repo.insert_url(&name, &url).unwrap();
+        let inserted = repo.get_url(&name).unwrap().unwrap();
+
+        // Sleep to make sure we get a new last_modified
+        std::thread::sleep(core::time::Duration::from_secs(1));
+
+        assert!(repo.touch(&name).unwrap());
+        let touched = repo.get_url(&name).unwrap().unwrap();
+        assert!(touched.last_modified > inserted.last_modified);
+        assert_eq!(touched.url, url);
+    }
+
+    #[test]
+    fn test_reserved_name() {
+        let mut repo = repo();
+        let name: ShortUrlName = "random".try_into().unwrap();
+        let url: Url = "https://example.com/".try_into().unwrap();
+        assert!(repo.insert_url(&name, &url).is_err());
+        assert!(repo.insert_urls(&[(name.clone(), url.clone())]).is_err());
+        assert!(repo.get_or_insert(&name, &url).is_err());
+        repo.insert_url(&"aa".try_into().unwrap(), &url).unwrap();
+        assert!(repo.add_alias(&name, &"aa".try_into().unwrap()).is_err());
+
+        // Stored before the name was reserved
+        repo.conn
+            .execute(
+                "INSERT INTO urls (shorturl, url) VALUES ('random', ?)",
+                [&url],
+            )
+            .unwrap();
+        assert_eq!(repo.get_url(&name).unwrap().unwrap().url, url);
+        assert_eq!(repo.iter_short_urls().unwrap().count(), 2);
+        assert!(repo.delete_url(&name).unwrap());
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut repo = repo();
+        let name = |x: &str| -> ShortUrlName { x.try_into().unwrap() };
+        let url: Url = "https://example.com/".try_into().unwrap();
+        repo.insert_url(&name("aa"), &url).unwrap();
+        repo.set_title(&name("aa"), Some("A")).unwrap();
+        repo.add_alias(&name("Bb"), &name("aa")).unwrap();
+
+        let short_url = repo.get_url(&name("bb")).unwrap().unwrap();
+        assert_eq!(short_url.name.as_ref(), "Bb");
+        assert_eq!(short_url.url, url);
+        assert_eq!(short_url.title.as_deref(), Some("A"));
+        assert_eq!(
+            repo.last_modified(&name("Bb")).unwrap(),
+            repo.last_modified(&name("aa")).unwrap()
+        );
+        assert!(repo.last_modified(&name("Bb")).unwrap().is_some());
+        assert!(repo.get_url(&name("cc")).unwrap().is_none());
+
+        // No chains, cycles or names that are both
+        let err = repo.add_alias(&name("cc"), &name("bb")).unwrap_err();
+        assert_eq!(err.to_string(), "short URL bb not found");
+        let err = repo.add_alias(&name("aa"), &name("bb")).unwrap_err();
+        assert_eq!(err.to_string(), "aa is a short URL, not an alias");
+        assert!(repo.insert_url(&name("bb"), &url).is_err());
+
+        // Removing an alias leaves the short URL
+        assert!(repo.delete_url(&name("BB")).unwrap());
+        assert!(repo.get_url(&name("bb")).unwrap().is_none());
+        assert!(repo.get_url(&name("aa")).unwrap().is_some());
+        repo.add_alias(&name("bb"), &name("aa")).unwrap();
+        assert_eq!(repo.delete_many(&[name("bb"), name("cc")]).unwrap(), 1);
+        assert!(repo.get_url(&name("bb")).unwrap().is_none());
+        repo.add_alias(&name("bb"), &name("aa")).unwrap();
+
+        repo.delete_url(&name("aa")).unwrap();
+        assert!(repo.get_url(&name("bb")).unwrap().is_none());
+        repo.insert_url(&name("bb"), &url).unwrap();
+    }
+
+    #[test]
+    fn test_swap_targets() {
+        let mut repo = repo();
+        let (blue, green, gone): (ShortUrlName, ShortUrlName, ShortUrlName) = (
+            "blue".try_into().unwrap(),
+            "green".try_into().unwrap(),
+            "gone".try_into().unwrap(),
+        );
+        let (url_blue, url_green): (Url, Url) = (
+            "https://blue.example.com".try_into().unwrap(),
+            "https://green.example.com".try_into().unwrap(),
+        );
+        repo.insert_url(&blue, &url_blue).unwrap();
+        repo.insert_url(&green, &url_green).unwrap();
+
+        repo.swap_targets(&blue, &"GREEN".try_into().unwrap())
+            .unwrap();
+        assert_eq!(repo.get_url(&blue).unwrap().unwrap().url, url_green);
+        assert_eq!(repo.get_url(&green).unwrap().unwrap().url, url_blue);
+
+        let err = repo.swap_targets(&blue, &gone).unwrap_err();
+        assert_eq!(err.to_string(), "short URL gone not found");
+        assert_eq!(repo.get_url(&blue).unwrap().unwrap().url, url_green);
+    }
+
+    #[test]
+    fn test_insert_quotation() {
+        let mut repo = repo();
+        assert!(repo.insert_quotation("Don't panic").unwrap());
+        assert!(!repo.insert_quotation("Don't panic").unwrap());
+        assert!(repo.insert_quotation("Mostly harmless").unwrap());
+    }
+
+    #[test]
+    fn test_insert_quotations() {
+        let mut repo = repo();
+        repo.insert_quotation("Don't panic").unwrap();
+        let quotes = ["Don't panic", "Mostly harmless", "Mostly harmless"].map(String::from);
+        assert_eq!(repo.insert_quotations("default", &quotes).unwrap(), 1);
+        assert_eq!(repo.insert_quotations("adams", &quotes).unwrap(), 2);
+        assert_eq!(
+            quotes_in(&repo, "default"),
+            ["Don't panic", "Mostly harmless"]
+        );
+        assert_eq!(
+            quotes_in(&repo, "adams"),
+            ["Don't panic", "Mostly harmless"]
+        );
+    }
+
+    #[test]
+    fn test_tags() {
+        let mut repo = repo();
+        let url = "https://example.com".try_into().unwrap();
+        let aa: ShortUrlName = "aa".try_into().unwrap();
+        let bb: ShortUrlName = "bb".try_into().unwrap();
+        repo.insert_url(&aa, &url).unwrap();
+        repo.insert_url(&bb, &url).unwrap();
+        let tagged = |repo: &Sqlite3Repo, tag| {
+            let names = core::cell::RefCell::new(Vec::new());
+            repo.find_by_tag(tag, &|x| {
+                names.borrow_mut().push(x.name.to_string());
+                Ok(())
+            })
+            .unwrap();
+            names.into_inner()
+        };
+
+        assert!(repo.tags_for(&aa).unwrap().is_empty());
+        assert!(tagged(&repo, "work").is_empty());
+
+        let tags = ["work".to_string(), "docs".to_string(), "Work".to_string()];
+        repo.set_tags(&"AA".try_into().unwrap(), &tags).unwrap();
+        repo.set_tags(&bb, &["work".to_string()]).unwrap();
+        assert_eq!(repo.tags_for(&aa).unwrap(), ["docs", "work"]);
+        assert_eq!(tagged(&repo, "WORK"), ["aa", "bb"]);
+        assert_eq!(tagged(&repo, "docs"), ["aa"]);
+
+        // Replaces, and clears with no tags
+        repo.set_tags(&aa, &["misc".to_string()]).unwrap();
+        assert_eq!(repo.tags_for(&aa).unwrap(), ["misc"]);
+        repo.set_tags(&aa, &[]).unwrap();
+        assert!(repo.tags_for(&aa).unwrap().is_empty());
+
+        // Unknown names and deleted names have no tags
+        let cc: ShortUrlName = "cc".try_into().unwrap();
+        repo.set_tags(&cc, &["work".to_string()]).unwrap();
+        assert!(repo.tags_for(&cc).unwrap().is_empty());
+        repo.delete_url(&bb).unwrap();
+        assert!(tagged(&repo, "work").is_empty());
+    }
+
+    #[test]
+    fn test_get_url_ci_exact() {
+        let mut repo = repo();
+        let name: ShortUrlName = "ab".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+
+        let (short_url, exact) = repo.get_url_ci_exact(&name).unwrap().unwrap();
+        assert!(exact);
+        assert_eq!(short_url.name.as_ref(), "ab");
+
+        let upper: ShortUrlName = "AB".try_into().unwrap();
+        let (short_url, exact) = repo.get_url_ci_exact(&upper).unwrap().unwrap();
+        assert!(!exact);
+        assert_eq!(short_url.name.as_ref(), "ab");
+
+        let missing: ShortUrlName = "cd".try_into().unwrap();
+        assert!(repo.get_url_ci_exact(&missing).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_last_modified() {
+        let mut repo = repo();
+        let name: ShortUrlName = "ab".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+        repo.conn
+            .execute_batch(
+                "DROP TRIGGER set_last_modified_on_update;
+                 UPDATE urls SET last_modified = 1000000000;",
+            )
+            .unwrap();
+
+        assert_eq!(
+            repo.last_modified(&name).unwrap(),
+            Some(UnixTimestamp(1_000_000_000))
+        );
+        // Only the exact spelling, the other case gets a redirect
+        assert_eq!(repo.last_modified(&"AB".try_into().unwrap()).unwrap(), None);
+        assert_eq!(repo.last_modified(&"cd".try_into().unwrap()).unwrap(), None);
+
+        repo.conn
+            .execute_batch("UPDATE urls SET last_modified = NULL")
+            .unwrap();
+        assert_eq!(repo.last_modified(&name).unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_or_404_status() {
+        let mut repo = repo();
+        let name: ShortUrlName = "ab".try_into().unwrap();
+        repo.insert_url(&name, &"https://example.com".try_into().unwrap())
+            .unwrap();
+        let stored = repo.get_url(&name).unwrap().unwrap();
+
+        assert_eq!(
+            repo.get_or_404_status(&name).unwrap(),
+            UrlStatus::Found(stored.clone())
+        );
+        assert_eq!(
+            repo.get_or_404_status(&"AB".try_into().unwrap()).unwrap(),
+            UrlStatus::OtherCase(stored)
+        );
+        assert_eq!(
+            repo.get_or_404_status(&"cd".try_into().unwrap()).unwrap(),
+            UrlStatus::NotFound
+        );
+    }
+
+    #[test]
+    fn test_get_random_url() {
+        let mut repo = repo();
+        assert_eq!(repo.get_random_url().unwrap(), None);
+
+        let names: Vec<ShortUrlName> = ["aa", "bb", "cc"]
+            .into_iter()
+            .map(|x| x.try_into().unwrap())
+            .collect();
+        for name in &names {
+            repo.insert_url(name, &"https://example.com".try_into().unwrap())
+                .unwrap();
+        }
+        for _ in 0..10 {
+            let short_url = repo.get_random_url().unwrap().unwrap();
+            assert!(names.contains(&short_url.name));
+            assert_eq!(short_url, repo.get_url(&short_url.name).unwrap().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_count_by_prefix() {
+        let mut repo = repo();
+        for name in ["ab-1", "ab-2", "AB-10", "abc", "a_b", "ba"] {
+            repo.insert_url(
+                &name.try_into().unwrap(),
+                &"https://example.com".try_into().unwrap(),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(repo.count_by_prefix("ab-").unwrap(), 3);
+        assert_eq!(repo.count_by_prefix("ab-1").unwrap(), 2);
+        assert_eq!(repo.count_by_prefix("ab").unwrap(), 4);
+        // `_` is a literal, not a wildcard
+        assert_eq!(repo.count_by_prefix("a_").unwrap(), 1);
+        assert_eq!(repo.count_by_prefix("%").unwrap(), 0);
+        assert_eq!(repo.count_by_prefix("").unwrap(), 6);
+
+        let names = core::cell::RefCell::new(Vec::new());
+        repo.find_by_prefix("AB-", &|x| {
+            names.borrow_mut().push(x.name.to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(names.into_inner(), ["ab-1", "AB-10", "ab-2"]);
+    }
+
+    #[test]
+    fn test_prefix_query_plan() {
+        let mut repo = repo();
+        let url: Url = "https://example.com".try_into().unwrap();
+        let rows: Vec<_> = (0..2000)
+            .map(|i| (format!("n{i}").as_str().try_into().unwrap(), url.clone()))
+            .collect();
+        repo.insert_urls(&rows).unwrap();
+        repo.conn.execute_batch("ANALYZE").unwrap();
+
+        for query in [FIND_BY_PREFIX, COUNT_BY_PREFIX] {
+            let plan: Vec<String> = repo
+                .conn
+                .prepare(&format!("EXPLAIN QUERY PLAN {query}"))
+                .unwrap()
+                .query_map(["n1"], |row| row.get(3))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert!(
+                plan.iter().all(|x| x.starts_with("SEARCH urls USING")),
+                "{query}: {plan:?}"
+            );
+        }
+        assert_eq!(repo.count_by_prefix("N1").unwrap(), 1111);
+    }
+
+    #[test]
+    fn test_find_by_pattern() {
+        let mut repo = repo();
+        for name in ["ab-1", "ab-2", "AB-10", "abc", "a_b", "xab"] {
+            repo.insert_url(
+                &name.try_into().unwrap(),
+                &"https://example.com".try_into().unwrap(),
+            )
+            .unwrap();
+        }
+        let find = |pattern: &Pattern| {
+            let names = core::cell::RefCell::new(Vec::new());
+            repo.find_by_pattern(pattern, &|x| {
+                names.borrow_mut().push(x.name.to_string());
+                Ok(())
+            })
+            .unwrap();
+            names.into_inner()
+        };
+
+        assert_eq!(
+            find(&Pattern::Glob("AB-*".to_string())),
+            ["ab-1", "AB-10", "ab-2"]
+        );
+        assert_eq!(find(&Pattern::Glob("ab-?".to_string())), ["ab-1", "ab-2"]);
+        // `_` is a literal, not a wildcard
+        assert_eq!(find(&Pattern::Glob("a_?".to_string())), ["a_b"]);
+        assert_eq!(
+            find(&Pattern::Regex(regex::Regex::new("^ab-[0-9]$").unwrap())),
+            ["ab-1", "ab-2"]
+        );
+        assert_eq!(
+            find(&Pattern::Regex(regex::Regex::new("(?i)ab-1").unwrap())),
+            ["ab-1", "AB-10"]
+        );
+    }
+
+    #[test]
+    fn test_delete_many() {
+        let mut repo = repo();
+        let names: Vec<ShortUrlName> = (0..MAX_PARAMS + 10)
+            .map(|i| format!("n{i}").try_into().unwrap())
+            .collect();
+        let url: Url = "https://example.com/".try_into().unwrap();
+        repo.insert_urls(
+            &names
+                .iter()
+                .step_by(2)
+                .map(|name| (name.clone(), url.clone()))
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let existing = repo.count_by_prefix("").unwrap();
+
+        // Half of the names exist, spread over both chunks
+        assert_eq!(repo.delete_many(&names).unwrap(), existing);
+        assert_eq!(repo.count_by_prefix("").unwrap(), 0);
+        assert_eq!(repo.delete_many(&names).unwrap(), 0);
+        assert_eq!(repo.delete_many(&[]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_find_by_host() {
+        let mut repo = repo();
+        for (name, url) in [
+            ("aa", "https://example.com/x"),
+            ("bb", "https://www.example.com"),
+            ("cc", "https://notexample.com"),
+            ("dd", "https://example.com.evil.org"),
+            ("ee", "http://[2001:db8::1]:8080"),
+        ] {
+            repo.insert_url(&name.try_into().unwrap(), &url.try_into().unwrap())
+                .unwrap();
+        }
+        let find = |host: &str| {
+            let names = core::cell::RefCell::new(Vec::new());
+            repo.find_by_host(host, &|x| {
+                names.borrow_mut().push(x.name.to_string());
+                Ok(())
+            })
+            .unwrap();
+            names.into_inner()
+        };
+
+        assert_eq!(find("example.com"), ["aa", "bb"]);
+        assert_eq!(find("EXAMPLE.com."), ["aa", "bb"]);
+        assert_eq!(find("www.example.com"), ["bb"]);
+        assert_eq!(find("[2001:db8::1]"), ["ee"]);
+        assert!(find("com.evil").is_empty());
+    }
+
+    #[test]
+    fn test_search() {
+        let mut repo = repo();
+        for (name, url) in [
+            ("ab-1", "https://example.com/1"),
+            ("ab-2", "https://www.example.com/2"),
+            ("ab-3", "https://other.org/3"),
+            ("AB-4", "https://EXAMPLE.com/4"),
+            ("ab-5", "https://example.com.evil.org/5"),
+            ("cd-1", "https://example.com/6"),
+        ] {
+            repo.insert_url(&name.try_into().unwrap(), &url.try_into().unwrap())
+                .unwrap();
+        }
+        repo.conn
+            .execute_batch("DROP TRIGGER set_last_modified_on_update")
+            .unwrap();
+        for (name, ts) in [("ab-1", 100), ("ab-2", 300), ("AB-4", 200)] {
+            repo.conn
+                .execute(
+                    "UPDATE urls SET last_modified = ? WHERE shorturl = ?",
+                    rusqlite::params![ts, name],
+                )
+                .unwrap();
+        }
+        repo.set_tags(&"ab-2".try_into().unwrap(), &["Docs".to_string()])
+            .unwrap();
+        repo.set_tags(&"cd-1".try_into().unwrap(), &["docs".to_string()])
+            .unwrap();
+        let search = |query: SearchQuery| {
+            let page = repo.search(query).unwrap();
+            let names: Vec<_> = page.items.iter().map(|x| x.name.to_string()).collect();
+            (names, page.total)
+        };
+
+        let query = SearchQuery {
+            prefix: Some("ab".to_string()),
+            host: Some("Example.com.".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            search(query.clone()),
+            (vec!["ab-1".into(), "ab-2".into(), "AB-4".into()], 3)
+        );
+        assert_eq!(
+            search(SearchQuery {
+                limit: Some(2),
+                offset: 1,
+                ..query.clone()
+            }),
+            (vec!["ab-2".into(), "AB-4".into()], 3)
+        );
+        assert_eq!(
+            search(SearchQuery {
+                offset: 3,
+                ..query.clone()
+            }),
+            (vec![], 3)
+        );
+        assert_eq!(
+            search(SearchQuery {
+                sort: SearchSort::Recent,
+                limit: Some(2),
+                ..query.clone()
+            }),
+            (vec!["ab-2".into(), "AB-4".into()], 3)
+        );
+        assert_eq!(
+            search(SearchQuery {
+                tag: Some("DOCS".to_string()),
+                ..query
+            }),
+            (vec!["ab-2".into()], 1)
+        );
+        assert_eq!(
+            search(SearchQuery {
+                tag: Some("docs".to_string()),
+                ..Default::default()
+            }),
+            (vec!["ab-2".into(), "cd-1".into()], 2)
+        );
+        assert_eq!(search(SearchQuery::default()).1, 6);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut repo = repo();
+        let name: ShortUrlName = "ab".try_into().unwrap();
+        repo.insert_url(&name, &"http://example.com/x?a=1".try_into().unwrap())
+            .unwrap();
+        let resolve = |opts| repo.resolve(&name, opts).unwrap().unwrap().to_string();
+
+        assert_eq!(resolve(ResolveOpts::default()), "http://example.com/x?a=1");
+        assert_eq!(
+            resolve(ResolveOpts {
+                https_upgrade: true,
+                ..Default::default()
+            }),
+            "https://example.com/x?a=1"
+        );
+        assert_eq!(
+            resolve(ResolveOpts {
+                base_path: Some("/app/"),
+                ..Default::default()
+            }),
+            "http://example.com/app/x?a=1"
+        );
+        assert_eq!(
+            resolve(ResolveOpts {
+                forward_query: Some("ref=x"),
+                ..Default::default()
+            }),
+            "http://example.com/x?a=1&ref=x"
+        );
+        assert_eq!(
+            resolve(ResolveOpts {
+                https_upgrade: true,
+                base_path: Some("app"),
+                forward_query: Some("?ref=x"),
+            }),
+            "https://example.com/app/x?a=1&ref=x"
+        );
+        assert_eq!(
+            repo.resolve(&"cd".try_into().unwrap(), ResolveOpts::default())
+                .unwrap(),
+            None
+        );
+    }
+
+    fn soft_delete_repo(names: &[&str]) -> Sqlite3Repo {
+        let mut repo = repo();
+        repo.soft_delete = true;
+        for name in names {
+            repo.insert_url(
+                &(*name).try_into().unwrap(),
+                &"https://example.com/".try_into().unwrap(),
+            )
+            .unwrap();
+        }
+        repo
+    }
+
+    #[test]
+    fn test_soft_delete_and_restore() {
+        let mut repo = soft_delete_repo(&["aa", "bb"]);
+        let aa: ShortUrlName = "aa".try_into().unwrap();
+        repo.set_tags(&aa, &["work".to_string()]).unwrap();
+
+        assert!(repo.delete_url(&"AA".try_into().unwrap()).unwrap());
+        assert!(!repo.delete_url(&aa).unwrap());
+        assert_eq!(repo.get_url(&aa).unwrap(), None);
+        assert_eq!(repo.count_by_prefix("").unwrap(), 1);
+        let trashed = repo.trashed().unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].0.name, aa);
+
+        assert!(repo.restore_url(&aa).unwrap());
+        assert!(!repo.restore_url(&aa).unwrap());
+        assert!(repo.get_url(&aa).unwrap().is_some());
+        assert!(repo.trashed().unwrap().is_empty());
+        // Tags survive the trip through the trash
+        assert_eq!(repo.tags_for(&aa).unwrap(), ["work"]);
+    }
+
+    #[test]
+    fn test_soft_delete_and_empty_trash() {
+        let mut repo = soft_delete_repo(&["aa", "bb", "cc"]);
+        let aa: ShortUrlName = "aa".try_into().unwrap();
+        let bb: ShortUrlName = "bb".try_into().unwrap();
+        repo.delete_url(&aa).unwrap();
+        repo.delete_url(&bb).unwrap();
+        repo.conn
+            .execute(
+                "UPDATE urls SET deleted_at = 1000 WHERE shorturl = 'aa'",
+                [],
+            )
+            .unwrap();
+
+        // Only rows deleted at least a day ago
+        assert_eq!(repo.empty_trash(24 * 60 * 60).unwrap(), 1);
+        assert_eq!(repo.trashed().unwrap().len(), 1);
+        assert!(!repo.restore_url(&aa).unwrap());
+
+        // Setting a trashed name takes it out of the trash
+        repo.insert_url(&bb, &"https://example.com/new".try_into().unwrap())
+            .unwrap();
+        assert!(repo.trashed().unwrap().is_empty());
+        assert_eq!(repo.count_by_prefix("").unwrap(), 2);
+        assert_eq!(repo.empty_trash(0).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_get_or_insert_trashed() {
+        let mut repo = soft_delete_repo(&["aa"]);
+        let aa: ShortUrlName = "aa".try_into().unwrap();
+        repo.delete_url(&aa).unwrap();
+        let url = "https://example.com/new".try_into().unwrap();
+        let (short_url, created) = repo.get_or_insert(&aa, &url).unwrap();
+        assert!(created);
+        assert_eq!(short_url.url, url);
+    }
+
+    #[test]
+    fn test_insert_trashed_clears_title_and_tags() {
+        let mut repo = soft_delete_repo(&["aa", "bb"]);
+        let url: Url = "https://example.com/new".try_into().unwrap();
+        for name in ["aa", "bb"] {
+            let name: ShortUrlName = name.try_into().unwrap();
+            repo.set_title(&name, Some("Old")).unwrap();
+            repo.set_tags(&name, &["old".to_string()]).unwrap();
+            repo.delete_url(&name).unwrap();
+        }
+
+        let aa: ShortUrlName = "aa".try_into().unwrap();
+        repo.insert_url(&aa, &url).unwrap();
+        assert_eq!(repo.get_url(&aa).unwrap().unwrap().title, None);
+        assert!(repo.tags_for(&aa).unwrap().is_empty());
+
+        let bb: ShortUrlName = "bb".try_into().unwrap();
+        let (short_url, _) = repo.get_or_insert(&bb, &url).unwrap();
+        assert_eq!(short_url.title, None);
+        assert!(repo.tags_for(&bb).unwrap().is_empty());
+
+        // A live name keeps them
+        repo.set_title(&aa, Some("New")).unwrap();
+        repo.set_tags(&aa, &["new".to_string()]).unwrap();
+        repo.insert_url(&aa, &url).unwrap();
+        assert_eq!(
+            repo.get_url(&aa).unwrap().unwrap().title.as_deref(),
+            Some("New")
+        );
+        assert_eq!(repo.tags_for(&aa).unwrap(), ["new"]);
+    }
+
+    #[test]
+    fn test_insert_urls_with_titles() {
+        let mut repo = repo();
+        let aa: ShortUrlName = "aa".try_into().unwrap();
+        let bb: ShortUrlName = "bb".try_into().unwrap();
+        let url: Url = "https://example.com/".try_into().unwrap();
+        repo.insert_url(&bb, &url).unwrap();
+        repo.set_title(&bb, Some("Kept")).unwrap();
+
+        repo.insert_urls_with_titles(&[
+            (aa.clone(), url.clone(), Some("Title".to_string())),
+            (bb.clone(), url.clone(), None),
+        ])
+        .unwrap();
+        assert_eq!(
+            repo.get_url(&aa).unwrap().unwrap().title.as_deref(),
+            Some("Title")
+        );
+        assert_eq!(
+            repo.get_url(&bb).unwrap().unwrap().title.as_deref(),
+            Some("Kept")
+        );
+
+        // Nothing is saved if a row fails
+        let cc: ShortUrlName = "cc".try_into().unwrap();
+        let alias: ShortUrlName = "dd".try_into().unwrap();
+        repo.add_alias(&alias, &aa).unwrap();
+        assert!(repo
+            .insert_urls_with_titles(&[
+                (cc.clone(), url.clone(), Some("Title".to_string())),
+                (alias, url, None),
+            ])
+            .is_err());
+        assert_eq!(repo.get_url(&cc).unwrap(), None);
+    }
+
+    #[test]
+    fn test_insert_trashed_drops_aliases() {
+        let mut repo = soft_delete_repo(&["aa"]);
+        let aa: ShortUrlName = "aa".try_into().unwrap();
+        let bb: ShortUrlName = "bb".try_into().unwrap();
+        repo.add_alias(&bb, &aa).unwrap();
+        repo.delete_url(&aa).unwrap();
+
+        repo.insert_url(&aa, &"https://example.org/".try_into().unwrap())
+            .unwrap();
+        assert!(matches!(
+            repo.get_or_404_status(&bb).unwrap(),
+            UrlStatus::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_add_alias_trashed_name() {
+        let mut repo = soft_delete_repo(&["aa", "bb"]);
+        let aa: ShortUrlName = "aa".try_into().unwrap();
+        let bb: ShortUrlName = "bb".try_into().unwrap();
+        repo.delete_url(&aa).unwrap();
+
+        let err = repo.add_alias(&aa, &bb).unwrap_err();
+        assert_eq!(err.to_string(), "aa is a short URL in the trash");
+        let err = repo.add_alias(&"cc".try_into().unwrap(), &aa).unwrap_err();
+        assert_eq!(err.to_string(), "short URL aa not found");
+        assert!(repo.restore_url(&aa).unwrap());
+    }
+
+    #[test]
+    fn test_duplicate_urls() {
+        let mut repo = repo();
+        for (name, url) in [
+            ("bb", "https://example.com/shared"),
+            ("aa", "https://example.com/shared"),
+            ("cc", "https://example.com/unique"),
+        ] {
+            repo.insert_url(&name.try_into().unwrap(), &url.try_into().unwrap())
+                .unwrap();
+        }
+
+        let duplicates = repo.duplicate_urls().unwrap();
+        assert_eq!(duplicates.len(), 1);
+        let (url, names) = &duplicates[0];
+        assert_eq!(url.to_string(), "https://example.com/shared");
+        assert_eq!(
+            names,
+            &[
+                ShortUrlName::try_from("aa").unwrap(),
+                ShortUrlName::try_from("bb").unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_random_quote_not_migrated() {
+        let empty = Sqlite3Repo::new(Connection::open_in_memory().unwrap()).unwrap();
+        let err = empty.get_random_quote().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(&RepositoryError::Migration)
+        );
+
+        // Other SQL errors are passed through untouched
+        let err = map_query_error(&empty.conn)(empty.conn.execute("NOT SQL", []).unwrap_err());
+        assert!(err.downcast_ref::<RepositoryError>().is_none());
+        assert!(err.downcast_ref::<rusqlite::Error>().is_some());
+
+        // A missing table in a migrated schema is a bug, not a migration
+        let repo = repo();
+        repo.conn.execute("DROP TABLE quotations", []).unwrap();
+        let err = repo.get_random_quote().unwrap_err();
+        assert!(err.downcast_ref::<RepositoryError>().is_none());
+        assert!(err.to_string().contains("no such table: quotations"));
+    }
+
+    #[test]
+    fn test_deadline_interrupts_long_query() {
+        let repo = repo();
+        repo.set_deadline(std::time::SystemTime::now() + core::time::Duration::from_millis(50))
+            .unwrap();
+
+        // Never terminates on its own
+        let query = "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) \
+                     SELECT COUNT(*) FROM c";
+        let err = repo
+            .conn
+            .query_row(query, [], |row| row.get::<_, i64>(0))
+            .map_err(map_sqlite_error)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(&RepositoryError::Interrupted)
+        );
+    }
+
+    #[test]
+    fn test_deadline_caps_busy_wait() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.db");
+        Sqlite3Repo::open(&path, None).unwrap().migrate().unwrap();
+        let writer = Connection::open(&path).unwrap();
+        writer.execute_batch("BEGIN EXCLUSIVE").unwrap();
+
+        let started = std::time::SystemTime::now();
+        let repo =
+            Sqlite3Repo::open(&path, Some(rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)).unwrap();
+        repo.set_deadline(started + Duration::from_millis(50))
+            .unwrap();
+        let err = repo.get_url(&"aa".try_into().unwrap()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(&RepositoryError::Locked)
+        );
+        // Well before the default busy timeout
+        assert!(started.elapsed().unwrap() < Duration::from_secs(2));
+    }
 }
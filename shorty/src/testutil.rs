@@ -0,0 +1,59 @@
+//! Shared setup for tests in this and downstream crates.
+//!
+//! Only compiled for tests or with the `testutil` feature.
+
+use std::path::PathBuf;
+
+use tempfile::TempDir;
+
+use crate::repository::{sqlite::open_writable_repository, WritableRepository};
+
+/// Name of the database file inside the directory returned by [`seeded_repo`].
+pub const DATABASE_FILE_NAME: &str = "test.db";
+
+#[must_use]
+pub fn database_path(temp_dir: &TempDir) -> PathBuf {
+    temp_dir.path().join(DATABASE_FILE_NAME)
+}
+
+/// A migrated database in a fresh temporary directory, containing
+/// `entries` as `(name, url)` pairs.
+///
+/// The database lives as long as the returned `TempDir`.
+///
+/// # Panics
+///
+/// If the database cannot be created or an entry is invalid.
+#[must_use]
+pub fn seeded_repo(entries: &[(&str, &str)]) -> (impl WritableRepository, TempDir) {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut repo =
+        open_writable_repository(database_path(&temp_dir)).expect("Failed to open database");
+    repo.migrate().expect("Failed to migrate database");
+    for (name, url) in entries {
+        let name = (*name).try_into().expect("Invalid short URL name");
+        let url = (*url).try_into().expect("Invalid URL");
+        repo.insert_url(&name, &url).expect("Failed to insert URL");
+    }
+    (repo, temp_dir)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{repository::Repository, types::ShortUrlName};
+
+    #[test]
+    fn test_seeded_repo() {
+        let (repo, temp_dir) = seeded_repo(&[
+            ("aa", "https://example.com/a"),
+            ("bb", "https://example.com/b"),
+        ]);
+        assert!(database_path(&temp_dir).exists());
+
+        let name: ShortUrlName = "bb".try_into().unwrap();
+        let short_url = repo.get_url(&name).unwrap().unwrap();
+        assert_eq!(short_url.url.to_string(), "https://example.com/b");
+        assert_eq!(repo.recent(10).unwrap().len(), 2);
+    }
+}
@@ -1,50 +1,111 @@
-use core::fmt;
+use core::{
+    borrow::Borrow,
+    fmt,
+    hash::{Hash, Hasher},
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
 
+#[cfg(feature = "tz")]
+pub use chrono_tz::Tz;
 use rusqlite::{
     types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
     ToSql,
 };
 
-#[derive(Debug, Clone, Copy)]
-pub struct InvalidShortUrlName;
+/// Why a string is not a valid [`ShortUrlName`], with the offending input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidShortUrlName {
+    TooShort(String),
+    TooLong(String),
+    IllegalChar {
+        input: String,
+        c: char,
+    },
+    /// Shadowed by a route of the web frontend, see [`ShortUrlName::RESERVED`].
+    Reserved(String),
+}
 
 impl fmt::Display for InvalidShortUrlName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Invalid short URL name")
+        match self {
+            Self::TooShort(input) => write!(
+                f,
+                "short URL name {input:?} is too short, it needs at least {} characters",
+                ShortUrlName::MIN_LENGTH
+            ),
+            Self::TooLong(input) => write!(
+                f,
+                "short URL name {input:?} is too long, it can have at most {} characters",
+                ShortUrlName::MAX_LENGTH
+            ),
+            Self::IllegalChar { input, c } => write!(
+                f,
+                "short URL name {input:?} contains {c:?}, only ASCII letters, digits, '-' and '_' are allowed"
+            ),
+            Self::Reserved(input) => write!(
+                f,
+                "short URL name {input:?} is reserved, it would be shadowed by a route"
+            ),
+        }
     }
 }
 
 impl core::error::Error for InvalidShortUrlName {}
 
-impl From<FromSqlError> for InvalidShortUrlName {
-    fn from(_: FromSqlError) -> Self {
-        Self
-    }
+/// Why a string is not an acceptable [`Url`], with the offending input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidUrl {
+    Parse {
+        input: String,
+        error: url::ParseError,
+    },
+    /// Not `http` or `https`.
+    Scheme(String),
+    /// Has a username or password.
+    Credentials(String),
+    /// The host is a loopback or private network IP address.
+    PrivateIp(String),
+    /// Strict policy only.
+    MissingHost(String),
+    /// Strict policy only.
+    Fragment(String),
+    /// Strict policy only: parsing changed the URL, e.g. by adding `/`.
+    NotNormalized(String),
+    /// Longer than [`UrlPolicy::max_len`] once parsed.
+    LengthExceeded { input: String, max_len: usize },
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct InvalidUrl;
-
 impl fmt::Display for InvalidUrl {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Invalid short URL")
+        match self {
+            Self::Parse { input, error } => write!(f, "URL {input:?} is not valid: {error}"),
+            Self::Scheme(input) => write!(f, "URL {input:?} must start with http:// or https://"),
+            Self::Credentials(input) => {
+                write!(f, "URL {input:?} must not contain a username or password")
+            }
+            Self::PrivateIp(input) => write!(
+                f,
+                "URL {input:?} points to a loopback or private network IP address"
+            ),
+            Self::MissingHost(input) => write!(f, "URL {input:?} has no host"),
+            Self::Fragment(input) => write!(f, "URL {input:?} must not have a #fragment"),
+            Self::NotNormalized(input) => write!(
+                f,
+                "URL {input:?} is not in normal form, write it as {}",
+                url::Url::parse(input).map_or_else(|_| String::new(), String::from)
+            ),
+            Self::LengthExceeded { input, max_len } => write!(
+                f,
+                "URL of {} bytes is longer than the limit of {max_len}",
+                input.len()
+            ),
+        }
     }
 }
 
 impl core::error::Error for InvalidUrl {}
 
-impl From<FromSqlError> for InvalidUrl {
-    fn from(_: FromSqlError) -> Self {
-        Self
-    }
-}
-
-impl From<url::ParseError> for InvalidUrl {
-    fn from(_: url::ParseError) -> Self {
-        Self
-    }
-}
-
 #[derive(Debug, Clone, Eq)]
 pub struct ShortUrlName(String);
 
@@ -54,6 +115,12 @@ impl PartialEq for ShortUrlName {
     }
 }
 
+impl Hash for ShortUrlName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self as &dyn NameKey).hash(state);
+    }
+}
+
 impl fmt::Display for ShortUrlName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -63,21 +130,66 @@ impl fmt::Display for ShortUrlName {
 impl ShortUrlName {
     pub const MIN_LENGTH: usize = 2;
     pub const MAX_LENGTH: usize = 16;
+    /// Characters that make distinct names: names compare ignoring case,
+    /// so 26 letters, 10 digits, `-` and `_`.
+    pub const ALPHABET_LEN: u32 = 38;
+    /// Names that collide with routes of the web frontend, compared
+    /// ignoring case.
+    pub const RESERVED: &[&str] = &["random"];
+
+    /// Reject names in [`ShortUrlName::RESERVED`]. Only checked where
+    /// names are created: a stored name may predate its reservation, and
+    /// must still be readable and deletable.
+    ///
+    /// # Errors
+    ///
+    /// [`InvalidShortUrlName::Reserved`] if the name is reserved.
+    pub fn check_not_reserved(&self) -> Result<(), InvalidShortUrlName> {
+        if Self::RESERVED
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(&self.0))
+        {
+            return Err(InvalidShortUrlName::Reserved(self.0.clone()));
+        }
+        Ok(())
+    }
+
+    /// Number of distinct names of `name_len` characters drawn from
+    /// `alphabet_len` characters. Saturates at `u128::MAX`.
+    #[must_use]
+    pub const fn capacity(alphabet_len: u32, name_len: u32) -> u128 {
+        (alphabet_len as u128).saturating_pow(name_len)
+    }
+
+    /// Whether `count` names use more than 1% of `capacity`. Past that,
+    /// randomly chosen names start colliding often (birthday bound), and
+    /// a longer name length should be used.
+    #[must_use]
+    pub const fn is_crowded(count: u64, capacity: u128) -> bool {
+        (count as u128).saturating_mul(100) > capacity
+    }
 }
 
 impl TryFrom<&str> for ShortUrlName {
     type Error = InvalidShortUrlName;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        if (Self::MIN_LENGTH..=Self::MAX_LENGTH).contains(&value.len())
-            && value
-                .chars()
-                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        if value.len() < Self::MIN_LENGTH {
+            return Err(InvalidShortUrlName::TooShort(value.to_string()));
+        }
+        if value.len() > Self::MAX_LENGTH {
+            return Err(InvalidShortUrlName::TooLong(value.to_string()));
+        }
+        if let Some(c) = value
+            .chars()
+            .find(|&c| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
         {
-            Ok(Self(value.to_string()))
-        } else {
-            Err(InvalidShortUrlName)
+            return Err(InvalidShortUrlName::IllegalChar {
+                input: value.to_string(),
+                c,
+            });
         }
+        Ok(Self(value.to_string()))
     }
 }
 
@@ -87,6 +199,71 @@ impl AsRef<str> for ShortUrlName {
     }
 }
 
+/// Common view of anything that compares like a [`ShortUrlName`].
+///
+/// Implemented by [`ShortUrlName`] and [`CaseInsensitive`] so that a
+/// `HashMap<ShortUrlName, _>` can be queried with a plain `&str`:
+///
+/// ```
+/// use std::collections::HashMap;
+/// use shorty::types::{CaseInsensitive, ShortUrlName};
+///
+/// let mut map = HashMap::new();
+/// map.insert(ShortUrlName::try_from("ab").unwrap(), 1);
+/// assert_eq!(map.get(CaseInsensitive("AB").as_key()), Some(&1));
+/// ```
+pub trait NameKey {
+    fn name_key(&self) -> &str;
+}
+
+impl NameKey for ShortUrlName {
+    fn name_key(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for dyn NameKey + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.name_key().eq_ignore_ascii_case(other.name_key())
+    }
+}
+
+impl Eq for dyn NameKey + '_ {}
+
+impl Hash for dyn NameKey + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for b in self.name_key().bytes() {
+            state.write_u8(b.to_ascii_lowercase());
+        }
+        // Same terminator as the `str` impl.
+        state.write_u8(0xff);
+    }
+}
+
+impl<'a> Borrow<dyn NameKey + 'a> for ShortUrlName {
+    fn borrow(&self) -> &(dyn NameKey + 'a) {
+        self
+    }
+}
+
+/// A borrowed name that hashes and compares like a [`ShortUrlName`],
+/// without validating or allocating.
+#[derive(Debug, Clone, Copy)]
+pub struct CaseInsensitive<'a>(pub &'a str);
+
+impl CaseInsensitive<'_> {
+    #[must_use]
+    pub fn as_key(&self) -> &dyn NameKey {
+        self
+    }
+}
+
+impl NameKey for CaseInsensitive<'_> {
+    fn name_key(&self) -> &str {
+        self.0
+    }
+}
+
 impl TryFrom<String> for ShortUrlName {
     type Error = InvalidShortUrlName;
 
@@ -134,16 +311,188 @@ fn has_username(url: &url::Url) -> bool {
     !url.username().is_empty()
 }
 
+/// Whether `url` serializes back to `input`. Only the `/` path added
+/// to a bare origin like `https://example.com` is not counted as a change.
+fn is_unchanged_by_parsing(input: &str, url: &url::Url) -> bool {
+    let serialized = url.as_str();
+    serialized == input || (url.path() == "/" && serialized.strip_suffix('/') == Some(input))
+}
+
+const fn is_private_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        // Shared address space (carrier-grade NAT), 100.64.0.0/10
+        || (a == 100 && (b & 0b1100_0000) == 64)
+}
+
+const fn is_private_ipv6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_private_ipv4(ip);
+    }
+    ip.is_loopback() || ip.is_unspecified() || ip.is_unique_local() || ip.is_unicast_link_local()
+}
+
+/// Rules a [`Url`] must follow on top of the basic checks done by
+/// every constructor.
+///
+/// The default is the policy for new URLs. Only literal IP addresses
+/// are inspected; host names are not resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UrlPolicy {
+    /// Accept loopback, private, link-local and similar non-public
+    /// IP addresses as host.
+    pub allow_private_ip: bool,
+    /// Also reject URLs without a host, URLs with a fragment, and input
+    /// the parser had to fix up, like spaces it percent-encoded or a
+    /// missing `//`.
+    pub strict: bool,
+    /// Longest URL in bytes once parsed, `None` for no limit.
+    pub max_len: Option<usize>,
+}
+
+impl Default for UrlPolicy {
+    fn default() -> Self {
+        Self {
+            allow_private_ip: false,
+            strict: false,
+            max_len: Some(Self::DEFAULT_MAX_LEN),
+        }
+    }
+}
+
+impl UrlPolicy {
+    /// Accepts everything the basic checks accept. Used when reading
+    /// stored URLs, which were checked when they were written.
+    pub const PERMISSIVE: Self = Self {
+        allow_private_ip: true,
+        strict: false,
+        max_len: None,
+    };
+
+    /// Default [`UrlPolicy::max_len`]. Browsers and proxies handle URLs
+    /// of this length, and a URL this long still fits in a QR code at
+    /// the default error correction level. `UrlPolicy::fitting_qr`
+    /// lowers it for higher levels.
+    pub const DEFAULT_MAX_LEN: usize = 2048;
+
+    /// # Errors
+    ///
+    /// Returns `InvalidUrl` if `url` breaks this policy.
+    pub fn check(self, url: &Url) -> Result<(), InvalidUrl> {
+        let private_ip = match url.0.host() {
+            Some(url::Host::Ipv4(ip)) => is_private_ipv4(ip),
+            Some(url::Host::Ipv6(ip)) => is_private_ipv6(ip),
+            Some(url::Host::Domain(_)) | None => false,
+        };
+        let input = || url.0.to_string();
+        if let Some(max_len) = self.max_len {
+            if url.0.as_str().len() > max_len {
+                return Err(InvalidUrl::LengthExceeded {
+                    input: input(),
+                    max_len,
+                });
+            }
+        }
+        if private_ip && !self.allow_private_ip {
+            return Err(InvalidUrl::PrivateIp(input()));
+        }
+        if self.strict && url.0.host_str().is_none_or(str::is_empty) {
+            return Err(InvalidUrl::MissingHost(input()));
+        }
+        if self.strict && url.0.fragment().is_some() {
+            return Err(InvalidUrl::Fragment(input()));
+        }
+        Ok(())
+    }
+}
+
+impl Url {
+    /// Parse `s` and check it against `policy`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidUrl` if `s` is not a valid URL or breaks `policy`.
+    pub fn parse_with(s: &str, policy: UrlPolicy) -> Result<Self, InvalidUrl> {
+        let url = url::Url::parse(s).map_err(|error| InvalidUrl::Parse {
+            input: s.to_string(),
+            error,
+        })?;
+        if !is_http_or_https(&url) {
+            return Err(InvalidUrl::Scheme(s.to_string()));
+        }
+        if has_password(&url) || has_username(&url) {
+            return Err(InvalidUrl::Credentials(s.to_string()));
+        }
+        if policy.strict && !is_unchanged_by_parsing(s, &url) {
+            return Err(InvalidUrl::NotNormalized(s.to_string()));
+        }
+        let url = Self(url);
+        policy.check(&url)?;
+        Ok(url)
+    }
+
+    /// The host, e.g. `example.com` or `[::1]`.
+    #[must_use]
+    pub fn host(&self) -> Option<&str> {
+        self.0.host_str()
+    }
+
+    /// The scheme, `http` or `https`.
+    #[must_use]
+    pub fn scheme(&self) -> &str {
+        self.0.scheme()
+    }
+
+    /// This URL with the already encoded `query` added after its own
+    /// query parameters, e.g. `https://a.example/?x=1` and `y=2` give
+    /// `https://a.example/?x=1&y=2`. An empty `query` changes nothing.
+    #[must_use]
+    pub fn with_extra_query(&self, query: &str) -> Self {
+        let query = query.trim_start_matches('?');
+        if query.is_empty() {
+            return self.clone();
+        }
+        let mut url = self.0.clone();
+        match self.0.query().filter(|x| !x.is_empty()) {
+            Some(own) => url.set_query(Some(&format!("{own}&{query}"))),
+            None => url.set_query(Some(query)),
+        }
+        Self(url)
+    }
+
+    /// This URL with `https` as scheme. An `https` URL is unchanged.
+    #[must_use]
+    pub fn with_https(&self) -> Self {
+        let mut url = self.0.clone();
+        // Only fails for special to non-special scheme changes
+        let _ = url.set_scheme("https");
+        Self(url)
+    }
+
+    /// This URL with `base_path` in front of its path, e.g.
+    /// `https://a.example/x` and `/app` give `https://a.example/app/x`.
+    #[must_use]
+    pub fn with_base_path(&self, base_path: &str) -> Self {
+        let base_path = base_path.trim_matches('/');
+        if base_path.is_empty() {
+            return self.clone();
+        }
+        let mut url = self.0.clone();
+        url.set_path(&format!("/{base_path}{}", self.0.path()));
+        Self(url)
+    }
+}
+
+/// Uses the default [`UrlPolicy`].
 impl TryFrom<&str> for Url {
     type Error = InvalidUrl;
 
     fn try_from(s: &str) -> Result<Self, Self::Error> {
-        let url = url::Url::parse(s)?;
-        if is_http_or_https(&url) && !has_password(&url) && !has_username(&url) {
-            Ok(Self(url))
-        } else {
-            Err(InvalidUrl)
-        }
+        Self::parse_with(s, UrlPolicy::default())
     }
 }
 
@@ -158,7 +507,7 @@ impl TryFrom<String> for Url {
 impl FromSql for Url {
     fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
         let url = value.as_str()?;
-        url.try_into().map_err(|_| FromSqlError::InvalidType)
+        Self::parse_with(url, UrlPolicy::PERMISSIVE).map_err(|_| FromSqlError::InvalidType)
     }
 }
 
@@ -179,6 +528,18 @@ impl UnixTimestamp {
         chrono::DateTime::from_timestamp(secs, 0)
             .map(|x| x.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
     }
+
+    /// Like [`Self::iso8601`], but with the offset of `tz` at that instant.
+    /// UTC is still written with a `Z` suffix.
+    #[cfg(feature = "tz")]
+    #[must_use]
+    pub fn iso8601_in_tz(self, tz: Tz) -> Option<String> {
+        let secs: i64 = self.0.try_into().ok()?;
+        chrono::DateTime::from_timestamp(secs, 0).map(|x| {
+            x.with_timezone(&tz)
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        })
+    }
 }
 
 impl core::fmt::Display for UnixTimestamp {
@@ -198,9 +559,16 @@ impl FromSql for UnixTimestamp {
     }
 }
 
+/// Stored as an integer, the way [`FromSql`] reads it.
+///
+/// Earlier versions bound the value as text. `urls.last_modified` is an
+/// `INTEGER` column in a `STRICT` table, which converted that text on
+/// insert, so no stored data needs migrating.
 impl ToSql for UnixTimestamp {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
-        Ok(ToSqlOutput::from(self.0.to_string()))
+        let secs = i64::try_from(self.0)
+            .map_err(|err| rusqlite::Error::ToSqlConversionFailure(err.into()))?;
+        Ok(ToSqlOutput::from(secs))
     }
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -208,25 +576,107 @@ pub struct ShortUrl {
     pub name: ShortUrlName,
     pub url: Url,
     pub last_modified: Option<UnixTimestamp>,
+    /// Human readable title shown on the short URL page.
+    pub title: Option<String>,
 }
 
+impl ShortUrl {
+    /// The short URL itself: `name` as the last path segment of `base`,
+    /// e.g. `https://sho.rt/app/` or `https://sho.rt/app` give
+    /// `https://sho.rt/app/{name}`. The query and fragment of `base` are
+    /// dropped.
+    ///
+    /// # Errors
+    ///
+    /// If the result is longer than [`UrlPolicy::DEFAULT_MAX_LEN`].
+    pub fn public_url(&self, base: &Url) -> Result<Url, InvalidUrl> {
+        let mut url = base.0.clone();
+        url.set_query(None);
+        url.set_fragment(None);
+        // http(s) URLs always have a path to add to
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.pop_if_empty().push(self.name.as_ref());
+        }
+        let url = Url(url);
+        UrlPolicy {
+            allow_private_ip: true,
+            ..Default::default()
+        }
+        .check(&url)?;
+        Ok(url)
+    }
+}
+
+/// `name -> url`. The alternate form `{:#}` appends
+/// ` (modified: <iso8601>)` when the timestamp is known.
 impl fmt::Display for ShortUrl {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} -> {}", self.name, self.url)
+        write!(f, "{} -> {}", self.name, self.url)?;
+        if f.alternate() {
+            if let Some(iso8601) = self.last_modified.and_then(UnixTimestamp::iso8601) {
+                write!(f, " (modified: {iso8601})")?;
+            }
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl ShortUrl {
+    /// Parse `name url`, or the `name -> url` form written by `Display`,
+    /// and check the URL against `policy`.
+    ///
+    /// Fields may be separated by any amount of whitespace. The timestamp
+    /// is not part of the textual form, so `last_modified` and `title`
+    /// are always `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidShortUrl` if `s` is not two fields, or either of
+    /// them is invalid.
+    pub fn parse_with(s: &str, policy: UrlPolicy) -> Result<Self, InvalidShortUrl> {
+        let mut fields = s.split_whitespace();
+        let name = fields.next().ok_or(InvalidShortUrl::Fields)?;
+        let url = match fields.next() {
+            Some("->") => fields.next(),
+            url => url,
+        }
+        .ok_or(InvalidShortUrl::Fields)?;
+        if fields.next().is_some() {
+            return Err(InvalidShortUrl::Fields);
+        }
+        Ok(Self {
+            name: name.try_into()?,
+            url: Url::parse_with(url, policy)?,
+            last_modified: None,
+            title: None,
+        })
+    }
+}
+
+/// Uses the default [`UrlPolicy`], see [`ShortUrl::parse_with`].
+impl FromStr for ShortUrl {
+    type Err = InvalidShortUrl;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(s, UrlPolicy::default())
+    }
+}
+
+/// Why a string is not a [`ShortUrl`].
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum InvalidShortUrl {
-    InvalidName,
-    InvalidUrl,
+    /// Not `name url` or `name -> url`.
+    Fields,
+    InvalidName(InvalidShortUrlName),
+    InvalidUrl(InvalidUrl),
 }
 
 impl fmt::Display for InvalidShortUrl {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::InvalidName => write!(f, "Invalid short URL name"),
-            Self::InvalidUrl => write!(f, "Invalid short URL url"),
+            Self::Fields => write!(f, "expected `name url`"),
+            Self::InvalidName(err) => err.fmt(f),
+            Self::InvalidUrl(err) => err.fmt(f),
         }
     }
 }
@@ -234,14 +684,14 @@ impl fmt::Display for InvalidShortUrl {
 impl core::error::Error for InvalidShortUrl {}
 
 impl From<InvalidShortUrlName> for InvalidShortUrl {
-    fn from(_: InvalidShortUrlName) -> Self {
-        Self::InvalidName
+    fn from(err: InvalidShortUrlName) -> Self {
+        Self::InvalidName(err)
     }
 }
 
 impl From<InvalidUrl> for InvalidShortUrl {
-    fn from(_: InvalidUrl) -> Self {
-        Self::InvalidUrl
+    fn from(err: InvalidUrl) -> Self {
+        Self::InvalidUrl(err)
     }
 }
 
@@ -273,6 +723,33 @@ mod test {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_short_url_name_capacity() {
+        assert_eq!(ShortUrlName::capacity(10, 0), 1);
+        assert_eq!(ShortUrlName::capacity(10, 3), 1000);
+        assert_eq!(ShortUrlName::capacity(ShortUrlName::ALPHABET_LEN, 2), 1444);
+        assert_eq!(ShortUrlName::capacity(u32::MAX, 16), u128::MAX);
+    }
+
+    #[test]
+    fn test_short_url_name_is_crowded() {
+        let capacity = ShortUrlName::capacity(10, 3);
+        assert!(!ShortUrlName::is_crowded(10, capacity));
+        assert!(ShortUrlName::is_crowded(11, capacity));
+        assert!(!ShortUrlName::is_crowded(u64::MAX, u128::MAX));
+    }
+
+    #[test]
+    fn test_short_url_name_case_insensitive_lookup() {
+        let mut map = std::collections::HashMap::new();
+        map.insert(ShortUrlName::try_from("Mixed-Case").unwrap(), 1);
+        map.insert(ShortUrlName::try_from("other").unwrap(), 2);
+
+        assert_eq!(map.get(CaseInsensitive("mIXED-cASE").as_key()), Some(&1));
+        assert_eq!(map.get(CaseInsensitive("OTHER").as_key()), Some(&2));
+        assert_eq!(map.get(CaseInsensitive("missing").as_key()), None);
+    }
+
     #[test]
     fn test_url_try_from_valid() {
         let result = Url::try_from("http://localhost/");
@@ -296,4 +773,516 @@ mod test {
         let result = Url::try_from("http://:pass@localhost/");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_url_loopback_ip() {
+        for url in [
+            "http://127.0.0.1/",
+            "http://127.1.2.3:8080/",
+            "http://[::1]/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://0.0.0.0/",
+        ] {
+            assert!(Url::try_from(url).is_err(), "{url}");
+            assert!(Url::parse_with(url, UrlPolicy::PERMISSIVE).is_ok(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_url_private_ip() {
+        for url in [
+            "http://10.1.2.3/",
+            "http://172.16.0.1/",
+            "http://192.168.1.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://100.64.0.1/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+        ] {
+            assert!(Url::try_from(url).is_err(), "{url}");
+            let policy = UrlPolicy {
+                allow_private_ip: true,
+                ..Default::default()
+            };
+            assert!(Url::parse_with(url, policy).is_ok(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_url_public_ip() {
+        for url in [
+            "http://93.184.216.34/",
+            "http://172.32.0.1/",
+            "http://100.128.0.1/",
+            "http://[2001:db8::1]/",
+        ] {
+            assert!(Url::try_from(url).is_ok(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_url_strict() {
+        let strict = UrlPolicy {
+            strict: true,
+            ..Default::default()
+        };
+        for url in [
+            "https://example.com/a b",
+            "http:example.com/",
+            "https://example.com/#chapter-1",
+            "HTTPS://Example.com/",
+            "https://example.com/a/../b",
+            "https://example.com?a=1",
+        ] {
+            assert!(Url::try_from(url).is_ok(), "{url}");
+            assert!(Url::parse_with(url, strict).is_err(), "{url}");
+        }
+        for url in [
+            "https://example.com",
+            "https://example.com/",
+            "https://example.com/a?b=c%20d",
+        ] {
+            assert!(Url::parse_with(url, strict).is_ok(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_url_max_len() {
+        let url = |len: usize| format!("https://example.com/{}", "a".repeat(len - 20));
+        assert_eq!(url(UrlPolicy::DEFAULT_MAX_LEN).len(), 2048);
+        assert!(Url::try_from(url(2048)).is_ok());
+        let err = Url::try_from(url(2049)).unwrap_err();
+        assert!(matches!(
+            err,
+            InvalidUrl::LengthExceeded { max_len: 2048, .. }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "URL of 2049 bytes is longer than the limit of 2048"
+        );
+
+        let short = UrlPolicy {
+            max_len: Some(30),
+            ..Default::default()
+        };
+        assert!(Url::parse_with(&url(30), short).is_ok());
+        assert!(Url::parse_with(&url(31), short).is_err());
+        assert!(Url::parse_with(&url(4096), UrlPolicy::PERMISSIVE).is_ok());
+    }
+
+    #[test]
+    fn test_url_host_and_scheme() {
+        let url = Url::try_from("https://www.Example.com:8080/a?b#c").unwrap();
+        assert_eq!(url.host(), Some("www.example.com"));
+        assert_eq!(url.scheme(), "https");
+
+        let url = Url::try_from("http://93.184.216.34/").unwrap();
+        assert_eq!(url.host(), Some("93.184.216.34"));
+        assert_eq!(url.scheme(), "http");
+
+        let url = Url::parse_with("http://[::1]/", UrlPolicy::PERMISSIVE).unwrap();
+        assert_eq!(url.host(), Some("[::1]"));
+    }
+
+    #[test]
+    fn test_short_url_from_str_round_trip() {
+        let short_url: ShortUrl = "abc https://example.com/".parse().unwrap();
+        assert_eq!(short_url.name.as_ref(), "abc");
+        assert_eq!(short_url.url.to_string(), "https://example.com/");
+        assert_eq!(
+            short_url.to_string().parse::<ShortUrl>().unwrap(),
+            short_url
+        );
+        assert_eq!(
+            "  abc \t https://example.com/ \n"
+                .parse::<ShortUrl>()
+                .unwrap(),
+            short_url
+        );
+    }
+
+    #[test]
+    fn test_short_url_display_alternate() {
+        let mut short_url = ShortUrl {
+            name: "aa".try_into().unwrap(),
+            url: "https://example.com".try_into().unwrap(),
+            last_modified: Some(UnixTimestamp(1_000_000_000)),
+            title: None,
+        };
+        assert_eq!(format!("{short_url}"), "aa -> https://example.com/");
+        assert_eq!(
+            format!("{short_url:#}"),
+            "aa -> https://example.com/ (modified: 2001-09-09T01:46:40Z)"
+        );
+
+        short_url.last_modified = None;
+        assert_eq!(format!("{short_url:#}"), "aa -> https://example.com/");
+    }
+
+    #[test]
+    fn test_short_url_public_url() {
+        let short_url = ShortUrl {
+            name: "aa".try_into().unwrap(),
+            url: "https://example.com".try_into().unwrap(),
+            last_modified: None,
+            title: None,
+        };
+        for (base, expected) in [
+            ("https://sho.rt", "https://sho.rt/aa"),
+            ("https://sho.rt/", "https://sho.rt/aa"),
+            ("https://sho.rt/app", "https://sho.rt/app/aa"),
+            ("https://sho.rt/app/", "https://sho.rt/app/aa"),
+            ("https://sho.rt/a/b/?x=1#top", "https://sho.rt/a/b/aa"),
+            ("http://10.0.0.1:8080/s", "http://10.0.0.1:8080/s/aa"),
+        ] {
+            let base = Url::parse_with(base, UrlPolicy::PERMISSIVE).unwrap();
+            assert_eq!(short_url.public_url(&base).unwrap().to_string(), expected);
+        }
+
+        let base = format!("https://sho.rt/{}", "a".repeat(UrlPolicy::DEFAULT_MAX_LEN));
+        let base = Url::parse_with(&base, UrlPolicy::PERMISSIVE).unwrap();
+        assert!(matches!(
+            short_url.public_url(&base),
+            Err(InvalidUrl::LengthExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_unix_timestamp_sql_integer() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        // No declared type, so nothing converts the bound value
+        conn.execute_batch("CREATE TABLE t (v)").unwrap();
+        let large = UnixTimestamp(i64::MAX.unsigned_abs());
+        conn.execute("INSERT INTO t (v) VALUES (?)", [large])
+            .unwrap();
+        let (read, kind): (UnixTimestamp, String) = conn
+            .query_row("SELECT v, typeof(v) FROM t", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(read, large);
+        assert_eq!(kind, "integer");
+
+        let too_large = UnixTimestamp(u64::MAX);
+        assert!(conn
+            .execute("INSERT INTO t (v) VALUES (?)", [too_large])
+            .is_err());
+    }
+
+    #[cfg(feature = "tz")]
+    #[test]
+    fn test_unix_timestamp_iso8601_in_tz() {
+        let ts = UnixTimestamp(1_000_000_000);
+        assert_eq!(
+            ts.iso8601_in_tz(Tz::UTC).as_deref(),
+            Some("2001-09-09T01:46:40Z")
+        );
+        assert_eq!(
+            ts.iso8601_in_tz(Tz::Europe__Oslo).as_deref(),
+            Some("2001-09-09T03:46:40+02:00")
+        );
+        assert_eq!(
+            ts.iso8601_in_tz(Tz::America__New_York).as_deref(),
+            Some("2001-09-08T21:46:40-04:00")
+        );
+        // Winter time
+        assert_eq!(
+            UnixTimestamp(0).iso8601_in_tz(Tz::Europe__Oslo).as_deref(),
+            Some("1970-01-01T01:00:00+01:00")
+        );
+    }
+
+    #[test]
+    fn test_url_with_extra_query() {
+        let url: Url = "https://example.com/path".try_into().unwrap();
+        assert_eq!(
+            url.with_extra_query("ref=x").to_string(),
+            "https://example.com/path?ref=x"
+        );
+        assert_eq!(url.with_extra_query(""), url);
+
+        let url: Url = "https://example.com/?a=1&b=2#top".try_into().unwrap();
+        assert_eq!(
+            url.with_extra_query("?ref=x%20y&a=3").to_string(),
+            "https://example.com/?a=1&b=2&ref=x%20y&a=3#top"
+        );
+
+        let url: Url = "https://example.com/?".try_into().unwrap();
+        assert_eq!(
+            url.with_extra_query("ref=x").to_string(),
+            "https://example.com/?ref=x"
+        );
+    }
+
+    #[test]
+    fn test_invalid_short_url_name_messages() {
+        let err = ShortUrlName::try_from("a").unwrap_err();
+        assert_eq!(err, InvalidShortUrlName::TooShort("a".to_string()));
+        assert_eq!(
+            err.to_string(),
+            "short URL name \"a\" is too short, it needs at least 2 characters"
+        );
+
+        let long = "a".repeat(17);
+        let err = ShortUrlName::try_from(long.as_str()).unwrap_err();
+        assert_eq!(err, InvalidShortUrlName::TooLong(long.clone()));
+        assert_eq!(
+            err.to_string(),
+            format!("short URL name \"{long}\" is too long, it can have at most 16 characters")
+        );
+
+        let err = ShortUrlName::try_from("abc$").unwrap_err();
+        assert_eq!(
+            err,
+            InvalidShortUrlName::IllegalChar {
+                input: "abc$".to_string(),
+                c: '$'
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "short URL name \"abc$\" contains '$', only ASCII letters, digits, '-' and '_' are allowed"
+        );
+
+        let name = ShortUrlName::try_from("Random").unwrap();
+        let err = name.check_not_reserved().unwrap_err();
+        assert_eq!(err, InvalidShortUrlName::Reserved("Random".to_string()));
+        assert_eq!(
+            err.to_string(),
+            "short URL name \"Random\" is reserved, it would be shadowed by a route"
+        );
+    }
+
+    #[test]
+    fn test_invalid_url_messages() {
+        let message = |s: &str, policy| Url::parse_with(s, policy).unwrap_err().to_string();
+        let strict = UrlPolicy {
+            strict: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            message("example.com", UrlPolicy::default()),
+            "URL \"example.com\" is not valid: relative URL without a base"
+        );
+        assert_eq!(
+            message("ftp://example.com/", UrlPolicy::default()),
+            "URL \"ftp://example.com/\" must start with http:// or https://"
+        );
+        assert_eq!(
+            message("https://user:pw@example.com/", UrlPolicy::default()),
+            "URL \"https://user:pw@example.com/\" must not contain a username or password"
+        );
+        assert_eq!(
+            message("http://10.0.0.1/", UrlPolicy::default()),
+            "URL \"http://10.0.0.1/\" points to a loopback or private network IP address"
+        );
+        assert_eq!(
+            message("https://example.com/#top", strict),
+            "URL \"https://example.com/#top\" must not have a #fragment"
+        );
+        assert_eq!(
+            message("https://EXAMPLE.com/", strict),
+            "URL \"https://EXAMPLE.com/\" is not in normal form, write it as https://example.com/"
+        );
+    }
+
+    #[test]
+    fn test_short_url_from_str_missing_fields() {
+        assert!(matches!(
+            "".parse::<ShortUrl>(),
+            Err(InvalidShortUrl::Fields)
+        ));
+        assert!(matches!(
+            "abc".parse::<ShortUrl>(),
+            Err(InvalidShortUrl::Fields)
+        ));
+        assert!(matches!(
+            "abc ->".parse::<ShortUrl>(),
+            Err(InvalidShortUrl::Fields)
+        ));
+        assert!(matches!(
+            "abc https://example.com/ extra".parse::<ShortUrl>(),
+            Err(InvalidShortUrl::Fields)
+        ));
+        assert!(matches!(
+            "a https://example.com/".parse::<ShortUrl>(),
+            Err(InvalidShortUrl::InvalidName(InvalidShortUrlName::TooShort(
+                _
+            )))
+        ));
+        assert!(matches!(
+            "abc http://10.0.0.1/".parse::<ShortUrl>(),
+            Err(InvalidShortUrl::InvalidUrl(InvalidUrl::PrivateIp(_)))
+        ));
+        let permissive = UrlPolicy {
+            allow_private_ip: true,
+            ..Default::default()
+        };
+        assert!(ShortUrl::parse_with("abc -> http://10.0.0.1/", permissive).is_ok());
+    }
+
+    #[test]
+    fn test_nul_and_control_characters() {
+        for name in ["ab\0", "\0ab", "a\tb", "a\nb", "ab\x7f"] {
+            assert!(matches!(
+                ShortUrlName::try_from(name),
+                Err(InvalidShortUrlName::IllegalChar { .. })
+            ));
+        }
+        assert!(Url::try_from("https://exa\0mple.com/").is_err());
+        // The URL parser trims leading and trailing control characters,
+        // drops tabs and newlines and percent-encodes the rest, so none
+        // reach the stored value
+        for (input, expected) in [
+            ("\0https://example.com/\x1f", "https://example.com/"),
+            ("https://exa\tmple.com/", "https://example.com/"),
+            ("https://example.com/a\nb", "https://example.com/ab"),
+            ("https://example.com/a\0b", "https://example.com/a%00b"),
+            (
+                "https://example.com/?a=\x1bb",
+                "https://example.com/?a=%1Bb",
+            ),
+        ] {
+            assert_eq!(Url::try_from(input).unwrap().to_string(), expected);
+        }
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use proptest::prelude::*;
+    use rusqlite::Connection;
+
+    /// Valid names; shrinks towards short runs of `a`.
+    fn short_url_name() -> impl Strategy<Value = ShortUrlName> {
+        "[a-zA-Z0-9_-]{2,16}".prop_map(|s| ShortUrlName::try_from(s).expect("Valid name"))
+    }
+
+    /// Public http(s) URLs with an optional port, path and query; shrinks
+    /// towards `http://a.aa/`. Labels have no `--`, which would make
+    /// `xn--` punycode that fails to decode.
+    fn url() -> impl Strategy<Value = Url> {
+        (
+            prop_oneof![Just("http"), Just("https")],
+            "[a-z]([a-z0-9]|-[a-z0-9]){0,10}",
+            "[a-z]{2,6}",
+            proptest::option::of(1u16..),
+            "(/[a-zA-Z0-9._~-]{0,8}){0,4}",
+            proptest::option::of("[a-z0-9]{1,8}=[a-z0-9]{0,8}"),
+        )
+            .prop_map(|(scheme, label, tld, port, path, query)| {
+                let port = port.map(|port| format!(":{port}")).unwrap_or_default();
+                let query = query.map(|query| format!("?{query}")).unwrap_or_default();
+                let url = format!("{scheme}://{label}.{tld}{port}{path}{query}");
+                Url::parse_with(&url, UrlPolicy::PERMISSIVE).expect("Valid URL")
+            })
+    }
+
+    /// Strings that are often close to a valid name, mixed with
+    /// arbitrary ones, so both accepting and rejecting paths get exercised.
+    fn name_candidate() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-zA-Z0-9_\\-$. /\\x00\\t\\n\\x7fé]{0,20}",
+            any::<String>(),
+        ]
+    }
+
+    /// Strings that are often close to a valid URL: various schemes,
+    /// optional credentials and control characters, mixed with arbitrary
+    /// ones.
+    fn url_candidate() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "(https?|HTTP|ftp|file|javascript|data):(//)?([a-z]{0,4}(:[a-z]{0,4})?@)?[a-z0-9.\\x00\\t]{1,12}(:[0-9]{1,5})?(/[ -~\\x00-\\x1f]{0,10})?",
+            any::<String>(),
+        ]
+    }
+
+    /// Timestamps SQLite can store; shrinks towards the epoch.
+    fn unix_timestamp() -> impl Strategy<Value = UnixTimestamp> {
+        (0..=i64::MAX.unsigned_abs()).prop_map(UnixTimestamp)
+    }
+
+    /// A table with the columns of `urls`, but untyped: `ANY` in a
+    /// `STRICT` table stores the bound value without coercing it, so
+    /// `typeof()` shows the type `ToSql` chose.
+    fn table() -> Connection {
+        let conn = Connection::open_in_memory().expect("Failed to open database");
+        conn.execute_batch(
+            "CREATE TABLE t (name ANY COLLATE nocase, url ANY, last_modified ANY) STRICT",
+        )
+        .expect("Failed to create table");
+        conn
+    }
+
+    proptest! {
+        #[test]
+        fn short_url_name_round_trip(name in short_url_name()) {
+            let conn = table();
+            conn.execute("INSERT INTO t (name) VALUES (?)", [&name]).unwrap();
+            let (read, kind): (ShortUrlName, String) = conn
+                .query_row("SELECT name, typeof(name) FROM t", [], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap();
+            prop_assert_eq!(read.as_ref(), name.as_ref());
+            prop_assert_eq!(kind, "text");
+        }
+
+        #[test]
+        fn url_round_trip(url in url()) {
+            let conn = table();
+            conn.execute("INSERT INTO t (url) VALUES (?)", [&url]).unwrap();
+            let (read, kind): (Url, String) = conn
+                .query_row("SELECT url, typeof(url) FROM t", [], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap();
+            prop_assert_eq!(read, url);
+            prop_assert_eq!(kind, "text");
+        }
+
+        #[test]
+        fn accepted_name_is_valid(s in name_candidate()) {
+            if let Ok(name) = ShortUrlName::try_from(s.as_str()) {
+                let len = name.as_ref().len();
+                prop_assert!((ShortUrlName::MIN_LENGTH..=ShortUrlName::MAX_LENGTH).contains(&len));
+                prop_assert!(name
+                    .as_ref()
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+            }
+        }
+
+        #[test]
+        fn accepted_url_is_valid(s in url_candidate()) {
+            if let Ok(url) = Url::try_from(s.as_str()) {
+                prop_assert!(matches!(url.scheme(), "http" | "https"));
+                prop_assert!(url.0.username().is_empty());
+                prop_assert!(url.0.password().is_none());
+                prop_assert!(!url.to_string().contains(|c: char| c.is_control()));
+            }
+        }
+
+        #[test]
+        fn short_url_name_display_round_trip(name in short_url_name()) {
+            let parsed = ShortUrlName::try_from(name.to_string()).unwrap();
+            prop_assert_eq!(parsed.as_ref(), name.as_ref());
+        }
+
+        #[test]
+        fn url_display_round_trip(url in url()) {
+            let parsed = Url::try_from(url.to_string().as_str()).unwrap();
+            prop_assert_eq!(parsed, url);
+        }
+
+        #[test]
+        fn unix_timestamp_round_trip(timestamp in unix_timestamp()) {
+            let conn = table();
+            conn.execute("INSERT INTO t (last_modified) VALUES (?)", [&timestamp]).unwrap();
+            let (read, kind): (UnixTimestamp, String) = conn
+                .query_row("SELECT last_modified, typeof(last_modified) FROM t", [], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })
+                .unwrap();
+            prop_assert_eq!(read, timestamp);
+            prop_assert_eq!(kind, "integer");
+        }
+    }
 }